@@ -31,7 +31,7 @@ impl FlowState for ProblemSolvingState {
     fn can_transition_to(&self, target: &Self) -> bool {
         use ProblemSolvingState::*;
         match (self, target) {
-            (Start, Thinking) => true,
+            (Start, Thinking | Planning) => true,
             (Thinking, Planning) => true,
             (Planning, Executing) => true,
             (Executing, Reflecting) => true,
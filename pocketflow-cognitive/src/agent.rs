@@ -11,7 +11,7 @@ use pocketflow_core::{context::Context, node::Node, state::FlowState};
 use pocketflow_mcp::client::McpClient;
 
 use crate::{
-    Result,
+    Result, keys,
     planning::{GoalOrientedPlanningNode, PlanningConfig, PlanningStrategy},
     thinking::{ChainOfThoughtNode, ThinkingConfig},
     traits::{CognitiveNode, ExecutionPlan, Goal, PlanningNode, ReasoningChain},
@@ -83,7 +83,7 @@ impl<S: FlowState> Node for CognitiveAgentNode<S> {
         };
 
         context.set("execution_goal", &goal)?;
-        context.set("execution_plan", &plan)?;
+        context.put(&keys::EXECUTION_PLAN, &plan)?;
 
         Ok((context, self.success_state.clone()))
     }
@@ -13,6 +13,7 @@ use pocketflow_mcp::client::McpClient;
 use crate::{
     Result,
     context::CognitiveContextExt,
+    keys,
     planning::{GoalOrientedPlanningNode, PlanningConfig, PlanningStrategy},
     thinking::{ChainOfThoughtNode, ThinkingConfig},
     traits::{
@@ -54,14 +55,14 @@ impl<S: FlowState> IterativeCognitiveAgentNode<S> {
 
     fn mark_one_step_completed(&self, plan: &ExecutionPlan, context: &mut Context) -> Result<()> {
         // Simple simulated executor: mark the first pending step as completed
-        let mut completed: Vec<String> = context
-            .get_json("completed_steps")?
+        let mut completed = context
+            .fetch(&keys::COMPLETED_STEPS)?
             .unwrap_or_else(Vec::new);
 
         let completed_set: std::collections::HashSet<_> = completed.iter().cloned().collect();
         if let Some(step) = plan.steps.iter().find(|s| !completed_set.contains(&s.id)) {
             completed.push(step.id.clone());
-            context.set("completed_steps", &completed)?;
+            context.put(&keys::COMPLETED_STEPS, &completed)?;
         }
         Ok(())
     }
@@ -108,7 +109,7 @@ impl<S: FlowState> Node for IterativeCognitiveAgentNode<S> {
             }
         };
         context.set("execution_goal", &goal)?;
-        context.set("execution_plan", &plan)?;
+        context.put(&keys::EXECUTION_PLAN, &plan)?;
 
         // Iterate until target completion or max iterations
         for _i in 0..self.max_iterations {
@@ -162,7 +163,7 @@ impl<S: FlowState> Node for IterativeCognitiveAgentNode<S> {
             // Replan if progress is insufficient according to planner policy
             if let Ok(new_plan) = self.planner.replan(&plan, &context).await {
                 plan = new_plan;
-                context.set("execution_plan", &plan)?;
+                context.put(&keys::EXECUTION_PLAN, &plan)?;
             }
         }
 
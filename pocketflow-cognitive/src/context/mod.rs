@@ -5,7 +5,7 @@
 
 use pocketflow_core::context::Context;
 
-use crate::{Result, memory::CognitiveMemory};
+use crate::{Result, keys, memory::CognitiveMemory};
 
 /// Extension trait for adding cognitive capabilities to Context
 pub trait CognitiveContextExt {
@@ -42,11 +42,11 @@ pub trait CognitiveContextExt {
 
 impl CognitiveContextExt for Context {
     fn get_cognitive_memory(&self) -> Result<Option<CognitiveMemory>> {
-        self.get_json("cognitive_memory")
+        self.fetch(&keys::COGNITIVE_MEMORY)
     }
 
     fn set_cognitive_memory(&mut self, memory: CognitiveMemory) -> Result<()> {
-        self.set("cognitive_memory", &memory)
+        self.put(&keys::COGNITIVE_MEMORY, memory)
     }
 
     fn get_or_create_cognitive_memory(&mut self) -> Result<CognitiveMemory> {
@@ -87,14 +87,14 @@ impl CognitiveContextExt for Context {
     }
 
     fn store_reasoning_trace(&mut self, trace: serde_json::Value) -> Result<()> {
-        self.set("reasoning_trace", &trace)
+        self.put(&keys::REASONING_TRACE, trace)
     }
 
     fn get_reasoning_trace(&self) -> Result<Option<serde_json::Value>> {
-        self.get_json("reasoning_trace")
+        self.fetch(&keys::REASONING_TRACE)
     }
 
     fn has_cognitive_memory(&self) -> bool {
-        self.contains_json("cognitive_memory")
+        self.has(&keys::COGNITIVE_MEMORY)
     }
 }
@@ -8,7 +8,7 @@ use pocketflow_mcp::client::McpClient;
 use serde_json::{Value, json};
 use tokio::time::{Duration, sleep};
 
-use crate::{Result, traits::ExecutionPlan};
+use crate::{Result, keys, traits::ExecutionPlan};
 
 /// Executes each step in `execution_plan` by invoking a configured MCP tool.
 /// Stores per-step results in `execution_results` and updates `completed_steps`.
@@ -48,7 +48,7 @@ impl<S: FlowState> Node for PlanExecutionNode<S> {
         &self,
         mut context: Context,
     ) -> pocketflow_core::error::Result<(Context, Self::State)> {
-        let plan: ExecutionPlan = if let Some(p) = context.get_json("execution_plan")? {
+        let plan: ExecutionPlan = if let Some(p) = context.fetch(&keys::EXECUTION_PLAN)? {
             p
         } else {
             context.set("execution_error", "No execution_plan in context")?;
@@ -56,9 +56,8 @@ impl<S: FlowState> Node for PlanExecutionNode<S> {
         };
 
         // Results vector of objects { step_id, status, output }
-        let mut results: Vec<serde_json::Value> =
-            context.get_json("execution_results")?.unwrap_or_default();
-        let mut completed: Vec<String> = context.get_json("completed_steps")?.unwrap_or_default();
+        let mut results = context.fetch(&keys::EXECUTION_RESULTS)?.unwrap_or_default();
+        let mut completed = context.fetch(&keys::COMPLETED_STEPS)?.unwrap_or_default();
 
         for step in &plan.steps {
             // Compute effective policies with per-step overrides
@@ -146,7 +145,7 @@ impl<S: FlowState> Node for PlanExecutionNode<S> {
 
                             if !all_ok {
                                 results.push(json!({ "step_id": step.id, "status": "error", "error": "success criteria not met", "output": output }));
-                                context.set("execution_results", &results)?;
+                                context.put(&keys::EXECUTION_RESULTS, &results)?;
                                 context.set("last_step_error", "success criteria not met")?;
                                 if stop_on_error {
                                     return Ok((context, self.error_state.clone()));
@@ -157,8 +156,8 @@ impl<S: FlowState> Node for PlanExecutionNode<S> {
                         results
                             .push(json!({ "step_id": step.id, "status": "ok", "output": output }));
                         completed.push(step.id.clone());
-                        context.set("execution_results", &results)?;
-                        context.set("completed_steps", &completed)?;
+                        context.put(&keys::EXECUTION_RESULTS, &results)?;
+                        context.put(&keys::COMPLETED_STEPS, &completed)?;
                         break;
                     }
                     Err(e) => {
@@ -166,7 +165,7 @@ impl<S: FlowState> Node for PlanExecutionNode<S> {
                         attempt += 1;
                         if attempt > max_retries {
                             results.push(json!({ "step_id": step.id, "status": "error", "error": err_str.clone() }));
-                            context.set("execution_results", &results)?;
+                            context.put(&keys::EXECUTION_RESULTS, &results)?;
                             context.set("last_step_error", err_str)?;
                             if stop_on_error {
                                 return Ok((context, self.error_state.clone()));
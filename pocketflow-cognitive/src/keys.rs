@@ -0,0 +1,31 @@
+//! Typed context keys used by the cognitive nodes.
+//!
+//! Planning nodes write [`EXECUTION_PLAN`], [`PlanExecutionNode`] reads it and
+//! records progress in [`COMPLETED_STEPS`]:
+//!
+//! ```rust,ignore
+//! let plan = context.fetch(&keys::EXECUTION_PLAN)?;
+//! let completed = context.fetch(&keys::COMPLETED_STEPS)?.unwrap_or_default();
+//! ```
+//!
+//! [`PlanExecutionNode`]: crate::execution::PlanExecutionNode
+
+use pocketflow_core::keys::ContextKey;
+use serde_json::Value;
+
+use crate::{memory::CognitiveMemory, traits::ExecutionPlan};
+
+/// The plan produced by a planning node.
+pub const EXECUTION_PLAN: ContextKey<ExecutionPlan> = ContextKey::new("execution_plan");
+
+/// Ids of the plan steps that have completed.
+pub const COMPLETED_STEPS: ContextKey<Vec<String>> = ContextKey::new("completed_steps");
+
+/// Per-step results recorded by [`PlanExecutionNode`](crate::execution::PlanExecutionNode).
+pub const EXECUTION_RESULTS: ContextKey<Vec<Value>> = ContextKey::new("execution_results");
+
+/// Working, episodic and semantic memory of an agent.
+pub const COGNITIVE_MEMORY: ContextKey<CognitiveMemory> = ContextKey::new("cognitive_memory");
+
+/// The last stored reasoning trace.
+pub const REASONING_TRACE: ContextKey<Value> = ContextKey::new("reasoning_trace");
@@ -89,6 +89,7 @@ pub mod agent_iterative;
 pub mod context;
 pub mod error;
 pub mod execution;
+pub mod keys;
 pub mod memory;
 pub mod planning;
 pub mod thinking;
@@ -9,7 +9,7 @@ use serde_json::{Value, json};
 
 use super::{PlanningConfig, PlanningStrategy};
 use crate::{
-    Result,
+    Result, keys,
     traits::{CognitiveNode, ExecutionPlan, Goal, PlanStep, PlanningNode, ProgressEvaluation},
 };
 
@@ -197,7 +197,7 @@ impl<S: FlowState> Node for AdaptivePlanningNode<S> {
 
         match self.create_adaptive_plan(goal, &context).await {
             Ok(execution_plan) => {
-                context.set("execution_plan", &execution_plan)?;
+                context.put(&keys::EXECUTION_PLAN, &execution_plan)?;
                 context.set("plan_type", "adaptive")?;
                 context.set("adaptation_count", 0)?;
                 context.set("max_adaptations", self.max_adaptations)?;
@@ -272,7 +272,7 @@ impl<S: FlowState> PlanningNode for AdaptivePlanningNode<S> {
         plan: &ExecutionPlan,
         context: &Context,
     ) -> Result<ProgressEvaluation> {
-        let completed_steps: Vec<String> = context.get_json("completed_steps")?.unwrap_or_default();
+        let completed_steps = context.fetch(&keys::COMPLETED_STEPS)?.unwrap_or_default();
 
         let blocked_steps: Vec<String> = context.get_json("blocked_steps")?.unwrap_or_default();
 
@@ -9,7 +9,7 @@ use serde_json::{Value, json};
 
 use super::{PlanningConfig, PlanningStrategy};
 use crate::{
-    Result,
+    Result, keys,
     traits::{CognitiveNode, ExecutionPlan, Goal, PlanStep, PlanningNode, ProgressEvaluation},
 };
 
@@ -325,8 +325,8 @@ Format your response as a structured plan that can be executed step by step.
         plan: &ExecutionPlan,
         context: &Context,
     ) -> Result<ProgressEvaluation> {
-        let completed_steps: Vec<String> = context
-            .get_json("completed_steps")?
+        let completed_steps = context
+            .fetch(&keys::COMPLETED_STEPS)?
             .unwrap_or_else(Vec::new);
 
         let total_steps = plan.steps.len();
@@ -365,7 +365,7 @@ impl<S: FlowState> Node for GoalOrientedPlanningNode<S> {
         match self.create_execution_plan(goal, &context).await {
             Ok(execution_plan) => {
                 // Store the execution plan in context
-                context.set("execution_plan", &execution_plan)?;
+                context.put(&keys::EXECUTION_PLAN, &execution_plan)?;
                 context.set("plan_id", &execution_plan.id)?;
                 context.set("plan_steps", &execution_plan.steps)?;
 
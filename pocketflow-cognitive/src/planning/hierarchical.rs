@@ -9,7 +9,7 @@ use serde_json::{Value, json};
 
 use super::{PlanningConfig, PlanningStrategy};
 use crate::{
-    Result,
+    Result, keys,
     traits::{CognitiveNode, ExecutionPlan, Goal, PlanStep, PlanningNode, ProgressEvaluation},
 };
 
@@ -343,7 +343,7 @@ impl<S: FlowState> Node for HierarchicalPlanningNode<S> {
 
         match self.create_hierarchical_plan(goal, &context).await {
             Ok(execution_plan) => {
-                context.set("execution_plan", &execution_plan)?;
+                context.put(&keys::EXECUTION_PLAN, &execution_plan)?;
                 context.set("plan_type", "hierarchical")?;
                 context.set("max_hierarchy_depth", self.max_depth)?;
 
@@ -404,7 +404,7 @@ impl<S: FlowState> PlanningNode for HierarchicalPlanningNode<S> {
         plan: &ExecutionPlan,
         context: &Context,
     ) -> Result<ProgressEvaluation> {
-        let completed_steps: Vec<String> = context.get_json("completed_steps")?.unwrap_or_default();
+        let completed_steps = context.fetch(&keys::COMPLETED_STEPS)?.unwrap_or_default();
 
         let total_steps = plan.steps.len();
         let completed_count = completed_steps.len();
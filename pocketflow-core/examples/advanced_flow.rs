@@ -35,17 +35,17 @@ impl FlowState for OrderState {
 
     fn can_transition_to(&self, target: &Self) -> bool {
         use OrderState::*;
-        match (self, target) {
-            (Received, ValidatingPayment) => true,
-            (ValidatingPayment, PaymentApproved | PaymentRejected) => true,
-            (PaymentApproved, ProcessingOrder) => true,
-            (ProcessingOrder, CheckingInventory) => true,
-            (CheckingInventory, Packaging | InsufficientStock) => true,
-            (Packaging, Shipped) => true,
-            (Shipped, Delivered) => true,
-            (_, Cancelled | Failed) => true,
-            _ => false,
-        }
+        matches!(
+            (self, target),
+            (Received, ValidatingPayment)
+                | (ValidatingPayment, PaymentApproved | PaymentRejected)
+                | (PaymentApproved, ProcessingOrder)
+                | (ProcessingOrder, CheckingInventory)
+                | (CheckingInventory, Packaging | InsufficientStock)
+                | (Packaging, Shipped)
+                | (Shipped, Delivered)
+                | (_, Cancelled | Failed)
+        )
     }
 }
 
@@ -97,11 +97,17 @@ impl Node for InventoryChecker {
 
         let items: Vec<String> = context.get_json("order_items")?.unwrap_or_default();
 
+        if items.is_empty() {
+            context.set("cancellation_reason", "Order has no items")?;
+            println!("🚫 Empty order cancelled");
+            return Ok((context, OrderState::Cancelled));
+        }
+
         // Simulate inventory check
         tokio::time::sleep(Duration::from_millis(150)).await;
 
         // Simulate stock availability (80% chance of success)
-        let has_stock = items.len() > 0 && fastrand::f32() > 0.2;
+        let has_stock = !items.is_empty() && fastrand::f32() > 0.2;
 
         if has_stock {
             context.set("inventory_checked_at", chrono::Utc::now())?;
@@ -166,6 +172,12 @@ impl Node for ShippingProcessor {
 
         let tracking_number: String = context.get_json("tracking_number")?.unwrap_or_default();
 
+        if tracking_number.is_empty() {
+            context.set("failure_reason", "Missing tracking number")?;
+            println!("❌ Cannot ship without a tracking number");
+            return Ok((context, OrderState::Failed));
+        }
+
         // Simulate shipping processing
         tokio::time::sleep(Duration::from_millis(100)).await;
 
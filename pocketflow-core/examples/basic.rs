@@ -18,12 +18,12 @@ impl FlowState for WorkflowState {
     }
 
     fn can_transition_to(&self, target: &Self) -> bool {
-        match (self, target) {
-            (WorkflowState::Start, WorkflowState::Processing) => true,
-            (WorkflowState::Processing, WorkflowState::Validating) => true,
-            (WorkflowState::Validating, WorkflowState::Success | WorkflowState::Error) => true,
-            _ => false,
-        }
+        matches!(
+            (self, target),
+            (WorkflowState::Start, WorkflowState::Processing)
+                | (WorkflowState::Processing, WorkflowState::Validating)
+                | (WorkflowState::Validating, WorkflowState::Success | WorkflowState::Error)
+        )
     }
 }
 
@@ -43,6 +43,14 @@ impl ProcessNode {
 impl Node for ProcessNode {
     type State = WorkflowState;
 
+    async fn prepare(&self, context: &Context) -> Result<()> {
+        // Runs before `execute`; failing here stops the flow before any work is done
+        if !context.contains_json("input") {
+            return Err(FlowError::context("Missing 'input' in context"));
+        }
+        Ok(())
+    }
+
     async fn execute(&self, mut context: Context) -> Result<(Context, Self::State)> {
         println!("🔄 Processing in node: {}", self.name);
 
@@ -124,6 +132,8 @@ async fn main() -> Result<()> {
     let flow = SimpleFlow::builder()
         .name("BasicWorkflow")
         .initial_state(WorkflowState::Start)
+        // The nodes may jump to Error from any state, which the rules above don't list
+        .strict_transitions(false)
         // Add nodes for each state
         .node(WorkflowState::Start, ProcessNode::new("DataProcessor"))
         .node(
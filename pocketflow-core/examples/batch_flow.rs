@@ -36,7 +36,6 @@ struct BatchStats {
     total_items: usize,
     processed_items: usize,
     failed_items: usize,
-    processing_time_ms: u64,
 }
 
 // Data loader node - simulates loading data from a source
@@ -80,7 +79,6 @@ impl Node for DataLoaderNode {
             total_items: items.len(),
             processed_items: 0,
             failed_items: 0,
-            processing_time_ms: 0,
         };
         context.set("batch_stats", &stats)?;
 
@@ -93,131 +91,51 @@ impl Node for DataLoaderNode {
     }
 }
 
-// Batch processor node - processes items in parallel
-#[derive(Debug)]
-struct BatchProcessorNode {
-    chunk_size: usize,
-}
-
-impl BatchProcessorNode {
-    fn new(chunk_size: usize) -> Self {
-        Self { chunk_size }
-    }
-
-    // Simulate processing a single item
-    async fn process_item(&self, item: &DataItem) -> Result<DataItem> {
-        // Simulate processing time based on priority
-        let delay_ms = match item.priority {
-            1 => 50,  // Low priority - quick processing
-            2 => 100, // Medium priority
-            3 => 200, // High priority - more processing
-            _ => 100,
-        };
-
-        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
-
-        // Simulate occasional failures (5% chance)
-        if item.id % 20 == 0 {
-            return Err(FlowError::context(format!(
-                "Processing failed for item {}",
-                item.id
-            )));
-        }
-
-        // Transform the item
-        let mut processed = item.clone();
-        processed.value = format!("processed_{}", item.value);
-
-        Ok(processed)
+// Simulate processing a single item; used by the map-style batch node
+async fn process_item(item: DataItem) -> Result<DataItem> {
+    // Simulate processing time based on priority
+    let delay_ms = match item.priority {
+        1 => 50,  // Low priority - quick processing
+        2 => 100, // Medium priority
+        3 => 200, // High priority - more processing
+        _ => 100,
+    };
+
+    tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+
+    // Simulate occasional failures (5% chance)
+    if item.id.is_multiple_of(20) {
+        return Err(FlowError::context(format!(
+            "Processing failed for item {}",
+            item.id
+        )));
     }
-}
-
-#[async_trait]
-impl Node for BatchProcessorNode {
-    type State = BatchState;
-
-    async fn execute(&self, mut context: Context) -> Result<(Context, Self::State)> {
-        println!("⚙️ Processing batch data...");
-
-        let start_time = std::time::Instant::now();
-
-        let items: Vec<DataItem> = context.get_json("batch_data")?.unwrap_or_default();
-
-        if items.is_empty() {
-            return Ok((context, BatchState::Error));
-        }
-
-        let mut processed_items = Vec::new();
-        let mut failed_count = 0;
-
-        // Process items in chunks for better memory management
-        for chunk in items.chunks(self.chunk_size) {
-            println!("  📦 Processing chunk of {} items...", chunk.len());
-
-            // Process chunk items in parallel
-            let mut chunk_tasks = Vec::new();
-            for item in chunk {
-                let processor = self.clone();
-                let item = item.clone();
-                chunk_tasks.push(tokio::spawn(
-                    async move { processor.process_item(&item).await },
-                ));
-            }
-
-            // Collect results
-            for task in chunk_tasks {
-                match task.await {
-                    Ok(Ok(processed_item)) => {
-                        processed_items.push(processed_item);
-                    }
-                    Ok(Err(_)) | Err(_) => {
-                        failed_count += 1;
-                    }
-                }
-            }
-        }
-
-        let processing_time = start_time.elapsed();
-
-        // Update statistics
-        let mut stats: BatchStats = context.get_json("batch_stats")?.unwrap_or_default();
-        stats.processed_items = processed_items.len();
-        stats.failed_items = failed_count;
-        stats.processing_time_ms = processing_time.as_millis() as u64;
-
-        context.set("processed_data", &processed_items)?;
-        context.set("batch_stats", &stats)?;
 
-        println!("✅ Batch processing complete:");
-        println!("  📊 Processed: {}", stats.processed_items);
-        println!("  ❌ Failed: {}", stats.failed_items);
-        println!("  ⏱️ Time: {}ms", stats.processing_time_ms);
-
-        // Decide next state based on results
-        if stats.failed_items == 0 {
-            Ok((context, BatchState::Validation))
-        } else if stats.processed_items > 0 {
-            // Some items processed successfully, continue but with warnings
-            context.set("has_warnings", true)?;
-            Ok((context, BatchState::Validation))
-        } else {
-            // All items failed
-            context.set("error", "All items failed processing")?;
-            Ok((context, BatchState::Error))
-        }
-    }
+    // Transform the item
+    let mut processed = item;
+    processed.value = format!("processed_{}", processed.value);
 
-    fn name(&self) -> String {
-        format!("BatchProcessor(chunk:{})", self.chunk_size)
-    }
+    Ok(processed)
 }
 
-impl Clone for BatchProcessorNode {
-    fn clone(&self) -> Self {
-        Self {
-            chunk_size: self.chunk_size,
-        }
-    }
+// Batch processor node - processes items concurrently with retries
+fn batch_processor(concurrency: usize) -> Result<MapBatchNode<DataItem, DataItem, BatchState>> {
+    MapBatchNode::builder("BatchProcessor", "batch_data", process_item)
+        .output_key("processed_data")
+        .errors_key("batch_errors")
+        .concurrency(concurrency)
+        .retry(RetryPolicy::new(
+            2,
+            Backoff::Exponential {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(100),
+            },
+        ))
+        // Some items processed successfully, continue and let validation decide
+        .failure_policy(PartialFailurePolicy::SucceedOnAny)
+        .on_success(BatchState::Validation)
+        .on_error(BatchState::Error)
+        .build()
 }
 
 // Validation node - validates processed data
@@ -239,7 +157,16 @@ impl Node for ValidationNode {
     async fn execute(&self, mut context: Context) -> Result<(Context, Self::State)> {
         println!("🔍 Validating processed data...");
 
-        let stats: BatchStats = context.get_json("batch_stats")?.unwrap_or_default();
+        let processed: Vec<Option<DataItem>> =
+            context.get_json("processed_data")?.unwrap_or_default();
+        let errors: Vec<ItemError> = context.get_json("batch_errors")?.unwrap_or_default();
+
+        // Update statistics from the per-item results
+        let mut stats: BatchStats = context.get_json("batch_stats")?.unwrap_or_default();
+        stats.processed_items = processed.iter().flatten().count();
+        stats.failed_items = errors.len();
+        context.set("batch_stats", &stats)?;
+        context.set("has_warnings", !errors.is_empty())?;
 
         // Calculate success rate
         let success_rate = if stats.total_items > 0 {
@@ -291,7 +218,12 @@ impl Node for DataSaverNode {
     async fn execute(&self, mut context: Context) -> Result<(Context, Self::State)> {
         println!("💾 Saving processed data...");
 
-        let processed_data: Vec<DataItem> = context.get_json("processed_data")?.unwrap_or_default();
+        let processed_data: Vec<DataItem> = context
+            .get_json::<Vec<Option<DataItem>>>("processed_data")?
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+            .collect();
 
         // Simulate saving delay
         tokio::time::sleep(Duration::from_millis(300)).await;
@@ -317,7 +249,7 @@ impl Node for DataSaverNode {
 // Create a batch processing flow
 fn create_batch_flow(
     batch_size: usize,
-    chunk_size: usize,
+    concurrency: usize,
 ) -> Result<pocketflow_core::flow::SimpleFlow<BatchState>> {
     let flow = pocketflow_core::flow::SimpleFlow::builder()
         .name("batch_processing_flow")
@@ -329,7 +261,7 @@ fn create_batch_flow(
         )
         .node(BatchState::LoadingData, DataLoaderNode::new(batch_size))
         // Batch processing
-        .node(BatchState::Processing, BatchProcessorNode::new(chunk_size))
+        .node(BatchState::Processing, batch_processor(concurrency)?)
         // Validation
         .node(BatchState::Validation, ValidationNode::new(0.8))
         // Saving results
@@ -339,11 +271,14 @@ fn create_batch_flow(
     Ok(flow)
 }
 
-async fn run_batch_job(name: &str, batch_size: usize, chunk_size: usize) -> Result<()> {
+async fn run_batch_job(name: &str, batch_size: usize, concurrency: usize) -> Result<()> {
     println!("\n🚀 Starting Batch Job: {}", name);
-    println!("📋 Batch Size: {}, Chunk Size: {}", batch_size, chunk_size);
+    println!(
+        "📋 Batch Size: {}, Concurrency: {}",
+        batch_size, concurrency
+    );
 
-    let flow = create_batch_flow(batch_size, chunk_size)?;
+    let flow = create_batch_flow(batch_size, concurrency)?;
 
     // Create initial context
     let mut context = Context::new();
@@ -366,7 +301,6 @@ async fn run_batch_job(name: &str, batch_size: usize, chunk_size: usize) -> Resu
         println!("  Total Items: {}", stats.total_items);
         println!("  Processed: {}", stats.processed_items);
         println!("  Failed: {}", stats.failed_items);
-        println!("  Processing Time: {}ms", stats.processing_time_ms);
 
         if let Some(success_rate) = result.context.get_json::<f64>("success_rate")? {
             println!("  Success Rate: {:.1}%", success_rate * 100.0);
@@ -384,10 +318,10 @@ async fn run_batch_job(name: &str, batch_size: usize, chunk_size: usize) -> Resu
     }
 
     // Show error if failed
-    if result.final_state == BatchState::Error {
-        if let Some(error) = result.context.get_json::<String>("error")? {
-            println!("❌ Error: {}", error);
-        }
+    if result.final_state == BatchState::Error
+        && let Some(error) = result.context.get_json::<String>("error")?
+    {
+        println!("❌ Error: {}", error);
     }
 
     Ok(())
@@ -405,7 +339,7 @@ async fn main() -> Result<()> {
     // Medium batch - should complete successfully
     run_batch_job("Medium Batch", 50, 10).await?;
 
-    // Large batch with small chunks - might have some failures but should pass validation
+    // Large batch with low concurrency - might have some failures but should pass validation
     run_batch_job("Large Batch", 100, 5).await?;
 
     // Very large batch - might fail validation due to simulated failures
@@ -414,9 +348,9 @@ async fn main() -> Result<()> {
     println!("\n🎉 Batch Processing Examples completed!");
 
     println!("\n📋 Key Features Demonstrated:");
-    println!("✅ Parallel processing within chunks");
-    println!("✅ Configurable batch and chunk sizes");
-    println!("✅ Error handling and partial success scenarios");
+    println!("✅ Bounded per-item concurrency with MapBatchNode");
+    println!("✅ Per-item retries with exponential backoff");
+    println!("✅ Per-item error records and partial failure policy");
     println!("✅ Statistics tracking and validation");
     println!("✅ Async processing with proper resource management");
 
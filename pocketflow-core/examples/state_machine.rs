@@ -29,6 +29,8 @@ impl FlowState for OrderState {
         )
     }
 
+    // Some rules repeat earlier arms to keep the full state machine readable
+    #[allow(unreachable_patterns)]
     fn can_transition_to(&self, target: &Self) -> bool {
         match (self, target) {
             // From terminal states, no transitions allowed
@@ -242,6 +244,8 @@ fn create_order_flow() -> Result<SimpleFlow<OrderState>> {
     let flow = SimpleFlow::builder()
         .name("order_processing_flow")
         .initial_state(OrderState::Received)
+        // CancellationNode refunds out-of-stock orders directly, skipping Cancelled
+        .strict_transitions(false)
         // Start with payment processing
         .node(
             OrderState::Received,
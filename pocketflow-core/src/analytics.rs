@@ -0,0 +1,739 @@
+//! Rolling analytics over finished flow runs.
+//!
+//! [`FlowAnalytics`] keeps a compact [`RunRecord`] of recent runs in a window
+//! bounded by count and, optionally, by age. From the records' traces it
+//! computes per-node latency percentiles, groups failures by error and by the
+//! state runs stopped in, and finds the paths most runs take.
+//!
+//! With a sink, every recorded run is also appended to durable storage and
+//! [`FlowAnalytics::restore`] refills the window after a restart:
+//!
+//! ```rust,ignore
+//! let mut analytics = FlowAnalytics::new()
+//!     .max_runs(10_000)
+//!     .max_age(Duration::from_secs(24 * 3600))
+//!     .sink(JsonlAnalyticsSink::new("analytics.jsonl"));
+//! analytics.restore().await?;
+//!
+//! analytics.record(&flow.execute(context).await?).await?;
+//! let latency = analytics.node_latency("charge_card");
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    hash::Hash,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    error::{FlowError, Result},
+    flow_advanced::{AdvancedFlowResult, StepKind},
+    state::FlowState,
+};
+
+/// Summary of one finished run, as kept by [`FlowAnalytics`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord<S: FlowState> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow_name: Option<String>,
+    pub final_state: S,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration: Duration,
+    pub steps: usize,
+    pub finished_at: DateTime<Utc>,
+    /// States the run moved through, starting with the first one.
+    pub path: Vec<S>,
+    /// Every node attempt, in order.
+    pub nodes: Vec<NodeTiming>,
+}
+
+/// Duration and outcome of one node attempt.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeTiming {
+    pub node: String,
+    pub duration: Duration,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl<S: FlowState> RunRecord<S> {
+    /// Summarize a run result.
+    pub fn from_result(result: &AdvancedFlowResult<S>) -> Self {
+        let mut path: Vec<S> = result
+            .trace
+            .first()
+            .map(|step| step.from_state.clone())
+            .into_iter()
+            .collect();
+        let mut nodes = Vec::new();
+
+        for step in &result.trace {
+            let moved = matches!(
+                step.kind,
+                StepKind::Node | StepKind::Router | StepKind::Fallback | StepKind::Middleware
+            );
+            if moved && step.error.is_none() {
+                path.push(step.to_state.clone());
+            }
+            if matches!(step.kind, StepKind::Node | StepKind::Fallback) {
+                nodes.push(NodeTiming {
+                    node: step.node_name.clone(),
+                    duration: step.duration,
+                    error: step.error.clone(),
+                });
+            }
+        }
+
+        Self {
+            run_id: result.metadata.get("run_id").cloned(),
+            flow_name: result.metadata.get("flow_name").cloned(),
+            final_state: result.final_state.clone(),
+            success: result.success,
+            error: result.error.clone(),
+            duration: result.duration,
+            steps: result.steps,
+            finished_at: Utc::now(),
+            path,
+            nodes,
+        }
+    }
+}
+
+/// Latency percentiles of a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    /// Number of attempts measured.
+    pub count: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    fn from_durations(mut durations: Vec<Duration>) -> Option<Self> {
+        durations.sort_unstable();
+        let max = *durations.last()?;
+        // Nearest-rank percentile
+        let rank = |p: f64| {
+            let index = (p * durations.len() as f64).ceil() as usize;
+            durations[index.clamp(1, durations.len()) - 1]
+        };
+        Some(Self {
+            count: durations.len(),
+            p50: rank(0.50),
+            p95: rank(0.95),
+            p99: rank(0.99),
+            max,
+        })
+    }
+}
+
+/// A path through the flow and how many runs in the window took it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HotPath<S: FlowState> {
+    pub states: Vec<S>,
+    pub runs: usize,
+    /// Fraction of the runs in the window that took this path.
+    pub share: f64,
+}
+
+/// Durable storage for run records, see [`FlowAnalytics::sink`].
+///
+/// Records are passed as JSON so sinks work for any state type.
+#[async_trait]
+pub trait AnalyticsSink: Send + Sync {
+    /// Append a serialized [`RunRecord`].
+    async fn append(&self, record: &Value) -> Result<()>;
+
+    /// Load up to `limit` of the most recent records, oldest first.
+    async fn load_recent(&self, limit: usize) -> Result<Vec<Value>>;
+}
+
+/// Analytics over a rolling window of finished runs.
+pub struct FlowAnalytics<S: FlowState> {
+    window: VecDeque<RunRecord<S>>,
+    max_runs: usize,
+    max_age: Option<Duration>,
+    sink: Option<Arc<dyn AnalyticsSink>>,
+}
+
+impl<S: FlowState> FlowAnalytics<S> {
+    /// Create analytics keeping the last 1000 runs.
+    pub fn new() -> Self {
+        Self {
+            window: VecDeque::new(),
+            max_runs: 1000,
+            max_age: None,
+            sink: None,
+        }
+    }
+
+    /// Keep at most `max_runs` runs in the window.
+    pub fn max_runs(mut self, max_runs: usize) -> Self {
+        self.max_runs = max_runs;
+        self.prune();
+        self
+    }
+
+    /// Drop runs that finished more than `max_age` ago.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self.prune();
+        self
+    }
+
+    /// Append every run recorded with [`FlowAnalytics::record`] to `sink`.
+    pub fn sink(mut self, sink: impl AnalyticsSink + 'static) -> Self {
+        self.sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Add a run to the window.
+    pub fn record_execution(&mut self, result: AdvancedFlowResult<S>) {
+        self.push(RunRecord::from_result(&result));
+    }
+
+    /// Add a run to the window and append it to the sink, if any.
+    pub async fn record(&mut self, result: &AdvancedFlowResult<S>) -> Result<()>
+    where
+        S: Serialize,
+    {
+        let record = RunRecord::from_result(result);
+        if let Some(sink) = &self.sink {
+            sink.append(&serde_json::to_value(&record)?).await?;
+        }
+        self.push(record);
+        Ok(())
+    }
+
+    /// Refill the window from the sink, returning the number of runs loaded.
+    pub async fn restore(&mut self) -> Result<usize>
+    where
+        S: DeserializeOwned,
+    {
+        let sink = self
+            .sink
+            .as_ref()
+            .ok_or_else(|| FlowError::construction("FlowAnalytics has no sink to restore from"))?;
+        let records = sink.load_recent(self.max_runs).await?;
+
+        self.window = records
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<std::result::Result<_, _>>()?;
+        self.prune();
+        Ok(self.window.len())
+    }
+
+    /// Runs in the window, oldest first.
+    pub fn runs(&self) -> impl Iterator<Item = &RunRecord<S>> {
+        self.window.iter()
+    }
+
+    /// Number of runs in the window.
+    pub fn len(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Whether the window is empty.
+    pub fn is_empty(&self) -> bool {
+        self.window.is_empty()
+    }
+
+    pub fn success_rate(&self) -> f64 {
+        if self.window.is_empty() {
+            return 0.0;
+        }
+
+        let successful = self.window.iter().filter(|r| r.success).count();
+
+        successful as f64 / self.window.len() as f64
+    }
+
+    pub fn average_execution_time(&self) -> Duration {
+        if self.window.is_empty() {
+            return Duration::from_secs(0);
+        }
+
+        let total: Duration = self.window.iter().map(|r| r.duration).sum();
+
+        total / self.window.len() as u32
+    }
+
+    pub fn average_steps(&self) -> f64 {
+        if self.window.is_empty() {
+            return 0.0;
+        }
+
+        let total_steps: usize = self.window.iter().map(|r| r.steps).sum();
+
+        total_steps as f64 / self.window.len() as f64
+    }
+
+    pub fn most_common_final_state(&self) -> Option<&S> {
+        count_by(self.window.iter().map(|r| &r.final_state))
+            .into_iter()
+            .next()
+            .map(|(state, _)| state)
+    }
+
+    /// Latency percentiles of a node's attempts.
+    pub fn node_latency(&self, node: &str) -> Option<LatencyStats> {
+        let durations = self
+            .window
+            .iter()
+            .flat_map(|r| &r.nodes)
+            .filter(|timing| timing.node == node)
+            .map(|timing| timing.duration)
+            .collect();
+        LatencyStats::from_durations(durations)
+    }
+
+    /// Latency percentiles of every node, by node name.
+    pub fn node_latencies(&self) -> BTreeMap<String, LatencyStats> {
+        let mut durations: BTreeMap<String, Vec<Duration>> = BTreeMap::new();
+        for timing in self.window.iter().flat_map(|r| &r.nodes) {
+            durations
+                .entry(timing.node.clone())
+                .or_default()
+                .push(timing.duration);
+        }
+        durations
+            .into_iter()
+            .filter_map(|(node, durations)| {
+                LatencyStats::from_durations(durations).map(|stats| (node, stats))
+            })
+            .collect()
+    }
+
+    /// Failed runs grouped by error message, most frequent first.
+    pub fn failures_by_error(&self) -> Vec<(String, usize)> {
+        count_by(self.failures().map(|r| {
+            r.error
+                .clone()
+                .unwrap_or_else(|| "unknown error".to_string())
+        }))
+    }
+
+    /// Failed runs grouped by the state they stopped in, most frequent first.
+    pub fn failures_by_state(&self) -> Vec<(S, usize)> {
+        count_by(self.failures().map(|r| r.final_state.clone()))
+    }
+
+    /// The `limit` most common paths through the flow.
+    pub fn hot_paths(&self, limit: usize) -> Vec<HotPath<S>> {
+        let total = self.window.len() as f64;
+        count_by(self.window.iter().map(|r| &r.path))
+            .into_iter()
+            .take(limit)
+            .map(|(states, runs)| HotPath {
+                states: states.clone(),
+                runs,
+                share: runs as f64 / total,
+            })
+            .collect()
+    }
+
+    fn failures(&self) -> impl Iterator<Item = &RunRecord<S>> {
+        self.window.iter().filter(|r| !r.success)
+    }
+
+    fn push(&mut self, record: RunRecord<S>) {
+        self.window.push_back(record);
+        self.prune();
+    }
+
+    fn prune(&mut self) {
+        while self.window.len() > self.max_runs {
+            self.window.pop_front();
+        }
+        if let Some(max_age) = self.max_age {
+            let cutoff = Utc::now() - max_age;
+            while self
+                .window
+                .front()
+                .is_some_and(|record| record.finished_at < cutoff)
+            {
+                self.window.pop_front();
+            }
+        }
+    }
+}
+
+impl<S: FlowState> Default for FlowAnalytics<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Count equal items, most frequent first; ties keep first-seen order.
+fn count_by<K: Eq + Hash + Clone>(items: impl IntoIterator<Item = K>) -> Vec<(K, usize)> {
+    let mut positions: HashMap<K, usize> = HashMap::new();
+    let mut counts: Vec<(K, usize)> = Vec::new();
+    for item in items {
+        match positions.get(&item) {
+            Some(&position) => counts[position].1 += 1,
+            None => {
+                positions.insert(item.clone(), counts.len());
+                counts.push((item, 1));
+            }
+        }
+    }
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    counts
+}
+
+/// Run records appended as JSON lines to a file.
+#[derive(Debug)]
+pub struct JsonlAnalyticsSink {
+    path: PathBuf,
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl JsonlAnalyticsSink {
+    /// Append to the file at `path`, creating it on first write.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl AnalyticsSink for JsonlAnalyticsSink {
+    async fn append(&self, record: &Value) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let _guard = self.lock.lock().await;
+        if let Some(dir) = self.path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&line).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn load_recent(&self, limit: usize) -> Result<Vec<Value>> {
+        let _guard = self.lock.lock().await;
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let lines: Vec<_> = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        lines[lines.len().saturating_sub(limit)..]
+            .iter()
+            .map(|line| serde_json::from_str(line).map_err(FlowError::from))
+            .collect()
+    }
+}
+
+/// Run records stored in a SQLite table.
+///
+/// Besides the JSON payload, the `pocketflow_analytics` table has `flow_name`,
+/// `success` and `finished_at` columns for ad-hoc queries.
+#[cfg(feature = "sqlite")]
+#[derive(Clone)]
+pub struct SqliteAnalyticsSink {
+    conn: Arc<std::sync::Mutex<rusqlite::Connection>>,
+}
+
+#[cfg(feature = "sqlite")]
+impl std::fmt::Debug for SqliteAnalyticsSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqliteAnalyticsSink").finish()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteAnalyticsSink {
+    /// Open (or create) an analytics database at `path`.
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let conn = rusqlite::Connection::open(path).map_err(FlowError::storage)?;
+        Self::with_connection(conn)
+    }
+
+    /// Create a sink backed by an in-memory database.
+    pub fn in_memory() -> Result<Self> {
+        let conn = rusqlite::Connection::open_in_memory().map_err(FlowError::storage)?;
+        Self::with_connection(conn)
+    }
+
+    fn with_connection(conn: rusqlite::Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS pocketflow_analytics (
+                id          INTEGER PRIMARY KEY AUTOINCREMENT,
+                flow_name   TEXT,
+                success     INTEGER NOT NULL,
+                finished_at TEXT NOT NULL,
+                payload     TEXT NOT NULL
+            )",
+        )
+        .map_err(FlowError::storage)?;
+
+        Ok(Self {
+            conn: Arc::new(std::sync::Mutex::new(conn)),
+        })
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&rusqlite::Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn
+                .lock()
+                .map_err(|_| FlowError::storage("SQLite connection lock poisoned"))?;
+            f(&conn).map_err(FlowError::storage)
+        })
+        .await
+        .map_err(FlowError::storage)?
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl AnalyticsSink for SqliteAnalyticsSink {
+    async fn append(&self, record: &Value) -> Result<()> {
+        let payload = serde_json::to_string(record)?;
+        let flow_name = record["flow_name"].as_str().map(str::to_string);
+        let success = record["success"].as_bool().unwrap_or(false);
+        let finished_at = record["finished_at"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO pocketflow_analytics (flow_name, success, finished_at, payload)
+                 VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![flow_name, success, finished_at, payload],
+            )
+            .map(|_| ())
+        })
+        .await
+    }
+
+    async fn load_recent(&self, limit: usize) -> Result<Vec<Value>> {
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let payloads: Vec<String> = self
+            .with_conn(move |conn| {
+                let mut statement = conn.prepare(
+                    "SELECT payload FROM pocketflow_analytics ORDER BY id DESC LIMIT ?1",
+                )?;
+                let rows = statement.query_map([limit], |row| row.get(0))?;
+                rows.collect()
+            })
+            .await?;
+
+        payloads
+            .iter()
+            .rev()
+            .map(|payload| serde_json::from_str(payload).map_err(FlowError::from))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::Context, flow_advanced::ExecutionStep, state::SimpleState};
+
+    fn step(
+        node: &str,
+        from: SimpleState,
+        to: SimpleState,
+        millis: u64,
+    ) -> ExecutionStep<SimpleState> {
+        ExecutionStep {
+            step_number: 1,
+            from_state: from,
+            to_state: to,
+            node_name: node.to_string(),
+            duration: Duration::from_millis(millis),
+            timestamp: Utc::now(),
+            kind: StepKind::Node,
+            error: None,
+            attempt: 1,
+            children: Vec::new(),
+            context_diff: None,
+        }
+    }
+
+    /// A run through `charge`, failing there when `error` is set.
+    fn run(charge_millis: u64, error: Option<&str>) -> AdvancedFlowResult<SimpleState> {
+        let mut trace = vec![step(
+            "reserve",
+            SimpleState::Start,
+            SimpleState::Processing,
+            5,
+        )];
+        let mut charge = step(
+            "charge",
+            SimpleState::Processing,
+            SimpleState::Success,
+            charge_millis,
+        );
+        charge.error = error.map(str::to_string);
+        trace.push(charge);
+
+        let final_state = if error.is_some() {
+            SimpleState::Processing
+        } else {
+            SimpleState::Success
+        };
+        AdvancedFlowResult {
+            final_state,
+            context: Context::new(),
+            duration: Duration::from_millis(charge_millis + 5),
+            steps: trace.len(),
+            success: error.is_none(),
+            error: error.map(str::to_string),
+            metadata: HashMap::from([("flow_name".to_string(), "checkout".to_string())]),
+            trace,
+        }
+    }
+
+    #[test]
+    fn window_reports_latencies_failures_and_hot_paths() {
+        let mut analytics = FlowAnalytics::new().max_runs(100);
+        // Pushed out of the window by the 100 runs below
+        analytics.record_execution(run(10_000, Some("stale")));
+        for millis in 1..=97 {
+            analytics.record_execution(run(millis, None));
+        }
+        analytics.record_execution(run(200, Some("card declined")));
+        analytics.record_execution(run(300, Some("card declined")));
+        analytics.record_execution(run(400, Some("timeout")));
+
+        assert_eq!(analytics.len(), 100);
+        assert_eq!(analytics.success_rate(), 0.97);
+
+        let charge = analytics.node_latency("charge").unwrap();
+        assert_eq!(charge.count, 100);
+        assert_eq!(charge.p50, Duration::from_millis(50));
+        assert_eq!(charge.p95, Duration::from_millis(95));
+        assert_eq!(charge.p99, Duration::from_millis(300));
+        assert_eq!(charge.max, Duration::from_millis(400));
+        assert_eq!(
+            analytics.node_latencies()["reserve"].p99,
+            Duration::from_millis(5)
+        );
+        assert!(analytics.node_latency("refund").is_none());
+
+        assert_eq!(
+            analytics.failures_by_error(),
+            [("card declined".to_string(), 2), ("timeout".to_string(), 1)]
+        );
+        assert_eq!(
+            analytics.failures_by_state(),
+            [(SimpleState::Processing, 3)]
+        );
+
+        let paths = analytics.hot_paths(5);
+        assert_eq!(paths.len(), 2);
+        assert_eq!(
+            paths[0].states,
+            [
+                SimpleState::Start,
+                SimpleState::Processing,
+                SimpleState::Success
+            ]
+        );
+        assert_eq!(paths[0].runs, 97);
+        assert_eq!(
+            paths[1].states,
+            [SimpleState::Start, SimpleState::Processing]
+        );
+        assert_eq!(paths[1].share, 0.03);
+    }
+
+    #[tokio::test]
+    async fn jsonl_sink_survives_restarts() {
+        let path = std::env::temp_dir().join(format!(
+            "pocketflow-analytics-{}.jsonl",
+            uuid::Uuid::new_v4()
+        ));
+
+        let mut analytics = FlowAnalytics::new().sink(JsonlAnalyticsSink::new(&path));
+        for millis in [10, 20, 30] {
+            analytics.record(&run(millis, None)).await.unwrap();
+        }
+        analytics
+            .record(&run(40, Some("card declined")))
+            .await
+            .unwrap();
+
+        let mut restored = FlowAnalytics::<SimpleState>::new()
+            .max_runs(3)
+            .sink(JsonlAnalyticsSink::new(&path));
+        assert_eq!(restored.restore().await.unwrap(), 3);
+        assert_eq!(
+            restored.runs().map(|r| r.duration).collect::<Vec<_>>(),
+            analytics
+                .runs()
+                .skip(1)
+                .map(|r| r.duration)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            restored.runs().next().unwrap().flow_name.as_deref(),
+            Some("checkout")
+        );
+        assert_eq!(
+            restored.failures_by_error(),
+            [("card declined".to_string(), 1)]
+        );
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_sink_loads_most_recent_runs() {
+        let sink = SqliteAnalyticsSink::in_memory().unwrap();
+        let mut analytics = FlowAnalytics::new().sink(sink.clone());
+        for millis in [10, 20, 30] {
+            analytics.record(&run(millis, None)).await.unwrap();
+        }
+
+        let mut restored = FlowAnalytics::<SimpleState>::new().max_runs(2).sink(sink);
+        assert_eq!(restored.restore().await.unwrap(), 2);
+        assert_eq!(
+            restored.node_latency("charge").unwrap().max,
+            Duration::from_millis(30)
+        );
+        assert_eq!(
+            restored.runs().map(|r| r.duration).collect::<Vec<_>>(),
+            [Duration::from_millis(25), Duration::from_millis(35)]
+        );
+    }
+}
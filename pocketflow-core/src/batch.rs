@@ -0,0 +1,467 @@
+//! Map-style batch processing.
+//!
+//! [`MapBatchNode`] reads a list of items from the context, runs an async
+//! function on every item with bounded concurrency and per-item retries, and
+//! writes the per-item results and errors back to the context.
+
+use std::{marker::PhantomData, sync::Arc};
+
+use async_trait::async_trait;
+use futures::{StreamExt, future::BoxFuture, stream};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    context::Context,
+    error::{FlowError, Result},
+    node::Node,
+    policy::RetryPolicy,
+    state::FlowState,
+};
+
+/// Async function applied to each batch item.
+pub type ItemFn<T, R> = Arc<dyn Fn(T) -> BoxFuture<'static, Result<R>> + Send + Sync>;
+
+/// Record of an item that failed after all retries.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ItemError {
+    /// Index of the item in the input list.
+    pub index: usize,
+    /// Number of attempts made.
+    pub attempts: u32,
+    /// Error message of the last attempt.
+    pub error: String,
+}
+
+/// Decides whether a batch with failed items counts as a success.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PartialFailurePolicy {
+    /// Any failed item routes to the error state.
+    #[default]
+    FailOnAny,
+    /// Succeed as long as at least one item succeeded (or the batch was empty).
+    SucceedOnAny,
+    /// Succeed when the fraction of successful items reaches the threshold.
+    MinSuccessRate(f64),
+}
+
+impl PartialFailurePolicy {
+    fn is_success(&self, total: usize, failed: usize) -> bool {
+        if total == 0 {
+            return true;
+        }
+        match self {
+            PartialFailurePolicy::FailOnAny => failed == 0,
+            PartialFailurePolicy::SucceedOnAny => failed < total,
+            PartialFailurePolicy::MinSuccessRate(rate) => {
+                (total - failed) as f64 / total as f64 >= *rate
+            }
+        }
+    }
+}
+
+/// Batch node mapping an async function over every item of a list.
+///
+/// Results are written to `output_key` as a list aligned with the input, with
+/// `null` for failed items. Failures are written to `errors_key` as a list of
+/// [`ItemError`] records.
+pub struct MapBatchNode<T, R, S>
+where
+    S: FlowState,
+{
+    name: String,
+    items_key: String,
+    output_key: String,
+    errors_key: String,
+    func: ItemFn<T, R>,
+    concurrency: usize,
+    retry: RetryPolicy,
+    failure_policy: PartialFailurePolicy,
+    on_success: S,
+    on_error: S,
+    _phantom: PhantomData<fn() -> (T, R)>,
+}
+
+impl<T, R, S> std::fmt::Debug for MapBatchNode<T, R, S>
+where
+    S: FlowState,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MapBatchNode")
+            .field("name", &self.name)
+            .field("items_key", &self.items_key)
+            .field("output_key", &self.output_key)
+            .field("concurrency", &self.concurrency)
+            .field("retry", &self.retry)
+            .field("failure_policy", &self.failure_policy)
+            .finish()
+    }
+}
+
+impl<T, R, S> MapBatchNode<T, R, S>
+where
+    T: Clone + Send + Sync + 'static,
+    R: Send + 'static,
+    S: FlowState,
+{
+    /// Create a builder reading items from `items_key` and mapping them with `func`.
+    pub fn builder<F, Fut>(
+        name: impl Into<String>,
+        items_key: impl Into<String>,
+        func: F,
+    ) -> MapBatchNodeBuilder<T, R, S>
+    where
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<R>> + Send + 'static,
+    {
+        MapBatchNodeBuilder::new(name, items_key, func)
+    }
+
+    /// Run one item with retries.
+    async fn run_item(
+        func: ItemFn<T, R>,
+        retry: RetryPolicy,
+        index: usize,
+        item: T,
+    ) -> std::result::Result<R, ItemError> {
+        let mut attempt = 1;
+        loop {
+            match func(item.clone()).await {
+                Ok(result) => return Ok(result),
+                Err(error) if attempt >= retry.max_attempts() => {
+                    return Err(ItemError {
+                        index,
+                        attempts: attempt,
+                        error: error.to_string(),
+                    });
+                }
+                Err(_) => {
+                    tokio::time::sleep(retry.delay(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<T, R, S> Node for MapBatchNode<T, R, S>
+where
+    T: Clone + DeserializeOwned + Send + Sync + 'static,
+    R: Serialize + Send + Sync + 'static,
+    S: FlowState,
+{
+    type State = S;
+
+    async fn execute(&self, mut context: Context) -> Result<(Context, Self::State)> {
+        let items: Vec<T> = context.get_json(&self.items_key)?.unwrap_or_default();
+        let total = items.len();
+
+        let tasks: Vec<BoxFuture<'static, std::result::Result<R, ItemError>>> = items
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let future = Self::run_item(self.func.clone(), self.retry.clone(), index, item);
+                Box::pin(future) as BoxFuture<'static, _>
+            })
+            .collect();
+
+        // `buffered` keeps results aligned with the input order
+        let outcomes: Vec<_> = stream::iter(tasks)
+            .buffered(self.concurrency.max(1))
+            .collect()
+            .await;
+
+        let mut results = Vec::with_capacity(total);
+        let mut errors = Vec::new();
+        for outcome in outcomes {
+            match outcome {
+                Ok(result) => results.push(Some(result)),
+                Err(error) => {
+                    results.push(None);
+                    errors.push(error);
+                }
+            }
+        }
+
+        let success = self.failure_policy.is_success(total, errors.len());
+        context.set(self.output_key.clone(), &results)?;
+        context.set(self.errors_key.clone(), &errors)?;
+
+        let next_state = if success {
+            self.on_success.clone()
+        } else {
+            self.on_error.clone()
+        };
+        Ok((context, next_state))
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn emits(&self) -> Option<Vec<Self::State>> {
+        Some(vec![self.on_success.clone(), self.on_error.clone()])
+    }
+}
+
+/// Builder for [`MapBatchNode`].
+pub struct MapBatchNodeBuilder<T, R, S>
+where
+    S: FlowState,
+{
+    name: String,
+    items_key: String,
+    output_key: Option<String>,
+    errors_key: Option<String>,
+    func: ItemFn<T, R>,
+    concurrency: usize,
+    retry: RetryPolicy,
+    failure_policy: PartialFailurePolicy,
+    on_success: Option<S>,
+    on_error: Option<S>,
+}
+
+impl<T, R, S> MapBatchNodeBuilder<T, R, S>
+where
+    T: Clone + Send + Sync + 'static,
+    R: Send + 'static,
+    S: FlowState,
+{
+    /// Create a new builder.
+    pub fn new<F, Fut>(name: impl Into<String>, items_key: impl Into<String>, func: F) -> Self
+    where
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<R>> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            items_key: items_key.into(),
+            output_key: None,
+            errors_key: None,
+            func: Arc::new(move |item| Box::pin(func(item))),
+            concurrency: 1,
+            retry: RetryPolicy::none(),
+            failure_policy: PartialFailurePolicy::default(),
+            on_success: None,
+            on_error: None,
+        }
+    }
+
+    /// Key the results are written to (defaults to `"{items_key}_results"`).
+    pub fn output_key(mut self, key: impl Into<String>) -> Self {
+        self.output_key = Some(key.into());
+        self
+    }
+
+    /// Key the error records are written to (defaults to `"{output_key}_errors"`).
+    pub fn errors_key(mut self, key: impl Into<String>) -> Self {
+        self.errors_key = Some(key.into());
+        self
+    }
+
+    /// Maximum number of items processed at the same time.
+    pub fn concurrency(mut self, limit: usize) -> Self {
+        self.concurrency = limit;
+        self
+    }
+
+    /// Retry policy applied to each item.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// How failed items affect the next state.
+    pub fn failure_policy(mut self, policy: PartialFailurePolicy) -> Self {
+        self.failure_policy = policy;
+        self
+    }
+
+    /// State to move to when the batch counts as a success.
+    pub fn on_success(mut self, state: S) -> Self {
+        self.on_success = Some(state);
+        self
+    }
+
+    /// State to move to when the batch counts as a failure.
+    pub fn on_error(mut self, state: S) -> Self {
+        self.on_error = Some(state);
+        self
+    }
+
+    /// Build the node.
+    pub fn build(self) -> Result<MapBatchNode<T, R, S>> {
+        let on_success = self
+            .on_success
+            .ok_or_else(|| FlowError::construction("Success state not set"))?;
+        let on_error = self
+            .on_error
+            .ok_or_else(|| FlowError::construction("Error state not set"))?;
+
+        let output_key = self
+            .output_key
+            .unwrap_or_else(|| format!("{}_results", self.items_key));
+        let errors_key = self
+            .errors_key
+            .unwrap_or_else(|| format!("{output_key}_errors"));
+
+        Ok(MapBatchNode {
+            name: self.name,
+            items_key: self.items_key,
+            output_key,
+            errors_key,
+            func: self.func,
+            concurrency: self.concurrency,
+            retry: self.retry,
+            failure_policy: self.failure_policy,
+            on_success,
+            on_error,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{policy::Backoff, state::SimpleState};
+
+    #[tokio::test]
+    async fn maps_items_with_retries() {
+        let attempts = Arc::new(Mutex::new(HashMap::<i32, u32>::new()));
+        let counter = attempts.clone();
+
+        let node = MapBatchNode::builder("double", "numbers", move |n: i32| {
+            let counter = counter.clone();
+            async move {
+                let attempt = {
+                    let mut counts = counter.lock().unwrap();
+                    let entry = counts.entry(n).or_insert(0);
+                    *entry += 1;
+                    *entry
+                };
+                // Item 2 is flaky and succeeds on its second attempt
+                if n == 2 && attempt == 1 {
+                    return Err(FlowError::context("flaky"));
+                }
+                Ok(n * 2)
+            }
+        })
+        .output_key("doubled")
+        .concurrency(2)
+        .retry(RetryPolicy::new(
+            2,
+            Backoff::Fixed(Duration::from_millis(1)),
+        ))
+        .on_success(SimpleState::Success)
+        .on_error(SimpleState::Error)
+        .build()
+        .unwrap();
+
+        let mut context = Context::new();
+        context.set("numbers", vec![1, 2, 3]).unwrap();
+        let (context, state) = node.execute(context).await.unwrap();
+
+        assert_eq!(state, SimpleState::Success);
+        assert_eq!(
+            context.get_json::<Vec<Option<i32>>>("doubled").unwrap(),
+            Some(vec![Some(2), Some(4), Some(6)])
+        );
+        assert_eq!(
+            context
+                .get_json::<Vec<ItemError>>("doubled_errors")
+                .unwrap(),
+            Some(vec![])
+        );
+        assert_eq!(attempts.lock().unwrap()[&2], 2);
+    }
+
+    #[tokio::test]
+    async fn partial_failures_follow_policy() {
+        let build = |policy| {
+            MapBatchNode::builder("evens", "numbers", |n: i32| async move {
+                if n % 2 == 0 {
+                    Ok(n)
+                } else {
+                    Err(FlowError::context(format!("odd: {n}")))
+                }
+            })
+            .retry(RetryPolicy::new(1, Backoff::None))
+            .failure_policy(policy)
+            .on_success(SimpleState::Success)
+            .on_error(SimpleState::Error)
+            .build()
+            .unwrap()
+        };
+
+        let mut context = Context::new();
+        context.set("numbers", vec![1, 2, 4]).unwrap();
+
+        let (_, state) = build(PartialFailurePolicy::FailOnAny)
+            .execute(context.clone())
+            .await
+            .unwrap();
+        assert_eq!(state, SimpleState::Error);
+
+        let (_, state) = build(PartialFailurePolicy::MinSuccessRate(0.9))
+            .execute(context.clone())
+            .await
+            .unwrap();
+        assert_eq!(state, SimpleState::Error);
+
+        let (result, state) = build(PartialFailurePolicy::SucceedOnAny)
+            .execute(context)
+            .await
+            .unwrap();
+        assert_eq!(state, SimpleState::Success);
+        assert_eq!(
+            result
+                .get_json::<Vec<Option<i32>>>("numbers_results")
+                .unwrap(),
+            Some(vec![None, Some(2), Some(4)])
+        );
+        let errors: Vec<ItemError> = result.get_json("numbers_results_errors").unwrap().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].index, 0);
+        assert_eq!(errors[0].attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn respects_concurrency_limit() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (tracker, peak_tracker) = (in_flight.clone(), peak.clone());
+
+        let node = MapBatchNode::builder("limited", "items", move |n: u32| {
+            let (in_flight, peak) = (tracker.clone(), peak_tracker.clone());
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(n)
+            }
+        })
+        .concurrency(3)
+        .on_success(SimpleState::Success)
+        .on_error(SimpleState::Error)
+        .build()
+        .unwrap();
+
+        let mut context = Context::new();
+        context.set("items", (0..10).collect::<Vec<u32>>()).unwrap();
+        let (_, state) = node.execute(context).await.unwrap();
+
+        assert_eq!(state, SimpleState::Success);
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+    }
+}
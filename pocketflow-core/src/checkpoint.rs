@@ -0,0 +1,640 @@
+//! Durable checkpointing for advanced flow executions.
+//!
+//! A [`CheckpointStore`] records the state, context and trace of a run after
+//! every committed step so that [`AdvancedFlow::resume`] can pick the run up
+//! again after a crash or restart.
+//!
+//! [`AdvancedFlow::resume`]: crate::flow_advanced::AdvancedFlow::resume
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+use crate::{
+    context::Context,
+    error::{FlowError, Result},
+    flow_advanced::ExecutionStep,
+    input::{PENDING_INPUT, PendingInput},
+    persist::TypeRegistry,
+    state::FlowState,
+};
+
+/// Snapshot of a flow run taken after a committed step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Identifier of the run this checkpoint belongs to.
+    pub run_id: String,
+    /// Name of the flow that produced the run.
+    pub flow_name: String,
+    /// Number of steps committed so far.
+    pub steps: usize,
+    /// Serialized current state.
+    pub state: Value,
+    /// Context JSON data at the time of the checkpoint.
+    pub json_data: HashMap<String, Value>,
+    /// Context metadata at the time of the checkpoint.
+    pub metadata: HashMap<String, Value>,
+    /// Typed context entries by registered type name, if the flow has a
+    /// [`TypeRegistry`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub typed: BTreeMap<String, Value>,
+    /// Serialized execution trace.
+    pub trace: Vec<Value>,
+    /// Run metadata (flow name, start time, ...).
+    pub run_metadata: HashMap<String, String>,
+    /// Whether the run has ended, in a terminal state or compensated after
+    /// a failure.
+    pub completed: bool,
+    /// When the checkpoint was written.
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Checkpoint {
+    /// Deserialize the checkpointed state.
+    pub fn state_as<S: DeserializeOwned>(&self) -> Result<S> {
+        serde_json::from_value(self.state.clone()).map_err(FlowError::from)
+    }
+
+    /// Deserialize the checkpointed trace.
+    pub fn trace_as<S>(&self) -> Result<Vec<ExecutionStep<S>>>
+    where
+        S: FlowState + DeserializeOwned,
+    {
+        self.trace
+            .iter()
+            .map(|step| serde_json::from_value(step.clone()).map_err(FlowError::from))
+            .collect()
+    }
+
+    /// The input the run is waiting for, if it is suspended.
+    pub fn pending_input(&self) -> Result<Option<PendingInput>> {
+        self.json_data
+            .get(PENDING_INPUT.name())
+            .map(|pending| serde_json::from_value(pending.clone()))
+            .transpose()
+            .map_err(FlowError::from)
+    }
+
+    /// Rebuild the checkpointed context (JSON data and metadata only).
+    pub fn context(&self) -> Context {
+        Context::from_parts(self.json_data.clone(), self.metadata.clone())
+    }
+
+    /// Rebuild the checkpointed context, restoring typed entries through
+    /// `registry`.
+    pub fn context_with(&self, registry: &TypeRegistry) -> Result<Context> {
+        let mut context = self.context();
+        registry.decode(self.typed.clone(), &mut context)?;
+        Ok(context)
+    }
+}
+
+/// Storage backend for flow checkpoints.
+///
+/// Only the latest checkpoint of each run is kept.
+#[async_trait]
+pub trait CheckpointStore: Send + Sync {
+    /// Save (or replace) the checkpoint for a run.
+    async fn save(&self, checkpoint: &Checkpoint) -> Result<()>;
+
+    /// Load the latest checkpoint for a run.
+    async fn load(&self, run_id: &str) -> Result<Option<Checkpoint>>;
+
+    /// Delete the checkpoint for a run.
+    async fn delete(&self, run_id: &str) -> Result<()>;
+
+    /// List the run ids that have a checkpoint.
+    async fn list_runs(&self) -> Result<Vec<String>>;
+}
+
+/// In-memory checkpoint store, mostly useful for tests.
+#[derive(Debug, Default)]
+pub struct InMemoryCheckpointStore {
+    checkpoints: RwLock<HashMap<String, Checkpoint>>,
+}
+
+impl InMemoryCheckpointStore {
+    /// Create an empty in-memory store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for InMemoryCheckpointStore {
+    async fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+        self.checkpoints
+            .write()
+            .await
+            .insert(checkpoint.run_id.clone(), checkpoint.clone());
+        Ok(())
+    }
+
+    async fn load(&self, run_id: &str) -> Result<Option<Checkpoint>> {
+        Ok(self.checkpoints.read().await.get(run_id).cloned())
+    }
+
+    async fn delete(&self, run_id: &str) -> Result<()> {
+        self.checkpoints.write().await.remove(run_id);
+        Ok(())
+    }
+
+    async fn list_runs(&self) -> Result<Vec<String>> {
+        Ok(self.checkpoints.read().await.keys().cloned().collect())
+    }
+}
+
+/// Checkpoint store writing one JSON file per run into a directory.
+#[derive(Debug, Clone)]
+pub struct FileCheckpointStore {
+    dir: PathBuf,
+}
+
+impl FileCheckpointStore {
+    /// Create a store rooted at `dir`. The directory is created on first save.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Directory the checkpoints are written to.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path_for(&self, run_id: &str) -> Result<PathBuf> {
+        let valid = !run_id.is_empty()
+            && run_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(FlowError::storage(format!("Invalid run id: {run_id:?}")));
+        }
+        Ok(self.dir.join(format!("{run_id}.json")))
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for FileCheckpointStore {
+    async fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let path = self.path_for(&checkpoint.run_id)?;
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        // Write to a temporary file first so a crash never leaves a torn checkpoint.
+        let tmp_path = path.with_extension("json.tmp");
+        let bytes = serde_json::to_vec_pretty(checkpoint)?;
+        tokio::fs::write(&tmp_path, bytes).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+
+    async fn load(&self, run_id: &str) -> Result<Option<Checkpoint>> {
+        let path = self.path_for(run_id)?;
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, run_id: &str) -> Result<()> {
+        let path = self.path_for(run_id)?;
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list_runs(&self) -> Result<Vec<String>> {
+        let mut runs = Vec::new();
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(runs),
+            Err(e) => return Err(e.into()),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json")
+                && let Some(stem) = path.file_stem().and_then(|s| s.to_str())
+            {
+                runs.push(stem.to_string());
+            }
+        }
+        Ok(runs)
+    }
+}
+
+/// SQLite-backed checkpoint store.
+#[cfg(feature = "sqlite")]
+#[derive(Clone)]
+pub struct SqliteCheckpointStore {
+    conn: std::sync::Arc<std::sync::Mutex<rusqlite::Connection>>,
+}
+
+#[cfg(feature = "sqlite")]
+impl std::fmt::Debug for SqliteCheckpointStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqliteCheckpointStore").finish()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteCheckpointStore {
+    /// Open (or create) a checkpoint database at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = rusqlite::Connection::open(path).map_err(FlowError::storage)?;
+        Self::with_connection(conn)
+    }
+
+    /// Create a store backed by an in-memory database.
+    pub fn in_memory() -> Result<Self> {
+        let conn = rusqlite::Connection::open_in_memory().map_err(FlowError::storage)?;
+        Self::with_connection(conn)
+    }
+
+    fn with_connection(conn: rusqlite::Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS pocketflow_checkpoints (
+                run_id     TEXT PRIMARY KEY,
+                flow_name  TEXT NOT NULL,
+                steps      INTEGER NOT NULL,
+                completed  INTEGER NOT NULL,
+                payload    TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
+        )
+        .map_err(FlowError::storage)?;
+
+        Ok(Self {
+            conn: std::sync::Arc::new(std::sync::Mutex::new(conn)),
+        })
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&rusqlite::Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn
+                .lock()
+                .map_err(|_| FlowError::storage("SQLite connection lock poisoned"))?;
+            f(&conn).map_err(FlowError::storage)
+        })
+        .await
+        .map_err(FlowError::storage)?
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl CheckpointStore for SqliteCheckpointStore {
+    async fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let payload = serde_json::to_string(checkpoint)?;
+        let run_id = checkpoint.run_id.clone();
+        let flow_name = checkpoint.flow_name.clone();
+        let steps = checkpoint.steps as i64;
+        let completed = checkpoint.completed;
+        let updated_at = checkpoint.updated_at.to_rfc3339();
+
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO pocketflow_checkpoints
+                    (run_id, flow_name, steps, completed, payload, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT(run_id) DO UPDATE SET
+                    flow_name = excluded.flow_name,
+                    steps = excluded.steps,
+                    completed = excluded.completed,
+                    payload = excluded.payload,
+                    updated_at = excluded.updated_at",
+                rusqlite::params![run_id, flow_name, steps, completed, payload, updated_at],
+            )
+            .map(|_| ())
+        })
+        .await
+    }
+
+    async fn load(&self, run_id: &str) -> Result<Option<Checkpoint>> {
+        use rusqlite::OptionalExtension;
+
+        let run_id = run_id.to_string();
+        let payload: Option<String> = self
+            .with_conn(move |conn| {
+                conn.query_row(
+                    "SELECT payload FROM pocketflow_checkpoints WHERE run_id = ?1",
+                    [run_id],
+                    |row| row.get(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        payload
+            .map(|payload| serde_json::from_str(&payload).map_err(FlowError::from))
+            .transpose()
+    }
+
+    async fn delete(&self, run_id: &str) -> Result<()> {
+        let run_id = run_id.to_string();
+        self.with_conn(move |conn| {
+            conn.execute(
+                "DELETE FROM pocketflow_checkpoints WHERE run_id = ?1",
+                [run_id],
+            )
+            .map(|_| ())
+        })
+        .await
+    }
+
+    async fn list_runs(&self) -> Result<Vec<String>> {
+        self.with_conn(|conn| {
+            let mut stmt =
+                conn.prepare("SELECT run_id FROM pocketflow_checkpoints ORDER BY updated_at")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect()
+        })
+        .await
+    }
+}
+
+/// Type-erased checkpoint codec for a flow's state type.
+///
+/// Flows only require `S: Serialize + DeserializeOwned` once checkpointing is
+/// enabled, so the conversions are captured when the store is configured.
+pub(crate) struct Checkpointer<S: FlowState> {
+    pub(crate) store: Arc<dyn CheckpointStore>,
+    /// Registry used to checkpoint typed context entries.
+    pub(crate) types: Option<Arc<TypeRegistry>>,
+    encode_state: fn(&S) -> Result<Value>,
+    decode_state: fn(&Checkpoint) -> Result<S>,
+    encode_step: fn(&ExecutionStep<S>) -> Result<Value>,
+    decode_trace: fn(&Checkpoint) -> Result<Vec<ExecutionStep<S>>>,
+}
+
+impl<S: FlowState> Clone for Checkpointer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            types: self.types.clone(),
+            encode_state: self.encode_state,
+            decode_state: self.decode_state,
+            encode_step: self.encode_step,
+            decode_trace: self.decode_trace,
+        }
+    }
+}
+
+impl<S: FlowState> Checkpointer<S> {
+    pub(crate) fn new(store: Arc<dyn CheckpointStore>) -> Self
+    where
+        S: Serialize + DeserializeOwned,
+    {
+        Self {
+            store,
+            types: None,
+            encode_state: |state| serde_json::to_value(state).map_err(FlowError::from),
+            decode_state: Checkpoint::state_as::<S>,
+            encode_step: |step| serde_json::to_value(step).map_err(FlowError::from),
+            decode_trace: Checkpoint::trace_as::<S>,
+        }
+    }
+
+    /// Build a checkpoint from the current run position.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn checkpoint(
+        &self,
+        run_id: &str,
+        flow_name: &str,
+        steps: usize,
+        state: &S,
+        context: &Context,
+        trace: &[ExecutionStep<S>],
+        run_metadata: &HashMap<String, String>,
+        completed: bool,
+    ) -> Result<Checkpoint> {
+        Ok(Checkpoint {
+            run_id: run_id.to_string(),
+            flow_name: flow_name.to_string(),
+            steps,
+            state: (self.encode_state)(state)?,
+            json_data: context.json_data().clone(),
+            metadata: context.metadata().clone(),
+            typed: match &self.types {
+                Some(types) => types.encode(context)?,
+                None => BTreeMap::new(),
+            },
+            trace: trace
+                .iter()
+                .map(self.encode_step)
+                .collect::<Result<Vec<_>>>()?,
+            run_metadata: run_metadata.clone(),
+            completed,
+            updated_at: Utc::now(),
+        })
+    }
+
+    /// Rebuild the context of a checkpoint, typed entries included when a
+    /// registry is configured.
+    pub(crate) fn decode_context(&self, checkpoint: &Checkpoint) -> Result<Context> {
+        match &self.types {
+            Some(types) => checkpoint.context_with(types),
+            None => Ok(checkpoint.context()),
+        }
+    }
+
+    pub(crate) fn decode_state(&self, checkpoint: &Checkpoint) -> Result<S> {
+        (self.decode_state)(checkpoint)
+    }
+
+    pub(crate) fn decode_trace(&self, checkpoint: &Checkpoint) -> Result<Vec<ExecutionStep<S>>> {
+        (self.decode_trace)(checkpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    };
+
+    use super::*;
+    use crate::{
+        flow_advanced::AdvancedFlow,
+        node::{Node, helpers},
+        state::SimpleState,
+    };
+
+    #[derive(Debug)]
+    struct FailOnce {
+        failed: AtomicBool,
+    }
+
+    #[async_trait]
+    impl Node for FailOnce {
+        type State = SimpleState;
+
+        async fn execute(&self, mut context: Context) -> Result<(Context, Self::State)> {
+            if !self.failed.swap(true, Ordering::SeqCst) {
+                return Err(FlowError::context("simulated crash"));
+            }
+            context.set("finished", true)?;
+            Ok((context, SimpleState::Success))
+        }
+    }
+
+    fn sample_checkpoint(run_id: &str) -> Checkpoint {
+        Checkpoint {
+            run_id: run_id.to_string(),
+            flow_name: "test".to_string(),
+            steps: 1,
+            state: serde_json::json!("Processing"),
+            json_data: HashMap::from([("key".to_string(), serde_json::json!(42))]),
+            metadata: HashMap::new(),
+            typed: BTreeMap::new(),
+            trace: Vec::new(),
+            run_metadata: HashMap::new(),
+            completed: false,
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn file_store_round_trip() {
+        let dir = std::env::temp_dir().join(format!("pocketflow-ckpt-{}", uuid::Uuid::new_v4()));
+        let store = FileCheckpointStore::new(&dir);
+
+        assert!(store.load("run-1").await.unwrap().is_none());
+        store.save(&sample_checkpoint("run-1")).await.unwrap();
+
+        let loaded = store.load("run-1").await.unwrap().unwrap();
+        assert_eq!(
+            loaded.state_as::<SimpleState>().unwrap(),
+            SimpleState::Processing
+        );
+        assert_eq!(loaded.context().get_json::<i32>("key").unwrap(), Some(42));
+        assert_eq!(store.list_runs().await.unwrap(), vec!["run-1".to_string()]);
+
+        store.delete("run-1").await.unwrap();
+        assert!(store.load("run-1").await.unwrap().is_none());
+        assert!(store.load("../escape").await.is_err());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn resume_continues_from_last_committed_step() {
+        let store = Arc::new(InMemoryCheckpointStore::new());
+        let mut types = TypeRegistry::new();
+        types.register::<u32>("attempts");
+        let flow = AdvancedFlow::builder()
+            .name("resumable")
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::passthrough("start", SimpleState::Processing),
+            )
+            .on_state(
+                SimpleState::Processing,
+                FailOnce {
+                    failed: AtomicBool::new(false),
+                },
+            )
+            .checkpoint_store(store.clone())
+            .type_registry(Arc::new(types))
+            .build()
+            .unwrap();
+
+        let mut context = Context::new();
+        context.insert(3u32).unwrap();
+        let result = flow.execute_with_run_id("run-42", context).await.unwrap();
+        assert!(!result.success);
+
+        let checkpoint = store.load("run-42").await.unwrap().unwrap();
+        assert_eq!(checkpoint.steps, 1);
+        assert_eq!(
+            checkpoint.state_as::<SimpleState>().unwrap(),
+            SimpleState::Processing
+        );
+        assert!(!checkpoint.completed);
+        assert_eq!(checkpoint.typed["attempts"], 3);
+
+        let resumed = flow.resume("run-42").await.unwrap();
+        assert!(resumed.success);
+        assert_eq!(resumed.final_state, SimpleState::Success);
+        assert_eq!(resumed.trace.len(), 2);
+        assert_eq!(resumed.trace[0].node_name, "start");
+        assert_eq!(
+            resumed.context.get_json::<bool>("finished").unwrap(),
+            Some(true)
+        );
+        assert_eq!(resumed.context.get::<u32>(), Some(&3));
+        assert!(store.load("run-42").await.unwrap().unwrap().completed);
+    }
+
+    #[tokio::test]
+    async fn resume_unknown_run_fails() {
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::passthrough("start", SimpleState::Success),
+            )
+            .checkpoint_store(Arc::new(InMemoryCheckpointStore::new()))
+            .build()
+            .unwrap();
+
+        assert!(flow.resume("missing").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn resume_finished_run_fails() {
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::passthrough("start", SimpleState::Success),
+            )
+            .checkpoint_store(Arc::new(InMemoryCheckpointStore::new()))
+            .build()
+            .unwrap();
+
+        let result = flow
+            .execute_with_run_id("done", Context::new())
+            .await
+            .unwrap();
+        assert!(result.success);
+
+        let error = flow.resume("done").await.unwrap_err();
+        assert!(error.to_string().contains("already finished"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_store_round_trip() {
+        let store = SqliteCheckpointStore::in_memory().unwrap();
+
+        store.save(&sample_checkpoint("run-1")).await.unwrap();
+        let mut updated = sample_checkpoint("run-1");
+        updated.steps = 2;
+        store.save(&updated).await.unwrap();
+
+        let loaded = store.load("run-1").await.unwrap().unwrap();
+        assert_eq!(loaded.steps, 2);
+        assert_eq!(store.list_runs().await.unwrap(), vec!["run-1".to_string()]);
+
+        store.delete("run-1").await.unwrap();
+        assert!(store.load("run-1").await.unwrap().is_none());
+    }
+}
@@ -2,11 +2,11 @@
 
 use std::{
     any::{Any, TypeId},
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     sync::Arc,
 };
 
-use serde::{Serialize, de::DeserializeOwned};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::DeserializeOwned};
 use serde_json::Value;
 
 use crate::error::{FlowError, Result};
@@ -15,14 +15,24 @@ use crate::error::{FlowError, Result};
 ///
 /// The Context provides a way to store and retrieve data between nodes
 /// in a workflow. It supports both typed data access and JSON serialization.
+///
+/// JSON data and metadata are copy-on-write: cloning a context (or taking a
+/// [`ContextSnapshot`]) shares them until one side is modified.
 #[derive(Clone, Debug, Default)]
 pub struct Context {
     /// Typed data storage
-    data: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    data: HashMap<TypeId, TypedEntry>,
     /// JSON data storage for serializable data
-    json_data: HashMap<String, Value>,
+    json_data: Arc<HashMap<String, Value>>,
     /// Metadata for the context
-    metadata: HashMap<String, Value>,
+    metadata: Arc<HashMap<String, Value>>,
+}
+
+/// A typed value and the name of its type.
+#[derive(Clone, Debug)]
+pub(crate) struct TypedEntry {
+    pub(crate) type_name: &'static str,
+    pub(crate) value: Arc<dyn Any + Send + Sync>,
 }
 
 impl Context {
@@ -33,10 +43,15 @@ impl Context {
 
     /// Create a context from JSON data.
     pub fn from_json(data: HashMap<String, Value>) -> Self {
+        Self::from_parts(data, HashMap::new())
+    }
+
+    /// Create a context from JSON data and metadata.
+    pub fn from_parts(json_data: HashMap<String, Value>, metadata: HashMap<String, Value>) -> Self {
         Self {
             data: HashMap::new(),
-            json_data: data,
-            metadata: HashMap::new(),
+            json_data: Arc::new(json_data),
+            metadata: Arc::new(metadata),
         }
     }
 
@@ -47,7 +62,13 @@ impl Context {
     where
         T: Send + Sync + 'static,
     {
-        self.data.insert(TypeId::of::<T>(), Arc::new(value));
+        self.data.insert(
+            TypeId::of::<T>(),
+            TypedEntry {
+                type_name: std::any::type_name::<T>(),
+                value: Arc::new(value),
+            },
+        );
         Ok(())
     }
 
@@ -58,7 +79,7 @@ impl Context {
     {
         self.data
             .get(&TypeId::of::<T>())
-            .and_then(|data| data.downcast_ref::<T>())
+            .and_then(|entry| entry.value.downcast_ref::<T>())
     }
 
     /// Remove typed data from the context.
@@ -67,9 +88,9 @@ impl Context {
         T: Send + Sync + 'static,
     {
         let type_id = TypeId::of::<T>();
-        if let Some(data) = self.data.remove(&type_id) {
+        if let Some(entry) = self.data.remove(&type_id) {
             // Try to downcast the Arc<dyn Any + Send + Sync> to Arc<T>
-            match data.downcast::<T>() {
+            match entry.value.downcast::<T>() {
                 Ok(arc_t) => {
                     // Try to unwrap the Arc to get the owned value
                     Arc::try_unwrap(arc_t).ok()
@@ -92,7 +113,7 @@ impl Context {
     /// Set JSON data by key.
     pub fn set(&mut self, key: impl Into<String>, value: impl Serialize) -> Result<()> {
         let json_value = serde_json::to_value(value)?;
-        self.json_data.insert(key.into(), json_value);
+        Arc::make_mut(&mut self.json_data).insert(key.into(), json_value);
         Ok(())
     }
 
@@ -115,7 +136,10 @@ impl Context {
 
     /// Remove JSON data by key.
     pub fn remove_json(&mut self, key: &str) -> Option<Value> {
-        self.json_data.remove(key)
+        if !self.json_data.contains_key(key) {
+            return None;
+        }
+        Arc::make_mut(&mut self.json_data).remove(key)
     }
 
     /// Check if JSON data exists by key.
@@ -131,7 +155,7 @@ impl Context {
     /// Set metadata.
     pub fn set_metadata(&mut self, key: impl Into<String>, value: impl Serialize) -> Result<()> {
         let json_value = serde_json::to_value(value)?;
-        self.metadata.insert(key.into(), json_value);
+        Arc::make_mut(&mut self.metadata).insert(key.into(), json_value);
         Ok(())
     }
 
@@ -152,26 +176,44 @@ impl Context {
         self.metadata.get(key)
     }
 
+    /// Remove metadata by key.
+    pub fn remove_metadata(&mut self, key: &str) -> Option<Value> {
+        if !self.metadata.contains_key(key) {
+            return None;
+        }
+        Arc::make_mut(&mut self.metadata).remove(key)
+    }
+
     /// Merge another context into this one.
     ///
-    /// JSON data and metadata from the other context will override
-    /// existing values with the same keys.
+    /// JSON data, metadata and typed entries from the other context will
+    /// override existing values with the same keys. See
+    /// [`Context::merge_with`] for other strategies and conflict reporting.
     pub fn merge(&mut self, other: &Context) {
-        // Note: We can't merge typed data safely without knowing the types
-        // so we only merge JSON data and metadata
-        for (key, value) in &other.json_data {
-            self.json_data.insert(key.clone(), value.clone());
+        // Typed values are immutable behind their `Arc`, so sharing them is
+        // as good as a clone
+        for (type_id, entry) in &other.data {
+            self.data.insert(*type_id, entry.clone());
         }
-        for (key, value) in &other.metadata {
-            self.metadata.insert(key.clone(), value.clone());
+        if !other.json_data.is_empty() {
+            let json_data = Arc::make_mut(&mut self.json_data);
+            for (key, value) in other.json_data.iter() {
+                json_data.insert(key.clone(), value.clone());
+            }
+        }
+        if !other.metadata.is_empty() {
+            let metadata = Arc::make_mut(&mut self.metadata);
+            for (key, value) in other.metadata.iter() {
+                metadata.insert(key.clone(), value.clone());
+            }
         }
     }
 
     /// Clear all data from the context.
     pub fn clear(&mut self) {
         self.data.clear();
-        self.json_data.clear();
-        self.metadata.clear();
+        self.json_data = Arc::default();
+        self.metadata = Arc::default();
     }
 
     /// Get the number of items in the context.
@@ -186,7 +228,7 @@ impl Context {
 
     /// Convert the context to a JSON representation (JSON data only).
     pub fn to_json(&self) -> Result<Value> {
-        serde_json::to_value(&self.json_data).map_err(FlowError::from)
+        serde_json::to_value(self.json_data.as_ref()).map_err(FlowError::from)
     }
 
     /// Get all JSON data as a HashMap.
@@ -198,6 +240,204 @@ impl Context {
     pub fn metadata(&self) -> &HashMap<String, Value> {
         &self.metadata
     }
+
+    pub(crate) fn typed_entries(&self) -> impl Iterator<Item = (&TypeId, &TypedEntry)> {
+        self.data.iter()
+    }
+
+    pub(crate) fn typed_entry(&self, type_id: &TypeId) -> Option<&TypedEntry> {
+        self.data.get(type_id)
+    }
+
+    pub(crate) fn insert_entry(&mut self, type_id: TypeId, entry: TypedEntry) {
+        self.data.insert(type_id, entry);
+    }
+
+    /// Take a snapshot of the context to diff against or roll back to.
+    ///
+    /// Snapshots are cheap: JSON data and metadata are only copied once
+    /// either the context or the snapshot is modified.
+    pub fn snapshot(&self) -> ContextSnapshot {
+        ContextSnapshot {
+            context: self.clone(),
+        }
+    }
+
+    /// Restore the context to a snapshot, discarding every later change.
+    pub fn rollback(&mut self, snapshot: &ContextSnapshot) {
+        *self = snapshot.context.clone();
+    }
+
+    /// Compute the changes that turn this context into `other`.
+    pub fn diff(&self, other: &Context) -> ContextDiff {
+        ContextDiff::between(self, other)
+    }
+}
+
+/// A point-in-time copy of a [`Context`], see [`Context::snapshot`].
+#[derive(Clone, Debug)]
+pub struct ContextSnapshot {
+    context: Context,
+}
+
+impl ContextSnapshot {
+    /// The context as it was when the snapshot was taken.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Changes made to `context` since the snapshot was taken.
+    pub fn changes(&self, context: &Context) -> ContextDiff {
+        self.context.diff(context)
+    }
+
+    /// Turn the snapshot back into a context.
+    pub fn into_context(self) -> Context {
+        self.context
+    }
+}
+
+/// Serialized form of a [`Context`], with keys in sorted order.
+#[derive(Serialize)]
+struct ContextRef<'a> {
+    data: BTreeMap<&'a String, &'a Value>,
+    metadata: BTreeMap<&'a String, &'a Value>,
+}
+
+#[derive(Deserialize)]
+struct ContextRepr {
+    #[serde(default)]
+    data: HashMap<String, Value>,
+    #[serde(default)]
+    metadata: HashMap<String, Value>,
+}
+
+/// Serializes JSON data and metadata as `{"data": {..}, "metadata": {..}}`.
+/// Typed entries are not serialized.
+impl Serialize for Context {
+    fn serialize<Ser: Serializer>(
+        &self,
+        serializer: Ser,
+    ) -> std::result::Result<Ser::Ok, Ser::Error> {
+        ContextRef {
+            data: self.json_data.iter().collect(),
+            metadata: self.metadata.iter().collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Context {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let repr = ContextRepr::deserialize(deserializer)?;
+        Ok(Context::from_parts(repr.data, repr.metadata))
+    }
+}
+
+/// Changes between the JSON data and metadata of two contexts.
+///
+/// Typed entries are not compared. Diffs keep the previous value of changed
+/// and removed keys, so they can be inspected and serialized on their own.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ContextDiff {
+    #[serde(default, skip_serializing_if = "MapDiff::is_empty")]
+    pub data: MapDiff,
+    #[serde(default, skip_serializing_if = "MapDiff::is_empty")]
+    pub metadata: MapDiff,
+}
+
+impl ContextDiff {
+    /// Compute the changes that turn `before` into `after`.
+    pub fn between(before: &Context, after: &Context) -> Self {
+        Self {
+            data: MapDiff::between(&before.json_data, &after.json_data),
+            metadata: MapDiff::between(&before.metadata, &after.metadata),
+        }
+    }
+
+    /// Whether the two contexts had the same JSON data and metadata.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty() && self.metadata.is_empty()
+    }
+
+    /// Apply the changes to `context`.
+    pub fn apply(&self, context: &mut Context) {
+        if !self.data.is_empty() {
+            self.data.apply(Arc::make_mut(&mut context.json_data));
+        }
+        if !self.metadata.is_empty() {
+            self.metadata.apply(Arc::make_mut(&mut context.metadata));
+        }
+    }
+}
+
+/// Changes between two key-value maps.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MapDiff {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub added: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub changed: BTreeMap<String, ValueChange>,
+    /// Removed keys with their previous values.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub removed: BTreeMap<String, Value>,
+}
+
+/// Previous and new value of a changed key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValueChange {
+    pub before: Value,
+    pub after: Value,
+}
+
+impl MapDiff {
+    fn between(before: &Arc<HashMap<String, Value>>, after: &Arc<HashMap<String, Value>>) -> Self {
+        let mut diff = MapDiff::default();
+        // Contexts that still share their storage can't differ
+        if Arc::ptr_eq(before, after) {
+            return diff;
+        }
+        for (key, value) in after.iter() {
+            match before.get(key) {
+                None => {
+                    diff.added.insert(key.clone(), value.clone());
+                }
+                Some(previous) if previous != value => {
+                    diff.changed.insert(
+                        key.clone(),
+                        ValueChange {
+                            before: previous.clone(),
+                            after: value.clone(),
+                        },
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+        for (key, value) in before.iter() {
+            if !after.contains_key(key) {
+                diff.removed.insert(key.clone(), value.clone());
+            }
+        }
+        diff
+    }
+
+    /// Whether no key was added, changed or removed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    fn apply(&self, map: &mut HashMap<String, Value>) {
+        for (key, value) in &self.added {
+            map.insert(key.clone(), value.clone());
+        }
+        for (key, change) in &self.changed {
+            map.insert(key.clone(), change.after.clone());
+        }
+        for key in self.removed.keys() {
+            map.remove(key);
+        }
+    }
 }
 
 /// Builder for creating contexts with initial data.
@@ -245,6 +485,75 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_serialize_and_diff() {
+        let mut before = Context::new();
+        before.set("kept", 1).unwrap();
+        before.set("changed", "old").unwrap();
+        before.set("removed", true).unwrap();
+        before.insert(7u8).unwrap();
+
+        let mut after = before.clone();
+        after.set("changed", "new").unwrap();
+        after.set("added", [1, 2]).unwrap();
+        after.remove_json("removed");
+        after.set_metadata("source", "test").unwrap();
+
+        let diff = ContextDiff::between(&before, &after);
+        assert_eq!(diff.data.added.keys().collect::<Vec<_>>(), vec!["added"]);
+        assert_eq!(diff.data.changed["changed"].before, "old");
+        assert_eq!(diff.data.removed["removed"], true);
+        assert_eq!(diff.metadata.added["source"], "test");
+
+        let mut replayed = before.clone();
+        diff.apply(&mut replayed);
+        assert_eq!(replayed.json_data(), after.json_data());
+        assert_eq!(replayed.metadata(), after.metadata());
+
+        // Keys are written in sorted order and typed entries are skipped
+        let json = serde_json::to_string(&after).unwrap();
+        assert_eq!(
+            json,
+            r#"{"data":{"added":[1,2],"changed":"new","kept":1},"metadata":{"source":"test"}}"#
+        );
+        let restored: Context = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.json_data(), after.json_data());
+        assert!(!restored.contains::<u8>());
+    }
+
+    #[test]
+    fn test_snapshot_and_rollback() {
+        let mut context = Context::new();
+        context.set("count", 1).unwrap();
+
+        let snapshot = context.snapshot();
+        assert!(Arc::ptr_eq(
+            &context.json_data,
+            &snapshot.context().json_data
+        ));
+
+        context.set("count", 2).unwrap();
+        context.set_metadata("attempt", 1).unwrap();
+        // Writing copied the data; the snapshot is unaffected
+        assert_eq!(
+            snapshot.context().get_json::<i32>("count").unwrap(),
+            Some(1)
+        );
+
+        let diff = snapshot.changes(&context);
+        assert_eq!(diff.data.changed["count"].after, 2);
+        assert_eq!(diff.metadata.added["attempt"], 1);
+        assert_eq!(
+            context.diff(snapshot.context()).data.changed["count"].after,
+            1
+        );
+
+        context.rollback(&snapshot);
+        assert_eq!(context.get_json::<i32>("count").unwrap(), Some(1));
+        assert!(context.get_metadata_raw("attempt").is_none());
+        assert!(context.diff(snapshot.context()).is_empty());
+    }
+
     #[derive(Debug, PartialEq, Serialize, Deserialize)]
     struct TestData {
         value: i32,
@@ -2,6 +2,8 @@
 
 use thiserror::Error;
 
+use crate::input::PendingInput;
+
 /// Result type for flow operations.
 pub type Result<T> = std::result::Result<T, FlowError>;
 
@@ -17,8 +19,10 @@ pub enum FlowError {
     Construction(String),
 
     /// Invalid state transition.
-    #[error("Invalid transition from {from:?} to {to:?}")]
+    #[error("Invalid transition from {from} to {to} in node '{node}'")]
     InvalidTransition {
+        /// Node that requested the transition
+        node: String,
         /// Source state
         from: String,
         /// Target state
@@ -37,6 +41,10 @@ pub enum FlowError {
     #[error("Error: {0}")]
     Generic(#[from] eyre::Report),
 
+    /// Checkpoint or persistence backend error.
+    #[error("Storage error: {0}")]
+    Storage(String),
+
     /// Flow execution was cancelled.
     #[error("Flow execution was cancelled")]
     Cancelled,
@@ -44,6 +52,15 @@ pub enum FlowError {
     /// Flow execution timeout.
     #[error("Flow execution timed out")]
     Timeout,
+
+    /// A node suspended the run until input is submitted, see
+    /// [`WaitForInputNode`](crate::input::WaitForInputNode).
+    #[error("Node '{}' is waiting for input '{}'", .0.node, .0.key)]
+    Suspended(Box<PendingInput>),
+
+    /// Error returned by a node in a recorded run, reproduced during replay.
+    #[error("{0}")]
+    Replayed(String),
 }
 
 impl FlowError {
@@ -62,9 +79,24 @@ impl FlowError {
         Self::Context(msg.into())
     }
 
-    /// Create a new invalid transition error.
+    /// Create a new storage error.
+    pub fn storage(msg: impl std::fmt::Display) -> Self {
+        Self::Storage(msg.to_string())
+    }
+
+    /// Create a new invalid transition error for an unknown node.
     pub fn invalid_transition(from: impl std::fmt::Debug, to: impl std::fmt::Debug) -> Self {
+        Self::invalid_node_transition("unknown", from, to)
+    }
+
+    /// Create a new invalid transition error naming the node that requested it.
+    pub fn invalid_node_transition(
+        node: impl Into<String>,
+        from: impl std::fmt::Debug,
+        to: impl std::fmt::Debug,
+    ) -> Self {
         Self::InvalidTransition {
+            node: node.into(),
             from: format!("{from:?}"),
             to: format!("{to:?}"),
         }
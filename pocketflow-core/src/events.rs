@@ -0,0 +1,288 @@
+//! Live execution events for advanced flows.
+//!
+//! Events are delivered to [`FlowObserver`]s registered on the builder as
+//! they happen, or consumed as a stream from
+//! [`AdvancedFlow::execute_stream`](crate::flow_advanced::AdvancedFlow::execute_stream).
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::state::FlowState;
+
+/// Something that happened while a flow was running.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum FlowEvent<S> {
+    /// A run started or resumed.
+    FlowStarted {
+        run_id: String,
+        flow_name: String,
+        state: S,
+        timestamp: DateTime<Utc>,
+    },
+    /// A node is about to run.
+    NodeStarted {
+        run_id: String,
+        node_name: String,
+        state: S,
+        step: usize,
+        timestamp: DateTime<Utc>,
+    },
+    /// A node finished, successfully or not.
+    NodeFinished {
+        run_id: String,
+        node_name: String,
+        state: S,
+        /// State returned by the node, if it succeeded.
+        next_state: Option<S>,
+        step: usize,
+        duration: Duration,
+        error: Option<String>,
+        timestamp: DateTime<Utc>,
+    },
+    /// The run moved to a new state.
+    TransitionTaken {
+        run_id: String,
+        /// Node, router or middleware that chose the transition.
+        node_name: String,
+        from: S,
+        to: S,
+        step: usize,
+        timestamp: DateTime<Utc>,
+    },
+    /// A middleware hook failed the run.
+    MiddlewareRejected {
+        run_id: String,
+        middleware: String,
+        node_name: String,
+        state: S,
+        error: String,
+        timestamp: DateTime<Utc>,
+    },
+    /// A run finished, successfully or not.
+    FlowCompleted {
+        run_id: String,
+        flow_name: String,
+        state: S,
+        success: bool,
+        error: Option<String>,
+        steps: usize,
+        duration: Duration,
+        timestamp: DateTime<Utc>,
+    },
+}
+
+impl<S> FlowEvent<S> {
+    /// Id of the run this event belongs to.
+    pub fn run_id(&self) -> &str {
+        match self {
+            FlowEvent::FlowStarted { run_id, .. }
+            | FlowEvent::NodeStarted { run_id, .. }
+            | FlowEvent::NodeFinished { run_id, .. }
+            | FlowEvent::TransitionTaken { run_id, .. }
+            | FlowEvent::MiddlewareRejected { run_id, .. }
+            | FlowEvent::FlowCompleted { run_id, .. } => run_id,
+        }
+    }
+
+    /// When the event happened.
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        match self {
+            FlowEvent::FlowStarted { timestamp, .. }
+            | FlowEvent::NodeStarted { timestamp, .. }
+            | FlowEvent::NodeFinished { timestamp, .. }
+            | FlowEvent::TransitionTaken { timestamp, .. }
+            | FlowEvent::MiddlewareRejected { timestamp, .. }
+            | FlowEvent::FlowCompleted { timestamp, .. } => *timestamp,
+        }
+    }
+}
+
+/// Receives flow events as they happen.
+///
+/// Observers are called inline by the executor, so they should return
+/// quickly and hand expensive work off to another task.
+pub trait FlowObserver<S: FlowState>: Send + Sync {
+    /// Handle a single event.
+    fn on_event(&self, event: &FlowEvent<S>);
+}
+
+impl<S, F> FlowObserver<S> for F
+where
+    S: FlowState,
+    F: Fn(&FlowEvent<S>) + Send + Sync,
+{
+    fn on_event(&self, event: &FlowEvent<S>) {
+        self(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::{
+        checkpoint::{Checkpoint, CheckpointStore},
+        context::Context,
+        error::{FlowError, Result},
+        flow_advanced::AdvancedFlow,
+        node::helpers,
+        state::SimpleState,
+    };
+
+    fn event_name<S>(event: &FlowEvent<S>) -> &'static str {
+        match event {
+            FlowEvent::FlowStarted { .. } => "flow_started",
+            FlowEvent::NodeStarted { .. } => "node_started",
+            FlowEvent::NodeFinished { .. } => "node_finished",
+            FlowEvent::TransitionTaken { .. } => "transition_taken",
+            FlowEvent::MiddlewareRejected { .. } => "middleware_rejected",
+            FlowEvent::FlowCompleted { .. } => "flow_completed",
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_yields_events_in_order() {
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::passthrough("step", SimpleState::Success),
+            )
+            .build()
+            .unwrap();
+
+        let events: Vec<_> = flow.execute_stream(Context::new()).collect().await;
+        let names: Vec<_> = events.iter().map(event_name).collect();
+        assert_eq!(
+            names,
+            vec![
+                "flow_started",
+                "node_started",
+                "node_finished",
+                "transition_taken",
+                "flow_completed"
+            ]
+        );
+
+        let run_id = events[0].run_id();
+        assert!(events.iter().all(|event| event.run_id() == run_id));
+        match &events[3] {
+            FlowEvent::TransitionTaken {
+                node_name,
+                from,
+                to,
+                ..
+            } => {
+                assert_eq!(node_name, "step");
+                assert_eq!(from, &SimpleState::Start);
+                assert_eq!(to, &SimpleState::Success);
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+        assert!(matches!(
+            events[4],
+            FlowEvent::FlowCompleted { success: true, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn observers_see_middleware_rejections() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::passthrough("step", SimpleState::Success),
+            )
+            .middleware(|_context, _state| Err(FlowError::context("not allowed")))
+            .observer(move |event: &FlowEvent<SimpleState>| {
+                sink.lock().unwrap().push(event.clone());
+            })
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+
+        let events = seen.lock().unwrap();
+        let names: Vec<_> = events.iter().map(event_name).collect();
+        assert_eq!(
+            names,
+            vec!["flow_started", "middleware_rejected", "flow_completed"]
+        );
+        match &events[1] {
+            FlowEvent::MiddlewareRejected {
+                middleware,
+                node_name,
+                ..
+            } => {
+                assert_eq!(middleware, "FnMiddleware");
+                assert_eq!(node_name, "step");
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+
+    struct FullDisk;
+
+    #[async_trait::async_trait]
+    impl CheckpointStore for FullDisk {
+        async fn save(&self, _checkpoint: &Checkpoint) -> Result<()> {
+            Err(FlowError::storage("disk full"))
+        }
+
+        async fn load(&self, _run_id: &str) -> Result<Option<Checkpoint>> {
+            Ok(None)
+        }
+
+        async fn delete(&self, _run_id: &str) -> Result<()> {
+            Ok(())
+        }
+
+        async fn list_runs(&self) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn completion_reports_where_an_erroring_run_stopped() {
+        let ship = SimpleState::Custom("ship".to_string());
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::compensated(helpers::passthrough("reserve", ship.clone()), |_| async {
+                    Ok(())
+                }),
+            )
+            .checkpoint_store(Arc::new(FullDisk))
+            .build()
+            .unwrap();
+
+        // Neither the step nor its compensation can be checkpointed
+        let events: Vec<_> = flow.execute_stream(Context::new()).collect().await;
+        match events.last() {
+            Some(FlowEvent::FlowCompleted {
+                state,
+                success,
+                error,
+                steps,
+                ..
+            }) => {
+                assert_eq!(state, &ship);
+                assert!(!success);
+                assert!(error.as_deref().unwrap().contains("disk full"));
+                assert_eq!(*steps, 1);
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+}
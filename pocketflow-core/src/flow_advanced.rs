@@ -6,17 +6,35 @@ use std::{
     time::{Duration, Instant},
 };
 
-use tokio::sync::RwLock;
+use futures::{Stream, StreamExt, stream};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
+pub use crate::{analytics::FlowAnalytics, shared::SharedFlowState};
 use crate::{
-    context::Context,
+    checkpoint::{Checkpoint, CheckpointStore, Checkpointer},
+    context::{Context, ContextDiff, ContextSnapshot},
     error::{FlowError, Result},
+    events::{FlowEvent, FlowObserver},
+    graph::{EdgeKind, FlowGraph, GraphReport},
+    input::{PENDING_INPUT, PendingInput, SUBMITTED_INPUT},
+    metrics,
+    middleware::{
+        FlowMiddleware, FnMiddleware, LoggingMiddleware, MiddlewareAction, NodeInfo,
+        TimingMiddleware,
+    },
     node::Node,
-    state::FlowState,
+    persist::TypeRegistry,
+    policy::{Fallback, NodePolicy},
+    replay::ReplayCursor,
+    shared::SharedRun,
+    spans::Span,
+    state::{FlowState, TransitionRules, TransitionTable},
 };
 
 /// Advanced flow execution result with enhanced metadata.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AdvancedFlowResult<S: FlowState> {
     /// Final execution state.
     pub final_state: S,
@@ -31,13 +49,34 @@ pub struct AdvancedFlowResult<S: FlowState> {
     /// Any error that occurred.
     pub error: Option<String>,
     /// Execution metadata.
+    #[serde(serialize_with = "sorted_metadata")]
     pub metadata: HashMap<String, String>,
     /// Step-by-step execution trace.
     pub trace: Vec<ExecutionStep<S>>,
 }
 
+/// Serialize run metadata in key order so results diff cleanly.
+fn sorted_metadata<Ser: serde::Serializer>(
+    metadata: &HashMap<String, String>,
+    serializer: Ser,
+) -> std::result::Result<Ser::Ok, Ser::Error> {
+    let sorted: std::collections::BTreeMap<_, _> = metadata.iter().collect();
+    sorted.serialize(serializer)
+}
+
+impl<S: FlowState> AdvancedFlowResult<S> {
+    /// The input the run is waiting for, if a
+    /// [`WaitForInputNode`](crate::input::WaitForInputNode) suspended it.
+    pub fn pending_input(&self) -> Option<PendingInput> {
+        if self.success {
+            return None;
+        }
+        self.context.fetch(&PENDING_INPUT).ok().flatten()
+    }
+}
+
 /// Individual execution step information.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExecutionStep<S: FlowState> {
     pub step_number: usize,
     pub from_state: S,
@@ -45,9 +84,155 @@ pub struct ExecutionStep<S: FlowState> {
     pub node_name: String,
     pub duration: Duration,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// What produced this step.
+    #[serde(default)]
+    pub kind: StepKind,
+    /// Error reported by the step, if it failed.
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Attempt number of the node execution, starting at 1.
+    #[serde(default = "first_attempt")]
+    pub attempt: u32,
+    /// Trace of a sub-flow run by this step's node.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<NestedStep>,
+    /// Context changes made by the node, when context recording is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_diff: Option<ContextDiff>,
+}
+
+impl<S: FlowState> ExecutionStep<S> {
+    fn new(
+        step_number: usize,
+        from_state: S,
+        to_state: S,
+        node_name: impl Into<String>,
+        duration: Duration,
+    ) -> Self {
+        Self {
+            step_number,
+            from_state,
+            to_state,
+            node_name: node_name.into(),
+            duration,
+            timestamp: chrono::Utc::now(),
+            kind: StepKind::Node,
+            error: None,
+            attempt: 1,
+            children: Vec::new(),
+            context_diff: None,
+        }
+    }
+
+    fn with_kind(mut self, kind: StepKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    fn with_error(mut self, error: impl ToString) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+
+    fn with_attempt(mut self, attempt: u32) -> Self {
+        self.attempt = attempt;
+        self
+    }
+
+    fn with_children(mut self, children: Vec<NestedStep>) -> Self {
+        self.children = children;
+        self
+    }
+
+    fn with_context_diff(mut self, diff: Option<ContextDiff>) -> Self {
+        self.context_diff = diff;
+        self
+    }
+}
+
+fn first_attempt() -> u32 {
+    1
+}
+
+/// Execution step of a nested flow, with states rendered as strings.
+///
+/// Sub-flows may use a different state type than their parent, so their
+/// trace is stored in this state-erased form.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NestedStep {
+    pub step_number: usize,
+    pub from_state: String,
+    pub to_state: String,
+    pub node_name: String,
+    pub duration: Duration,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub kind: StepKind,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default = "first_attempt")]
+    pub attempt: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<NestedStep>,
 }
 
-/// Middleware function type.
+impl<S: FlowState> From<&ExecutionStep<S>> for NestedStep {
+    fn from(step: &ExecutionStep<S>) -> Self {
+        Self {
+            step_number: step.step_number,
+            from_state: format!("{:?}", step.from_state),
+            to_state: format!("{:?}", step.to_state),
+            node_name: step.node_name.clone(),
+            duration: step.duration,
+            timestamp: step.timestamp,
+            kind: step.kind,
+            error: step.error.clone(),
+            attempt: step.attempt,
+            children: step.children.clone(),
+        }
+    }
+}
+
+/// Sub-flow trace handed from a node to the executor through the context.
+pub(crate) struct ChildTrace(pub(crate) Vec<NestedStep>);
+
+/// Kind of work recorded by an [`ExecutionStep`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepKind {
+    /// A node's `execute` call.
+    #[default]
+    Node,
+    /// A conditional route declared with `when_state`.
+    Router,
+    /// A node's `prepare` hook.
+    Prepare,
+    /// A node's `cleanup` hook.
+    Cleanup,
+    /// A fallback taken after a node exhausted its retries.
+    Fallback,
+    /// A redirect requested by middleware.
+    Middleware,
+    /// A node's `compensate` hook, run after the flow failed.
+    Compensation,
+}
+
+impl std::fmt::Display for StepKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            StepKind::Node => "node",
+            StepKind::Router => "router",
+            StepKind::Prepare => "prepare",
+            StepKind::Cleanup => "cleanup",
+            StepKind::Fallback => "fallback",
+            StepKind::Middleware => "middleware",
+            StepKind::Compensation => "compensation",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Synchronous middleware function type, see [`FnMiddleware`].
 pub type Middleware<S> = Arc<dyn Fn(&Context, &S) -> Result<()> + Send + Sync>;
 
 /// Conditional function type.
@@ -58,141 +243,1198 @@ pub struct AdvancedFlow<S: FlowState> {
     nodes: HashMap<S, Arc<dyn Node<State = S>>>,
     initial_state: S,
     name: String,
-    middleware: Vec<Middleware<S>>,
+    middleware: Vec<Arc<dyn FlowMiddleware<S>>>,
+    observers: Vec<Arc<dyn FlowObserver<S>>>,
     conditions: HashMap<S, (Condition<S>, S, S)>, // state -> (condition, true_state, false_state)
+    policies: HashMap<S, NodePolicy<S>>,
     max_steps: usize,
+    checkpointer: Option<Checkpointer<S>>,
+    transitions: TransitionRules<S>,
+    record_context: bool,
+    transactional: bool,
+}
+
+/// How the steps of a run stopped.
+enum RunExit {
+    /// The run reached a terminal state.
+    Completed,
+    /// The run failed with the given error.
+    Failed(String),
+    /// The run was cancelled.
+    Cancelled,
+    /// A node is waiting for input.
+    Suspended(PendingInput),
+}
+
+/// Mutable bookkeeping for a single flow run.
+struct RunState<S: FlowState> {
+    run_id: String,
+    current_state: S,
+    context: Context,
+    steps: usize,
+    trace: Vec<ExecutionStep<S>>,
+    metadata: HashMap<String, String>,
+    start_time: Instant,
+    cancel: CancellationToken,
+    events: Option<mpsc::UnboundedSender<FlowEvent<S>>>,
+    /// Record the context diff of every node step.
+    record: bool,
+    /// Recorded node outputs to return instead of calling nodes.
+    replay: Option<Arc<ReplayCursor<S>>>,
+    /// Context before the current step, restored if the step fails.
+    rollback: Option<ContextSnapshot>,
+    /// Shared state the run publishes its steps to.
+    shared: Option<SharedRun<S>>,
+}
+
+impl<S: FlowState> RunState<S> {
+    fn finish(mut self, success: bool, error: Option<String>) -> AdvancedFlowResult<S> {
+        if !success {
+            self.roll_back();
+        }
+        if self.shared.is_some() {
+            self.context.remove::<SharedFlowState<S>>();
+        }
+        AdvancedFlowResult {
+            final_state: self.current_state,
+            context: self.context,
+            duration: self.start_time.elapsed(),
+            steps: self.steps,
+            success,
+            error,
+            metadata: self.metadata,
+            trace: self.trace,
+        }
+    }
+
+    /// Restore the context from before the current step, if it was
+    /// snapshotted.
+    fn roll_back(&mut self) {
+        if let Some(snapshot) = self.rollback.take() {
+            self.context.rollback(&snapshot);
+            self.metadata
+                .insert("rolled_back_step".to_string(), self.steps.to_string());
+        }
+    }
+
+    /// The node to run for the current state, replaced by its recorded
+    /// outputs when replaying.
+    fn node(&self, node: &Arc<dyn Node<State = S>>) -> Arc<dyn Node<State = S>> {
+        match &self.replay {
+            Some(cursor) => cursor.node(node, &self.current_state),
+            None => node.clone(),
+        }
+    }
+
+    /// Drive `future` unless the run is cancelled first.
+    async fn until_cancelled<T>(&self, future: impl Future<Output = Result<T>>) -> Result<T> {
+        tokio::select! {
+            biased;
+            () = self.cancel.cancelled() => Err(FlowError::Cancelled),
+            result = future => result,
+        }
+    }
 }
 
 impl<S: FlowState> AdvancedFlow<S> {
     /// Execute the workflow with middleware support.
-    pub async fn execute(&self, mut context: Context) -> Result<AdvancedFlowResult<S>> {
-        let start_time = Instant::now();
-        let mut current_state = self.initial_state.clone();
-        let mut steps = 0;
-        let mut trace = Vec::new();
-        let mut metadata = HashMap::new();
+    pub async fn execute(&self, context: Context) -> Result<AdvancedFlowResult<S>> {
+        self.execute_with_run_id(uuid::Uuid::new_v4().to_string(), context)
+            .await
+    }
+
+    /// Execute the workflow under a caller-chosen run id.
+    ///
+    /// The run id is used as the checkpoint key, so a run started this way can
+    /// later be continued with [`AdvancedFlow::resume`].
+    pub async fn execute_with_run_id(
+        &self,
+        run_id: impl Into<String>,
+        context: Context,
+    ) -> Result<AdvancedFlowResult<S>> {
+        let run = self.run_state(run_id.into(), context, CancellationToken::new(), None);
+        self.run(run).await
+    }
+
+    /// Execute the workflow until it finishes or `cancel` is triggered.
+    ///
+    /// Cancellation is checked between steps and also aborts the node that is
+    /// currently running. The aborted node's cleanup hook still runs, and the
+    /// returned result carries the partial context and trace with
+    /// [`FlowError::Cancelled`] as its error.
+    pub async fn execute_with_cancel(
+        &self,
+        context: Context,
+        cancel: CancellationToken,
+    ) -> Result<AdvancedFlowResult<S>> {
+        self.execute_run(uuid::Uuid::new_v4().to_string(), context, cancel)
+            .await
+    }
+
+    /// Execute the workflow under a run id chosen by the caller.
+    pub(crate) async fn execute_run(
+        &self,
+        run_id: String,
+        context: Context,
+        cancel: CancellationToken,
+    ) -> Result<AdvancedFlowResult<S>> {
+        let run = self.run_state(run_id, context, cancel, None);
+        self.run(run).await
+    }
+
+    /// Execute the workflow as one of several participants on a shared state.
+    ///
+    /// The run starts from the shared state and context and publishes each
+    /// step back: the state moves with a compare-and-swap from the state the
+    /// step started in and the step's context changes are applied key by key.
+    /// A step that loses the race to another participant is discarded (and
+    /// counted in the `shared_conflicts` metadata) and the run continues from
+    /// the new shared state. While the shared state is one this flow has no
+    /// node or router for, the run waits for another participant to move it.
+    ///
+    /// Nodes reach the shared state through [`SharedFlowState::from_context`],
+    /// for example to wait for a key another participant produces; the handle
+    /// is left out of checkpoints and the result context. A failed
+    /// run leaves the shared state where it was, so cancel any participants
+    /// still waiting on it.
+    pub async fn execute_shared(
+        &self,
+        shared: &SharedFlowState<S>,
+        cancel: CancellationToken,
+    ) -> Result<AdvancedFlowResult<S>> {
+        let run_id = uuid::Uuid::new_v4().to_string();
+        let mut run = self.run_state(run_id, Context::new(), cancel, None);
+        run.current_state = shared.get_state().await;
+        run.shared = Some(SharedRun::new(shared.clone()));
+        self.run(run).await
+    }
 
+    /// Execute the workflow, yielding its events as they happen.
+    ///
+    /// The run makes progress while the stream is polled and the stream ends
+    /// after the final [`FlowEvent::FlowCompleted`] event.
+    pub fn execute_stream(&self, context: Context) -> impl Stream<Item = FlowEvent<S>> + Send + '_ {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let run_id = uuid::Uuid::new_v4().to_string();
+
+        // The run owns the only sender, so the receiver closes once it is done
+        let run = self.run_state(run_id, context, CancellationToken::new(), Some(sender));
+        let driver = stream::once(self.run(run)).filter_map(|_| async { None });
+        let events = stream::poll_fn(move |cx| receiver.poll_recv(cx));
+
+        stream::select(driver, events)
+    }
+
+    /// Execute with context recording, replaying recorded outputs if a
+    /// cursor is given.
+    pub(crate) async fn execute_recorded(
+        &self,
+        run_id: String,
+        context: Context,
+        replay: Option<Arc<ReplayCursor<S>>>,
+    ) -> Result<AdvancedFlowResult<S>> {
+        let mut run = self.run_state(run_id, context, CancellationToken::new(), None);
+        run.record = true;
+        if let Some(cursor) = replay {
+            run.metadata
+                .insert("replayed_at".to_string(), chrono::Utc::now().to_rfc3339());
+            run.replay = Some(cursor);
+        }
+        self.run(run).await
+    }
+
+    fn run_state(
+        &self,
+        run_id: String,
+        context: Context,
+        cancel: CancellationToken,
+        events: Option<mpsc::UnboundedSender<FlowEvent<S>>>,
+    ) -> RunState<S> {
+        let mut metadata = HashMap::new();
         metadata.insert("flow_name".to_string(), self.name.clone());
+        metadata.insert("run_id".to_string(), run_id.clone());
         metadata.insert("started_at".to_string(), chrono::Utc::now().to_rfc3339());
 
+        RunState {
+            run_id,
+            current_state: self.initial_state.clone(),
+            context,
+            steps: 0,
+            trace: Vec::new(),
+            metadata,
+            start_time: Instant::now(),
+            cancel,
+            events,
+            record: self.record_context,
+            replay: None,
+            rollback: None,
+            shared: None,
+        }
+    }
+
+    /// Resume a run from its last committed checkpoint.
+    ///
+    /// Requires a checkpoint store to be configured on the builder. The node
+    /// that was running when the previous attempt stopped is executed again.
+    /// Runs that already finished cannot be resumed.
+    pub async fn resume(&self, run_id: &str) -> Result<AdvancedFlowResult<S>> {
+        let (checkpointer, checkpoint) = self.load_checkpoint(run_id).await?;
+        self.resume_from(checkpointer, &checkpoint).await
+    }
+
+    /// The input a suspended run is waiting for.
+    pub async fn pending_input(&self, run_id: &str) -> Result<Option<PendingInput>> {
+        let (_, checkpoint) = self.load_checkpoint(run_id).await?;
+        checkpoint.pending_input()
+    }
+
+    /// Answer the input a suspended run is waiting for and resume the run.
+    ///
+    /// The answer is checkpointed before the run resumes, so it is not lost
+    /// if the process stops before the node picks it up.
+    pub async fn submit_input(
+        &self,
+        run_id: &str,
+        value: serde_json::Value,
+    ) -> Result<AdvancedFlowResult<S>> {
+        let (checkpointer, mut checkpoint) = self.load_checkpoint(run_id).await?;
+        if checkpoint.pending_input()?.is_none() {
+            return Err(FlowError::context(format!(
+                "Run '{run_id}' is not waiting for input"
+            )));
+        }
+
+        checkpoint
+            .json_data
+            .insert(SUBMITTED_INPUT.name().to_string(), value);
+        checkpointer.store.save(&checkpoint).await?;
+        self.resume_from(checkpointer, &checkpoint).await
+    }
+
+    /// Resume every suspended run of this flow whose input request is past
+    /// its deadline, moving it to the waiting node's timeout state.
+    pub async fn expire_pending_inputs(&self) -> Result<Vec<AdvancedFlowResult<S>>> {
+        let checkpointer = self.checkpointer()?;
+
+        let mut results = Vec::new();
+        for run_id in checkpointer.store.list_runs().await? {
+            let Some(checkpoint) = checkpointer.store.load(&run_id).await? else {
+                continue;
+            };
+            if checkpoint.flow_name != self.name || checkpoint.completed {
+                continue;
+            }
+            if checkpoint
+                .pending_input()?
+                .is_some_and(|pending| pending.is_expired())
+            {
+                results.push(self.resume_from(checkpointer, &checkpoint).await?);
+            }
+        }
+        Ok(results)
+    }
+
+    fn checkpointer(&self) -> Result<&Checkpointer<S>> {
+        self.checkpointer.as_ref().ok_or_else(|| {
+            FlowError::construction(format!("Flow '{}' has no checkpoint store", self.name))
+        })
+    }
+
+    /// Load the checkpoint of one of this flow's runs.
+    async fn load_checkpoint(&self, run_id: &str) -> Result<(&Checkpointer<S>, Checkpoint)> {
+        let checkpointer = self.checkpointer()?;
+
+        let checkpoint =
+            checkpointer.store.load(run_id).await?.ok_or_else(|| {
+                FlowError::storage(format!("No checkpoint found for run '{run_id}'"))
+            })?;
+
+        if checkpoint.flow_name != self.name {
+            return Err(FlowError::storage(format!(
+                "Run '{run_id}' belongs to flow '{}', not '{}'",
+                checkpoint.flow_name, self.name
+            )));
+        }
+        Ok((checkpointer, checkpoint))
+    }
+
+    async fn resume_from(
+        &self,
+        checkpointer: &Checkpointer<S>,
+        checkpoint: &Checkpoint,
+    ) -> Result<AdvancedFlowResult<S>> {
+        if checkpoint.run_metadata.contains_key("compensated_at") {
+            return Err(FlowError::context(format!(
+                "Run '{}' failed and was compensated, so it cannot be resumed",
+                checkpoint.run_id
+            )));
+        }
+        if checkpoint.completed {
+            return Err(FlowError::context(format!(
+                "Run '{}' already finished, so it cannot be resumed",
+                checkpoint.run_id
+            )));
+        }
+
+        let mut metadata = checkpoint.run_metadata.clone();
+        metadata.insert("resumed_at".to_string(), chrono::Utc::now().to_rfc3339());
+
+        self.run(RunState {
+            run_id: checkpoint.run_id.clone(),
+            current_state: checkpointer.decode_state(checkpoint)?,
+            context: checkpointer.decode_context(checkpoint)?,
+            steps: checkpoint.steps,
+            trace: checkpointer.decode_trace(checkpoint)?,
+            metadata,
+            start_time: Instant::now(),
+            cancel: CancellationToken::new(),
+            events: None,
+            record: self.record_context,
+            replay: None,
+            rollback: None,
+            shared: None,
+        })
+        .await
+    }
+
+    /// Persist the current run position if a checkpoint store is configured.
+    ///
+    /// Replays are not checkpointed, as they reuse the recorded run's id.
+    async fn commit(&self, run: &RunState<S>, completed: bool) -> Result<()> {
+        let Some(checkpointer) = &self.checkpointer else {
+            return Ok(());
+        };
+        if run.replay.is_some() {
+            return Ok(());
+        }
+
+        // The shared state handle is only meant for the run's nodes
+        let mut unshared;
+        let context = if run.shared.is_some() {
+            unshared = run.context.clone();
+            unshared.remove::<SharedFlowState<S>>();
+            &unshared
+        } else {
+            &run.context
+        };
+
+        let checkpoint = checkpointer.checkpoint(
+            &run.run_id,
+            &self.name,
+            run.steps,
+            &run.current_state,
+            context,
+            &run.trace,
+            &run.metadata,
+            completed,
+        )?;
+        checkpointer.store.save(&checkpoint).await
+    }
+
+    /// Drive a run and report its start and completion to observers.
+    async fn run(&self, mut run: RunState<S>) -> Result<AdvancedFlowResult<S>> {
+        let run_id = run.run_id.clone();
+        let events = run.events.clone();
+        let started = Instant::now();
+
+        self.emit(
+            &events,
+            FlowEvent::FlowStarted {
+                run_id: run_id.clone(),
+                flow_name: self.name.clone(),
+                state: run.current_state.clone(),
+                timestamp: chrono::Utc::now(),
+            },
+        );
+
+        let span = Span::flow(&self.name, &run_id, &run.current_state);
+        metrics::flow_started(&self.name);
+        let exit = span.instrument(self.drive(&mut run)).await;
+        let (state, steps) = (run.current_state.clone(), run.steps);
+        let result = span.instrument(self.finish_run(run, exit)).await;
+
+        let (state, success, error, steps) = match &result {
+            Ok(result) => (
+                result.final_state.clone(),
+                result.success,
+                result.error.clone(),
+                result.steps,
+            ),
+            Err(error) => (state, false, Some(error.to_string()), steps),
+        };
+        metrics::flow_finished(&self.name, Some(&state), success, started.elapsed());
+        span.record("final_state", &state);
+        span.record("steps", steps);
+        if let Some(error) = &error {
+            span.record_error(error);
+        }
+        self.emit(
+            &events,
+            FlowEvent::FlowCompleted {
+                run_id,
+                flow_name: self.name.clone(),
+                state,
+                success,
+                error,
+                steps,
+                duration: started.elapsed(),
+                timestamp: chrono::Utc::now(),
+            },
+        );
+        result
+    }
+
+    /// Deliver an event to the registered observers and the run's stream.
+    fn emit(&self, events: &Option<mpsc::UnboundedSender<FlowEvent<S>>>, event: FlowEvent<S>) {
+        for observer in &self.observers {
+            observer.on_event(&event);
+        }
+        if let Some(sender) = events {
+            // The stream may have been dropped; the run carries on regardless
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Move the run to `to`, reporting the transition to observers.
+    fn transition(&self, run: &mut RunState<S>, node_name: &str, to: S) {
+        let from = std::mem::replace(&mut run.current_state, to);
+        self.emit(
+            &run.events,
+            FlowEvent::TransitionTaken {
+                run_id: run.run_id.clone(),
+                node_name: node_name.to_string(),
+                from,
+                to: run.current_state.clone(),
+                step: run.steps,
+                timestamp: chrono::Utc::now(),
+            },
+        );
+    }
+
+    /// Report a node's outcome to observers.
+    fn node_finished(
+        &self,
+        run: &RunState<S>,
+        node_name: &str,
+        outcome: std::result::Result<&S, &FlowError>,
+        started: Instant,
+        span: &Span,
+    ) {
+        match outcome {
+            Ok(state) => span.record("to", state),
+            Err(error) => span.record_error(error),
+        }
+        metrics::node_finished(
+            &self.name,
+            node_name,
+            &run.current_state,
+            outcome.is_ok(),
+            started.elapsed(),
+        );
+        self.emit(
+            &run.events,
+            FlowEvent::NodeFinished {
+                run_id: run.run_id.clone(),
+                node_name: node_name.to_string(),
+                state: run.current_state.clone(),
+                next_state: outcome.ok().cloned(),
+                step: run.steps,
+                duration: started.elapsed(),
+                error: outcome.err().map(|error| error.to_string()),
+                timestamp: chrono::Utc::now(),
+            },
+        );
+    }
+
+    /// Run steps until the run completes, fails, is cancelled or suspends.
+    ///
+    /// An `Err` fails the run like [`RunExit::Failed`].
+    async fn drive(&self, run: &mut RunState<S>) -> Result<RunExit> {
         loop {
-            steps += 1;
+            run.rollback = None;
+            if run.shared.is_some() {
+                self.sync_shared(run).await?;
+            }
 
-            // Prevent infinite loops
-            if steps > self.max_steps {
-                return Ok(AdvancedFlowResult {
-                    final_state: current_state,
-                    context,
-                    duration: start_time.elapsed(),
-                    steps,
-                    success: false,
-                    error: Some(format!("Flow exceeded maximum steps ({})", self.max_steps)),
-                    metadata,
-                    trace,
-                });
+            // Stop between steps once cancelled
+            if run.cancel.is_cancelled() && !run.current_state.is_terminal() {
+                return Ok(RunExit::Cancelled);
             }
 
-            // Check if we've reached a terminal state
-            if current_state.is_terminal() {
-                metadata.insert("completed_at".to_string(), chrono::Utc::now().to_rfc3339());
-                return Ok(AdvancedFlowResult {
-                    final_state: current_state,
-                    context,
-                    duration: start_time.elapsed(),
-                    steps,
-                    success: true,
-                    error: None,
-                    metadata,
-                    trace,
-                });
+            run.steps += 1;
+
+            // Prevent infinite loops
+            if run.steps > self.max_steps {
+                let error = format!("Flow exceeded maximum steps ({})", self.max_steps);
+                return Ok(RunExit::Failed(error));
             }
 
-            // Run pre-execution middleware
-            for middleware in &self.middleware {
-                if let Err(e) = middleware(&context, &current_state) {
-                    return Ok(AdvancedFlowResult {
-                        final_state: current_state,
-                        context,
-                        duration: start_time.elapsed(),
-                        steps,
-                        success: false,
-                        error: Some(format!("Middleware error: {e}")),
-                        metadata,
-                        trace,
-                    });
-                }
+            // Check if we've reached a terminal state
+            if run.current_state.is_terminal() {
+                return Ok(RunExit::Completed);
             }
 
             let step_start = Instant::now();
-            let from_state = current_state.clone();
+            let from_state = run.current_state.clone();
 
             // Check for conditional routing
-            if let Some((condition, true_state, false_state)) = self.conditions.get(&current_state)
+            if let Some((condition, true_state, false_state)) =
+                self.conditions.get(&run.current_state)
             {
-                let next_state = if condition(&context, &current_state) {
+                let next_state = if condition(&run.context, &run.current_state) {
                     true_state.clone()
                 } else {
                     false_state.clone()
                 };
+                if let Err(error) =
+                    self.transitions
+                        .check("conditional_router", &from_state, &next_state)
+                {
+                    return Ok(RunExit::Failed(error.to_string()));
+                }
 
-                let step = ExecutionStep {
-                    step_number: steps,
-                    from_state: from_state.clone(),
-                    to_state: next_state.clone(),
-                    node_name: "conditional_router".to_string(),
-                    duration: step_start.elapsed(),
-                    timestamp: chrono::Utc::now(),
-                };
-                trace.push(step);
-
-                current_state = next_state;
+                let step = ExecutionStep::new(
+                    run.steps,
+                    from_state.clone(),
+                    next_state.clone(),
+                    "conditional_router",
+                    step_start.elapsed(),
+                )
+                .with_kind(StepKind::Router);
+                run.trace.push(step);
+
+                self.transition(run, "conditional_router", next_state);
+                self.commit(run, false).await?;
                 continue;
             }
 
             // Find the node for the current state
-            let node = self.nodes.get(&current_state).ok_or_else(|| {
-                FlowError::execution(format!("No node found for state: {current_state:?}"))
+            let node = self.nodes.get(&run.current_state).ok_or_else(|| {
+                FlowError::execution(format!("No node found for state: {:?}", run.current_state))
             })?;
+            let node = run.node(node);
+
+            let node_name = node.name();
+            if self.transactional {
+                run.rollback = Some(run.context.snapshot());
+            }
+
+            // Run before-node middleware; a redirect skips the node
+            match self.before_middleware(run, &node_name).await {
+                Ok(None) => {}
+                Ok(Some((middleware, state))) => {
+                    self.redirect(run, middleware, state, step_start).await?;
+                    continue;
+                }
+                Err(error) => {
+                    let error = format!("Middleware error: {error}");
+                    return Ok(RunExit::Failed(error));
+                }
+            }
+
+            let span = Span::node(&node_name, &from_state, run.steps);
+            metrics::node_started(&self.name, &node_name, &from_state);
+            self.emit(
+                &run.events,
+                FlowEvent::NodeStarted {
+                    run_id: run.run_id.clone(),
+                    node_name: node_name.clone(),
+                    state: from_state.clone(),
+                    step: run.steps,
+                    timestamp: chrono::Utc::now(),
+                },
+            );
+
+            // Prepare the node; cleanup still runs if preparation fails
+            if let Err(error) = span.instrument(node.prepare(&run.context)).await {
+                self.node_finished(run, &node_name, Err(&error), step_start, &span);
+                let step = ExecutionStep::new(
+                    run.steps,
+                    from_state.clone(),
+                    from_state.clone(),
+                    node_name.clone(),
+                    step_start.elapsed(),
+                )
+                .with_kind(StepKind::Prepare)
+                .with_error(&error);
+                run.trace.push(step);
+
+                self.cleanup_after_error(node.as_ref(), run).await;
+                let error = format!("prepare hook failed for node '{node_name}': {error}");
+                return Ok(RunExit::Failed(error));
+            }
 
-            // Execute the node
-            match node.execute(context.clone()).await {
-                Ok((new_context, new_state)) => {
-                    let step = ExecutionStep {
-                        step_number: steps,
-                        from_state: from_state.clone(),
-                        to_state: new_state.clone(),
-                        node_name: node.name(),
-                        duration: step_start.elapsed(),
-                        timestamp: chrono::Utc::now(),
-                    };
-                    trace.push(step);
-
-                    context = new_context;
-                    current_state = new_state;
+            // Execute the node, applying its policy if one is configured
+            let policy = self.policies.get(&from_state);
+            let mut active = node.clone();
+            let mut kind = StepKind::Node;
+            let mut outcome = span
+                .instrument(self.execute_attempts(node.as_ref(), policy, run))
+                .await;
+            let result = outcome.as_ref().map(|(_, state, _)| state);
+            self.node_finished(run, &node_name, result, step_start, &span);
+
+            // Let error middleware recover before any fallback applies
+            if let Err(error) = &outcome
+                && !matches!(error, FlowError::Cancelled | FlowError::Suspended(_))
+            {
+                match self.error_middleware(run, &node_name, error).await {
+                    Ok(None) => {}
+                    Ok(Some((middleware, state))) => {
+                        self.cleanup_after_error(node.as_ref(), run).await;
+                        self.redirect(run, middleware, state, step_start).await?;
+                        continue;
+                    }
+                    Err(error) => {
+                        self.cleanup_after_error(node.as_ref(), run).await;
+                        let error = format!("Middleware error: {error}");
+                        return Ok(RunExit::Failed(error));
+                    }
+                }
+            }
+
+            if let Err(error) = &outcome
+                && !matches!(error, FlowError::Cancelled | FlowError::Suspended(_))
+                && let Some(fallback) = policy.and_then(|policy| policy.fallback.as_ref())
+            {
+                self.cleanup_after_error(node.as_ref(), run).await;
+                match fallback {
+                    Fallback::State(state) => {
+                        if let Err(error) = self.transitions.check(&node_name, &from_state, state) {
+                            return Ok(RunExit::Failed(error.to_string()));
+                        }
+
+                        let step = ExecutionStep::new(
+                            run.steps,
+                            from_state.clone(),
+                            state.clone(),
+                            node_name.clone(),
+                            step_start.elapsed(),
+                        )
+                        .with_kind(StepKind::Fallback)
+                        .with_error(error);
+                        run.trace.push(step);
+
+                        self.transition(run, &node_name, state.clone());
+                        self.commit(run, false).await?;
+                        continue;
+                    }
+                    Fallback::Node(fallback) => {
+                        let fallback = run.node(fallback);
+                        let fallback_name = fallback.name();
+                        let fallback_start = Instant::now();
+                        let span = Span::node(&fallback_name, &from_state, run.steps);
+                        metrics::node_started(&self.name, &fallback_name, &from_state);
+                        self.emit(
+                            &run.events,
+                            FlowEvent::NodeStarted {
+                                run_id: run.run_id.clone(),
+                                node_name: fallback_name.clone(),
+                                state: from_state.clone(),
+                                step: run.steps,
+                                timestamp: chrono::Utc::now(),
+                            },
+                        );
+
+                        // Prepare the fallback like any other node
+                        if let Err(error) = span.instrument(fallback.prepare(&run.context)).await {
+                            self.node_finished(
+                                run,
+                                &fallback_name,
+                                Err(&error),
+                                fallback_start,
+                                &span,
+                            );
+                            let step = ExecutionStep::new(
+                                run.steps,
+                                from_state.clone(),
+                                from_state.clone(),
+                                fallback_name.clone(),
+                                fallback_start.elapsed(),
+                            )
+                            .with_kind(StepKind::Prepare)
+                            .with_error(&error);
+                            run.trace.push(step);
+
+                            self.cleanup_after_error(fallback.as_ref(), run).await;
+                            let error =
+                                format!("prepare hook failed for node '{fallback_name}': {error}");
+                            return Ok(RunExit::Failed(error));
+                        }
+
+                        active = fallback.clone();
+                        kind = StepKind::Fallback;
+                        outcome = span
+                            .instrument(run.until_cancelled(fallback.execute(run.context.clone())))
+                            .await
+                            .map(|(context, state)| (context, state, 1));
+
+                        let result = outcome.as_ref().map(|(_, state, _)| state);
+                        self.node_finished(run, &fallback_name, result, fallback_start, &span);
+                    }
+                }
+            }
+            let node_name = active.name();
+
+            match outcome {
+                Ok((mut new_context, new_state, attempt)) => {
+                    let children = new_context
+                        .remove::<ChildTrace>()
+                        .map(|trace| trace.0)
+                        .unwrap_or_default();
+                    let diff = run
+                        .record
+                        .then(|| ContextDiff::between(&run.context, &new_context));
+                    let step = ExecutionStep::new(
+                        run.steps,
+                        from_state.clone(),
+                        new_state.clone(),
+                        node_name.clone(),
+                        step_start.elapsed(),
+                    )
+                    .with_kind(kind)
+                    .with_attempt(attempt)
+                    .with_children(children)
+                    .with_context_diff(diff);
+                    run.trace.push(step);
+
+                    if let Err(error) = active.cleanup(&new_context, &new_state).await {
+                        let step = ExecutionStep::new(
+                            run.steps,
+                            from_state.clone(),
+                            new_state.clone(),
+                            node_name.clone(),
+                            step_start.elapsed(),
+                        )
+                        .with_kind(StepKind::Cleanup)
+                        .with_error(&error);
+                        run.trace.push(step);
+
+                        run.context = new_context;
+                        let error = format!("cleanup hook failed for node '{node_name}': {error}");
+                        return Ok(RunExit::Failed(error));
+                    }
+
+                    run.context = new_context;
+                    if let Err(error) = self.transitions.check(&node_name, &from_state, &new_state)
+                    {
+                        return Ok(RunExit::Failed(error.to_string()));
+                    }
+
+                    self.transition(run, &node_name, new_state);
+
+                    // After-node middleware may replace the next state
+                    match self
+                        .after_middleware(run, &node_name, &from_state, step_start)
+                        .await
+                    {
+                        Ok(None) => self.commit(run, false).await?,
+                        Ok(Some((middleware, state))) => {
+                            self.redirect(run, middleware, state, step_start).await?;
+                        }
+                        Err(error) => {
+                            let error = format!("Middleware error: {error}");
+                            return Ok(RunExit::Failed(error));
+                        }
+                    }
                 }
                 Err(error) => {
-                    let step = ExecutionStep {
-                        step_number: steps,
-                        from_state: from_state.clone(),
-                        to_state: current_state.clone(),
-                        node_name: node.name(),
-                        duration: step_start.elapsed(),
-                        timestamp: chrono::Utc::now(),
-                    };
-                    trace.push(step);
-
-                    return Ok(AdvancedFlowResult {
-                        final_state: current_state,
-                        context,
-                        duration: start_time.elapsed(),
-                        steps,
-                        success: false,
-                        error: Some(error.to_string()),
-                        metadata,
-                        trace,
-                    });
+                    // Failed attempts of the primary node are already in the trace
+                    if kind == StepKind::Fallback {
+                        let step = ExecutionStep::new(
+                            run.steps,
+                            from_state.clone(),
+                            from_state.clone(),
+                            node_name.clone(),
+                            step_start.elapsed(),
+                        )
+                        .with_kind(StepKind::Fallback)
+                        .with_error(&error);
+                        run.trace.push(step);
+                    }
+
+                    self.cleanup_after_error(active.as_ref(), run).await;
+                    match error {
+                        FlowError::Cancelled => return Ok(RunExit::Cancelled),
+                        FlowError::Suspended(pending) => return Ok(RunExit::Suspended(*pending)),
+                        error => return Ok(RunExit::Failed(error.to_string())),
+                    }
+                }
+            }
+        }
+    }
+
+    /// End a run the way its steps stopped it.
+    async fn finish_run(
+        &self,
+        mut run: RunState<S>,
+        exit: Result<RunExit>,
+    ) -> Result<AdvancedFlowResult<S>> {
+        match exit {
+            Ok(RunExit::Completed) => {
+                run.metadata
+                    .insert("completed_at".to_string(), chrono::Utc::now().to_rfc3339());
+                if let Err(error) = self.commit(&run, true).await {
+                    return self.fail(run, error.to_string()).await;
                 }
+                if run.current_state.is_error() {
+                    let error = format!("Flow ended in error state {:?}", run.current_state);
+                    return self.fail(run, error).await;
+                }
+                Ok(run.finish(true, None))
+            }
+            Ok(RunExit::Failed(error)) => self.fail(run, error).await,
+            Ok(RunExit::Cancelled) => self.finish_cancelled(run).await,
+            Ok(RunExit::Suspended(pending)) => self.finish_suspended(run, pending).await,
+            Err(error) => self.fail(run, error.to_string()).await,
+        }
+    }
+
+    /// Run `before_node` on every middleware, stopping at the first redirect.
+    async fn before_middleware(
+        &self,
+        run: &mut RunState<S>,
+        node_name: &str,
+    ) -> Result<Option<(String, S)>> {
+        for middleware in &self.middleware {
+            let node = NodeInfo {
+                flow_name: &self.name,
+                run_id: &run.run_id,
+                node_name,
+                state: &run.current_state,
+                step: run.steps,
+            };
+            let action = middleware.before_node(&mut run.context, node).await;
+            if let MiddlewareAction::Redirect(state) =
+                self.check_middleware(run, middleware.as_ref(), node_name, action)?
+            {
+                return Ok(Some((middleware.name(), state)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Run `after_node` on every middleware, stopping at the first redirect.
+    ///
+    /// Expects the run to already hold the node's new context and state.
+    async fn after_middleware(
+        &self,
+        run: &mut RunState<S>,
+        node_name: &str,
+        from_state: &S,
+        started: Instant,
+    ) -> Result<Option<(String, S)>> {
+        for middleware in &self.middleware {
+            let node = NodeInfo {
+                flow_name: &self.name,
+                run_id: &run.run_id,
+                node_name,
+                state: from_state,
+                step: run.steps,
+            };
+            let action = middleware
+                .after_node(
+                    &mut run.context,
+                    node,
+                    &run.current_state,
+                    started.elapsed(),
+                )
+                .await;
+            if let MiddlewareAction::Redirect(state) =
+                self.check_middleware(run, middleware.as_ref(), node_name, action)?
+            {
+                return Ok(Some((middleware.name(), state)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Run `on_error` on every middleware, stopping at the first redirect.
+    async fn error_middleware(
+        &self,
+        run: &mut RunState<S>,
+        node_name: &str,
+        error: &FlowError,
+    ) -> Result<Option<(String, S)>> {
+        for middleware in &self.middleware {
+            let node = NodeInfo {
+                flow_name: &self.name,
+                run_id: &run.run_id,
+                node_name,
+                state: &run.current_state,
+                step: run.steps,
+            };
+            let action = middleware.on_error(&mut run.context, node, error).await;
+            if let MiddlewareAction::Redirect(state) =
+                self.check_middleware(run, middleware.as_ref(), node_name, action)?
+            {
+                return Ok(Some((middleware.name(), state)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Report a failed middleware hook to observers before passing it on.
+    fn check_middleware(
+        &self,
+        run: &RunState<S>,
+        middleware: &dyn FlowMiddleware<S>,
+        node_name: &str,
+        action: Result<MiddlewareAction<S>>,
+    ) -> Result<MiddlewareAction<S>> {
+        if let Err(error) = &action {
+            self.emit(
+                &run.events,
+                FlowEvent::MiddlewareRejected {
+                    run_id: run.run_id.clone(),
+                    middleware: middleware.name(),
+                    node_name: node_name.to_string(),
+                    state: run.current_state.clone(),
+                    error: error.to_string(),
+                    timestamp: chrono::Utc::now(),
+                },
+            );
+        }
+        action
+    }
+
+    /// Move the run to a state requested by middleware.
+    async fn redirect(
+        &self,
+        run: &mut RunState<S>,
+        middleware: String,
+        state: S,
+        started: Instant,
+    ) -> Result<()> {
+        self.transitions
+            .check(&middleware, &run.current_state, &state)?;
+
+        let step = ExecutionStep::new(
+            run.steps,
+            run.current_state.clone(),
+            state.clone(),
+            middleware.clone(),
+            started.elapsed(),
+        )
+        .with_kind(StepKind::Middleware);
+        run.trace.push(step);
+
+        self.transition(run, &middleware, state);
+        self.commit(run, false).await
+    }
+
+    /// Execute a node with its policy's timeout and retries.
+    ///
+    /// Every failed attempt is recorded in the trace. On success the new
+    /// context and state are returned together with the attempt number.
+    async fn execute_attempts(
+        &self,
+        node: &dyn Node<State = S>,
+        policy: Option<&NodePolicy<S>>,
+        run: &mut RunState<S>,
+    ) -> Result<(Context, S, u32)> {
+        let retry = policy
+            .map(|policy| policy.retry.clone())
+            .unwrap_or_default();
+        let timeout = policy.and_then(|policy| policy.timeout);
+
+        let mut attempt = 1;
+        loop {
+            let attempt_start = Instant::now();
+            let execution = async {
+                match timeout {
+                    Some(limit) => tokio::time::timeout(limit, node.execute(run.context.clone()))
+                        .await
+                        .unwrap_or(Err(FlowError::Timeout)),
+                    None => node.execute(run.context.clone()).await,
+                }
+            };
+            let result = run.until_cancelled(execution).await;
+
+            let error = match result {
+                Ok((context, state)) => return Ok((context, state, attempt)),
+                Err(error) => error,
+            };
+
+            let step = ExecutionStep::new(
+                run.steps,
+                run.current_state.clone(),
+                run.current_state.clone(),
+                node.name(),
+                attempt_start.elapsed(),
+            )
+            .with_attempt(attempt)
+            .with_error(&error);
+            run.trace.push(step);
+
+            if matches!(error, FlowError::Cancelled | FlowError::Suspended(_))
+                || attempt >= retry.max_attempts()
+            {
+                return Err(error);
+            }
+            run.until_cancelled(async {
+                tokio::time::sleep(retry.delay(attempt)).await;
+                Ok(())
+            })
+            .await?;
+            metrics::node_retried(&self.name, &node.name(), &run.current_state);
+            attempt += 1;
+        }
+    }
+
+    /// Publish the previous step to the shared state, then continue from the
+    /// shared state once it is one this flow handles.
+    async fn sync_shared(&self, run: &mut RunState<S>) -> Result<()> {
+        let Some(shared) = run.shared.as_mut() else {
+            return Ok(());
+        };
+        if !shared.publish(&run.current_state, &run.context).await {
+            let conflicts = run
+                .metadata
+                .get("shared_conflicts")
+                .and_then(|count| count.parse::<usize>().ok())
+                .unwrap_or(0);
+            run.metadata
+                .insert("shared_conflicts".to_string(), (conflicts + 1).to_string());
+        }
+
+        let handles = |state: &S| {
+            state.is_terminal()
+                || self.nodes.contains_key(state)
+                || self.conditions.contains_key(state)
+        };
+        let (state, context) = shared.refresh(&run.cancel, handles).await?;
+        run.current_state = state;
+        run.context = context;
+        Ok(())
+    }
+
+    /// End a failed run, compensating its successful node steps first.
+    ///
+    /// A compensated run is checkpointed as ended, so it cannot be resumed
+    /// past the undone steps.
+    async fn fail(&self, mut run: RunState<S>, error: String) -> Result<AdvancedFlowResult<S>> {
+        run.roll_back();
+        if self.compensate(&mut run).await {
+            run.metadata.insert(
+                "compensated_at".to_string(),
+                chrono::Utc::now().to_rfc3339(),
+            );
+            self.commit(&run, true).await?;
+        }
+        Ok(run.finish(false, Some(error)))
+    }
+
+    /// Run the `compensate` hook of every node that completed a step, most
+    /// recent step first, recording the nodes that had something to undo.
+    ///
+    /// Compensation is best effort: a failing hook is recorded and the
+    /// remaining ones still run. Returns whether any hook had something to
+    /// undo.
+    async fn compensate(&self, run: &mut RunState<S>) -> bool {
+        let mut completed = Vec::new();
+        for step in run.trace.iter().rev() {
+            if step.error.is_some() {
+                continue;
+            }
+            let node =
+                match step.kind {
+                    StepKind::Node => self.nodes.get(&step.from_state),
+                    StepKind::Fallback => self.policies.get(&step.from_state).and_then(|policy| {
+                        match &policy.fallback {
+                            Some(Fallback::Node(node)) => Some(node),
+                            _ => None,
+                        }
+                    }),
+                    _ => None,
+                };
+            if let Some(node) = node.filter(|node| node.name() == step.node_name) {
+                let node = match &run.replay {
+                    Some(cursor) => cursor.node(node, &step.from_state),
+                    None => node.clone(),
+                };
+                completed.push((node, step.from_state.clone(), step.to_state.clone()));
+            }
+        }
+
+        let mut compensated = false;
+        for (node, from_state, to_state) in completed {
+            let started = Instant::now();
+            let outcome = node.compensate(&run.context).await;
+            if matches!(outcome, Ok(false)) {
+                continue;
+            }
+
+            let mut step = ExecutionStep::new(
+                run.steps,
+                from_state,
+                to_state,
+                node.name(),
+                started.elapsed(),
+            )
+            .with_kind(StepKind::Compensation);
+            if let Err(error) = outcome {
+                step = step.with_error(error);
             }
+            run.trace.push(step);
+            compensated = true;
+        }
+        compensated
+    }
+
+    /// End a cancelled run, keeping its checkpoint resumable.
+    async fn finish_cancelled(&self, mut run: RunState<S>) -> Result<AdvancedFlowResult<S>> {
+        run.roll_back();
+        run.metadata
+            .insert("cancelled_at".to_string(), chrono::Utc::now().to_rfc3339());
+        if let Err(error) = self.commit(&run, false).await {
+            return self.fail(run, error.to_string()).await;
+        }
+        Ok(run.finish(false, Some(FlowError::Cancelled.to_string())))
+    }
+
+    /// End a run waiting for input, checkpointing it with the pending request.
+    async fn finish_suspended(
+        &self,
+        mut run: RunState<S>,
+        pending: PendingInput,
+    ) -> Result<AdvancedFlowResult<S>> {
+        if self.checkpointer.is_none() {
+            let error = format!(
+                "Node '{}' needs input but flow '{}' has no checkpoint store",
+                pending.node, self.name
+            );
+            return self.fail(run, error).await;
+        }
+
+        run.roll_back();
+        let error = FlowError::Suspended(Box::new(pending.clone())).to_string();
+        run.metadata
+            .insert("suspended_at".to_string(), chrono::Utc::now().to_rfc3339());
+        let committed = match run.context.put(&PENDING_INPUT, pending) {
+            Ok(()) => self.commit(&run, false).await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = committed {
+            return self.fail(run, error.to_string()).await;
+        }
+        Ok(run.finish(false, Some(error)))
+    }
+
+    /// Run a node's cleanup hook on the error path, recording any failure.
+    async fn cleanup_after_error(&self, node: &dyn Node<State = S>, run: &mut RunState<S>) {
+        let started = Instant::now();
+        if let Err(error) = node.cleanup(&run.context, &run.current_state).await {
+            let step = ExecutionStep::new(
+                run.steps,
+                run.current_state.clone(),
+                run.current_state.clone(),
+                node.name(),
+                started.elapsed(),
+            )
+            .with_kind(StepKind::Cleanup)
+            .with_error(&error);
+            run.trace.push(step);
         }
     }
 
@@ -201,6 +1443,68 @@ impl<S: FlowState> AdvancedFlow<S> {
         &self.name
     }
 
+    /// Get the state runs start in.
+    pub fn initial_state(&self) -> &S {
+        &self.initial_state
+    }
+
+    /// Build the static graph of this flow.
+    ///
+    /// Edges come from the states declared by [`Node::emits`], conditional
+    /// routers and fallback policies. Nodes that don't declare their exits
+    /// use the flow's transition table when one is enforced.
+    pub fn graph(&self) -> FlowGraph<S> {
+        let table = self.transitions.enforced_table();
+        let mut graph = FlowGraph::new(&self.name, self.initial_state.clone());
+
+        for (state, (_, true_state, false_state)) in &self.conditions {
+            graph.add_handler(state.clone(), "conditional_router");
+            graph.add_edge(state.clone(), true_state.clone(), EdgeKind::Router, "true");
+            graph.add_edge(
+                state.clone(),
+                false_state.clone(),
+                EdgeKind::Router,
+                "false",
+            );
+        }
+
+        for (state, node) in &self.nodes {
+            // Routers take precedence over nodes registered for the same state
+            if self.conditions.contains_key(state) {
+                continue;
+            }
+            graph.add_handler(state.clone(), node.name());
+            graph.add_node_exits(state, node.as_ref(), EdgeKind::Node, table);
+
+            match self
+                .policies
+                .get(state)
+                .and_then(|policy| policy.fallback.as_ref())
+            {
+                Some(Fallback::State(fallback)) => {
+                    graph.add_edge(
+                        state.clone(),
+                        fallback.clone(),
+                        EdgeKind::Fallback,
+                        "fallback",
+                    );
+                }
+                Some(Fallback::Node(fallback)) => {
+                    graph.add_node_exits(state, fallback.as_ref(), EdgeKind::Fallback, table);
+                }
+                None => {}
+            }
+        }
+
+        graph.finish()
+    }
+
+    /// Check the flow graph for missing handlers, unreachable states, states
+    /// with no path to a terminal state and cycles without exits.
+    pub fn validate(&self) -> GraphReport<S> {
+        self.graph().validate()
+    }
+
     /// Create a new flow builder.
     pub fn builder() -> AdvancedFlowBuilder<S> {
         AdvancedFlowBuilder::new()
@@ -212,9 +1516,17 @@ pub struct AdvancedFlowBuilder<S: FlowState> {
     nodes: HashMap<S, Arc<dyn Node<State = S>>>,
     initial_state: Option<S>,
     name: String,
-    middleware: Vec<Middleware<S>>,
+    middleware: Vec<Arc<dyn FlowMiddleware<S>>>,
+    observers: Vec<Arc<dyn FlowObserver<S>>>,
     conditions: HashMap<S, (Condition<S>, S, S)>,
+    policies: HashMap<S, NodePolicy<S>>,
     max_steps: usize,
+    checkpointer: Option<Checkpointer<S>>,
+    types: Option<Arc<TypeRegistry>>,
+    transitions: TransitionRules<S>,
+    strict_graph: bool,
+    record_context: bool,
+    transactional: bool,
 }
 
 impl<S: FlowState> AdvancedFlowBuilder<S> {
@@ -225,8 +1537,16 @@ impl<S: FlowState> AdvancedFlowBuilder<S> {
             initial_state: None,
             name: "advanced_flow".to_string(),
             middleware: Vec::new(),
+            observers: Vec::new(),
             conditions: HashMap::new(),
+            policies: HashMap::new(),
             max_steps: 1000,
+            checkpointer: None,
+            types: None,
+            transitions: TransitionRules::strict(),
+            strict_graph: false,
+            record_context: false,
+            transactional: false,
         }
     }
 
@@ -254,32 +1574,127 @@ impl<S: FlowState> AdvancedFlowBuilder<S> {
         self
     }
 
-    /// Add middleware that runs before each node execution.
-    pub fn middleware<F>(mut self, middleware: F) -> Self
+    /// Add a node for a specific state with a timeout, retry and fallback policy.
+    pub fn on_state_with_policy(
+        mut self,
+        state: S,
+        node: impl Node<State = S> + 'static,
+        policy: NodePolicy<S>,
+    ) -> Self {
+        self.nodes.insert(state.clone(), Arc::new(node));
+        self.policies.insert(state, policy);
+        self
+    }
+
+    /// Validate every transition returned by a node (enabled by default).
+    ///
+    /// Transitions are checked against [`FlowState::can_transition_to`], or
+    /// against the flow's transition table when one has been declared.
+    pub fn strict_transitions(mut self, strict: bool) -> Self {
+        self.transitions.strict = strict;
+        self
+    }
+
+    /// Declare an allowed transition in the flow's transition table.
+    pub fn allow_transition(mut self, from: S, to: S) -> Self {
+        self.transitions.allow(from, to);
+        self
+    }
+
+    /// Use a transition table instead of [`FlowState::can_transition_to`].
+    pub fn transition_table(mut self, table: TransitionTable<S>) -> Self {
+        self.transitions.table = Some(table);
+        self
+    }
+
+    /// Reject flows whose graph has structural issues when building.
+    ///
+    /// See [`AdvancedFlow::validate`].
+    pub fn strict_graph(mut self, strict: bool) -> Self {
+        self.strict_graph = strict;
+        self
+    }
+
+    /// Record the context changes made by every node in the trace.
+    ///
+    /// See [`ExecutionStep::context_diff`] and [`AdvancedFlow::record`].
+    pub fn record_context(mut self, record: bool) -> Self {
+        self.record_context = record;
+        self
+    }
+
+    /// Roll the context back to its snapshot from before the failing step
+    /// when a run fails or is cancelled.
+    ///
+    /// Writes made by `before_node`/`on_error` middleware and by a node whose
+    /// cleanup hook fails are discarded, so the result carries the last good
+    /// context. The step number is recorded in the `rolled_back_step`
+    /// metadata key.
+    pub fn transactional(mut self, transactional: bool) -> Self {
+        self.transactional = transactional;
+        self
+    }
+
+    /// Persist a checkpoint after every committed step.
+    ///
+    /// Enables [`AdvancedFlow::resume`] for runs of this flow.
+    pub fn checkpoint_store(mut self, store: Arc<dyn CheckpointStore>) -> Self
+    where
+        S: Serialize + DeserializeOwned,
+    {
+        self.checkpointer = Some(Checkpointer::new(store));
+        self
+    }
+
+    /// Checkpoint typed context entries whose types are registered in
+    /// `registry`.
+    ///
+    /// Without a registry only JSON data and metadata are checkpointed. With
+    /// one, a typed entry of an unregistered type fails the commit.
+    pub fn type_registry(mut self, registry: Arc<TypeRegistry>) -> Self {
+        self.types = Some(registry);
+        self
+    }
+
+    /// Add a synchronous check that runs before each node execution.
+    ///
+    /// Returning an error fails the flow.
+    pub fn middleware<F>(self, middleware: F) -> Self
     where
         F: Fn(&Context, &S) -> Result<()> + Send + Sync + 'static,
     {
+        self.with_middleware(FnMiddleware::new(middleware))
+    }
+
+    /// Add async middleware. Middleware runs in the order it was added.
+    pub fn with_middleware(mut self, middleware: impl FlowMiddleware<S> + 'static) -> Self {
         self.middleware.push(Arc::new(middleware));
         self
     }
 
+    /// Register an observer receiving execution events as they happen.
+    pub fn observer(mut self, observer: impl FlowObserver<S> + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
     /// Add logging middleware.
     pub fn with_logging(self) -> Self {
-        self.middleware(|_context, state| {
-            println!("🔄 Executing state: {state:?}");
-            Ok(())
-        })
+        self.with_middleware(LoggingMiddleware)
     }
 
-    /// Add timing middleware.
+    /// Add timing middleware recording per-node durations in the context metadata.
     pub fn with_timing(self) -> Self {
-        self.middleware(|_context, state| {
-            let timestamp = chrono::Utc::now().to_rfc3339();
-            // Note: We can't modify context here since it's a reference
-            // In a real implementation, you might want to use a different approach
-            println!("⏱️  State {state:?} at {timestamp}");
-            Ok(())
-        })
+        self.with_middleware(TimingMiddleware)
+    }
+
+    /// Add the metrics middleware.
+    ///
+    /// Node and flow metrics are reported by the engine itself, so this is
+    /// a no-op kept for compatibility; see [`crate::metrics`].
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(self) -> Self {
+        self.with_middleware(crate::middleware::MetricsMiddleware)
     }
 
     /// Add conditional routing for a state.
@@ -304,14 +1719,30 @@ impl<S: FlowState> AdvancedFlowBuilder<S> {
             ));
         }
 
-        Ok(AdvancedFlow {
+        let mut checkpointer = self.checkpointer;
+        if let Some(checkpointer) = &mut checkpointer {
+            checkpointer.types = self.types;
+        }
+
+        let flow = AdvancedFlow {
             nodes: self.nodes,
             initial_state,
             name: self.name,
             middleware: self.middleware,
+            observers: self.observers,
             conditions: self.conditions,
+            policies: self.policies,
             max_steps: self.max_steps,
-        })
+            checkpointer,
+            transitions: self.transitions,
+            record_context: self.record_context,
+            transactional: self.transactional,
+        };
+
+        if self.strict_graph {
+            flow.validate().into_result(&flow.name)?;
+        }
+        Ok(flow)
     }
 }
 
@@ -321,51 +1752,6 @@ impl<S: FlowState> Default for AdvancedFlowBuilder<S> {
     }
 }
 
-/// Shared flow state for concurrent access.
-pub struct SharedFlowState<S: FlowState> {
-    state: Arc<RwLock<S>>,
-    context: Arc<RwLock<Context>>,
-    metadata: Arc<RwLock<HashMap<String, String>>>,
-}
-
-impl<S: FlowState> SharedFlowState<S> {
-    pub fn new(initial_state: S, context: Context) -> Self {
-        Self {
-            state: Arc::new(RwLock::new(initial_state)),
-            context: Arc::new(RwLock::new(context)),
-            metadata: Arc::new(RwLock::new(HashMap::new())),
-        }
-    }
-
-    pub async fn get_state(&self) -> S {
-        self.state.read().await.clone()
-    }
-
-    pub async fn set_state(&self, new_state: S) {
-        *self.state.write().await = new_state;
-    }
-
-    pub async fn get_context(&self) -> Context {
-        self.context.read().await.clone()
-    }
-
-    pub async fn update_context<F>(&self, f: F) -> Result<()>
-    where
-        F: FnOnce(&mut Context) -> Result<()>,
-    {
-        let mut context = self.context.write().await;
-        f(&mut context)
-    }
-
-    pub async fn set_metadata(&self, key: String, value: String) {
-        self.metadata.write().await.insert(key, value);
-    }
-
-    pub async fn get_metadata(&self, key: &str) -> Option<String> {
-        self.metadata.read().await.get(key).cloned()
-    }
-}
-
 /// Flow registry for managing multiple flows.
 pub struct FlowRegistry<S: FlowState> {
     flows: HashMap<String, AdvancedFlow<S>>,
@@ -405,78 +1791,12 @@ impl<S: FlowState> Default for FlowRegistry<S> {
     }
 }
 
-/// Flow analytics and metrics.
-pub struct FlowAnalytics<S: FlowState> {
-    execution_history: Vec<AdvancedFlowResult<S>>,
-}
-
-impl<S: FlowState> FlowAnalytics<S> {
-    pub fn new() -> Self {
-        Self {
-            execution_history: Vec::new(),
-        }
-    }
-
-    pub fn record_execution(&mut self, result: AdvancedFlowResult<S>) {
-        self.execution_history.push(result);
-    }
-
-    pub fn success_rate(&self) -> f64 {
-        if self.execution_history.is_empty() {
-            return 0.0;
-        }
-
-        let successful = self.execution_history.iter().filter(|r| r.success).count();
-
-        successful as f64 / self.execution_history.len() as f64
-    }
-
-    pub fn average_execution_time(&self) -> Duration {
-        if self.execution_history.is_empty() {
-            return Duration::from_secs(0);
-        }
-
-        let total: Duration = self.execution_history.iter().map(|r| r.duration).sum();
-
-        total / self.execution_history.len() as u32
-    }
-
-    pub fn average_steps(&self) -> f64 {
-        if self.execution_history.is_empty() {
-            return 0.0;
-        }
-
-        let total_steps: usize = self.execution_history.iter().map(|r| r.steps).sum();
-
-        total_steps as f64 / self.execution_history.len() as f64
-    }
-
-    pub fn most_common_final_state(&self) -> Option<&S> {
-        let mut state_counts = HashMap::new();
-
-        for result in &self.execution_history {
-            *state_counts.entry(&result.final_state).or_insert(0) += 1;
-        }
-
-        state_counts
-            .into_iter()
-            .max_by_key(|(_, count)| *count)
-            .map(|(state, _)| state)
-    }
-}
-
-impl<S: FlowState> Default for FlowAnalytics<S> {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::prelude::*;
 
-    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    #[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
     enum TestState {
         Start,
         Middle,
@@ -558,6 +1878,331 @@ mod tests {
         assert_eq!(result.steps, 2); // Start -> End (skipped Middle)
     }
 
+    #[tokio::test]
+    async fn test_strict_transitions_cover_conditional_routes() {
+        let flow = AdvancedFlow::builder()
+            .initial_state(TestState::Start)
+            .when_state(
+                TestState::Start,
+                |_context, _state| true,
+                TestState::End,
+                TestState::Middle,
+            )
+            .transition_table(TransitionTable::new().allow(TestState::Start, TestState::Middle))
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(result.final_state, TestState::Start);
+        let error = FlowError::invalid_node_transition(
+            "conditional_router",
+            TestState::Start,
+            TestState::End,
+        );
+        assert_eq!(result.error, Some(error.to_string()));
+
+        let lenient = AdvancedFlow::builder()
+            .initial_state(TestState::Start)
+            .on_state(TestState::Start, TestNode(TestState::End))
+            .transition_table(TransitionTable::new())
+            .strict_transitions(false)
+            .build()
+            .unwrap();
+        assert!(lenient.execute(Context::new()).await.unwrap().success);
+    }
+
+    #[derive(Debug)]
+    struct HookedNode {
+        fail_prepare: bool,
+        fail_execute: bool,
+        cleanups: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Node for HookedNode {
+        type State = TestState;
+
+        async fn prepare(&self, _context: &Context) -> Result<()> {
+            if self.fail_prepare {
+                return Err(FlowError::context("missing input"));
+            }
+            Ok(())
+        }
+
+        async fn execute(&self, context: Context) -> Result<(Context, Self::State)> {
+            if self.fail_execute {
+                return Err(FlowError::context("boom"));
+            }
+            Ok((context, TestState::End))
+        }
+
+        async fn cleanup(&self, _context: &Context, _state: &Self::State) -> Result<()> {
+            self.cleanups
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(FlowError::context("teardown failed"))
+        }
+
+        fn name(&self) -> String {
+            "hooked".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_hooks_are_driven_and_recorded() {
+        let cleanups = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let flow = AdvancedFlow::builder()
+            .initial_state(TestState::Start)
+            .on_state(
+                TestState::Start,
+                HookedNode {
+                    fail_prepare: true,
+                    fail_execute: false,
+                    cleanups: cleanups.clone(),
+                },
+            )
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("prepare hook failed"));
+        assert_eq!(cleanups.load(std::sync::atomic::Ordering::SeqCst), 1);
+        let kinds: Vec<_> = result.trace.iter().map(|step| step.kind).collect();
+        assert_eq!(kinds, vec![StepKind::Prepare, StepKind::Cleanup]);
+
+        // Cleanup runs on the execute error path and the original error is kept
+        let flow = AdvancedFlow::builder()
+            .initial_state(TestState::Start)
+            .on_state(
+                TestState::Start,
+                HookedNode {
+                    fail_prepare: false,
+                    fail_execute: true,
+                    cleanups: cleanups.clone(),
+                },
+            )
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert_eq!(result.error.as_deref(), Some("Context error: boom"));
+        assert_eq!(cleanups.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(result.trace[1].kind, StepKind::Cleanup);
+        assert_eq!(
+            result.trace[1].error.as_deref(),
+            Some("Context error: teardown failed")
+        );
+
+        // Fallback nodes are prepared and cleaned up as well
+        let policy = NodePolicy::new().fallback_node(HookedNode {
+            fail_prepare: true,
+            fail_execute: false,
+            cleanups: cleanups.clone(),
+        });
+        let flow = AdvancedFlow::builder()
+            .initial_state(TestState::Start)
+            .on_state_with_policy(TestState::Start, flaky(1, Duration::ZERO), policy)
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(
+            result
+                .error
+                .unwrap()
+                .starts_with("prepare hook failed for node 'hooked'")
+        );
+        assert_eq!(cleanups.load(std::sync::atomic::Ordering::SeqCst), 3);
+        let kinds: Vec<_> = result.trace.iter().map(|step| step.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![StepKind::Node, StepKind::Prepare, StepKind::Cleanup]
+        );
+    }
+
+    #[derive(Debug)]
+    struct FlakyNode {
+        failures: usize,
+        delay: Duration,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Node for FlakyNode {
+        type State = TestState;
+
+        async fn execute(&self, context: Context) -> Result<(Context, Self::State)> {
+            let call = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            if call < self.failures {
+                return Err(FlowError::context(format!("attempt {} failed", call + 1)));
+            }
+            Ok((context, TestState::End))
+        }
+
+        fn name(&self) -> String {
+            "flaky".to_string()
+        }
+    }
+
+    fn flaky(failures: usize, delay: Duration) -> FlakyNode {
+        FlakyNode {
+            failures,
+            delay,
+            calls: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_node_policy_retries_are_traced() {
+        let policy = NodePolicy::new()
+            .max_retries(2)
+            .backoff(Backoff::Fixed(Duration::from_millis(1)))
+            .jitter(0.5);
+        let flow = AdvancedFlow::builder()
+            .initial_state(TestState::Start)
+            .on_state_with_policy(TestState::Start, flaky(2, Duration::ZERO), policy)
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.final_state, TestState::End);
+
+        let attempts: Vec<_> = result
+            .trace
+            .iter()
+            .map(|step| (step.attempt, step.error.is_some()))
+            .collect();
+        assert_eq!(attempts, vec![(1, true), (2, true), (3, false)]);
+    }
+
+    #[tokio::test]
+    async fn test_node_policy_timeout_and_fallbacks() {
+        // Timed out attempts fail with FlowError::Timeout
+        let policy = NodePolicy::new().timeout(Duration::from_millis(5));
+        let flow = AdvancedFlow::builder()
+            .initial_state(TestState::Start)
+            .on_state_with_policy(TestState::Start, flaky(0, Duration::from_secs(5)), policy)
+            .build()
+            .unwrap();
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(result.error, Some(FlowError::Timeout.to_string()));
+
+        // A fallback state takes over once retries are exhausted
+        let policy = NodePolicy::new()
+            .max_retries(1)
+            .fallback_state(TestState::Middle);
+        let flow = AdvancedFlow::builder()
+            .initial_state(TestState::Start)
+            .on_state_with_policy(TestState::Start, flaky(5, Duration::ZERO), policy)
+            .on_state(TestState::Middle, TestNode(TestState::End))
+            .build()
+            .unwrap();
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.trace.len(), 4);
+        assert_eq!(result.trace[2].kind, StepKind::Fallback);
+        assert_eq!(result.trace[2].to_state, TestState::Middle);
+
+        // A fallback node runs in place of the failed one
+        let policy = NodePolicy::new().fallback_node(TestNode(TestState::End));
+        let flow = AdvancedFlow::builder()
+            .initial_state(TestState::Start)
+            .on_state_with_policy(TestState::Start, flaky(5, Duration::ZERO), policy)
+            .build()
+            .unwrap();
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(result.success);
+        let last = result.trace.last().unwrap();
+        assert_eq!(last.kind, StepKind::Fallback);
+        assert_eq!(last.to_state, TestState::End);
+    }
+
+    #[derive(Debug, Default)]
+    struct StuckNode {
+        cleaned_up: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait]
+    impl Node for Arc<StuckNode> {
+        type State = TestState;
+
+        async fn execute(&self, context: Context) -> Result<(Context, Self::State)> {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            Ok((context, TestState::End))
+        }
+
+        async fn cleanup(&self, _context: &Context, _state: &Self::State) -> Result<()> {
+            self.cleaned_up
+                .store(true, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn name(&self) -> String {
+            "stuck".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_aborts_running_node() {
+        let stuck = Arc::new(StuckNode::default());
+        let first = crate::node::helpers::fn_node("first", |mut context: Context| async move {
+            context.set("first_done", true)?;
+            Ok((context, TestState::Middle))
+        });
+        let flow = AdvancedFlow::builder()
+            .initial_state(TestState::Start)
+            .on_state(TestState::Start, first)
+            .on_state(TestState::Middle, stuck.clone())
+            .build()
+            .unwrap();
+
+        let cancel = CancellationToken::new();
+        let trigger = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            trigger.cancel();
+        });
+
+        let result = flow
+            .execute_with_cancel(Context::new(), cancel)
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert_eq!(result.error, Some(FlowError::Cancelled.to_string()));
+        assert_eq!(result.final_state, TestState::Middle);
+        assert_eq!(
+            result.context.get_json::<bool>("first_done").unwrap(),
+            Some(true)
+        );
+        assert_eq!(result.trace.len(), 2);
+        assert!(result.trace[1].error.is_some());
+        assert!(result.metadata.contains_key("cancelled_at"));
+        assert!(stuck.cleaned_up.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_before_start_runs_nothing() {
+        let flow = AdvancedFlow::builder()
+            .initial_state(TestState::Start)
+            .on_state(TestState::Start, TestNode(TestState::End))
+            .build()
+            .unwrap();
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let result = flow
+            .execute_with_cancel(Context::new(), cancel)
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert_eq!(result.final_state, TestState::Start);
+        assert!(result.trace.is_empty());
+    }
+
     #[tokio::test]
     async fn test_flow_registry() {
         let mut registry = FlowRegistry::new();
@@ -616,4 +2261,319 @@ mod tests {
         assert_eq!(analytics.average_steps(), 1.8);
         assert_eq!(analytics.most_common_final_state(), Some(&TestState::End));
     }
+
+    struct Audit;
+
+    #[async_trait]
+    impl FlowMiddleware<TestState> for Audit {
+        async fn before_node(
+            &self,
+            context: &mut Context,
+            node: NodeInfo<'_, TestState>,
+        ) -> Result<MiddlewareAction<TestState>> {
+            context.set("last_node", node.node_name)?;
+            Ok(MiddlewareAction::Continue)
+        }
+    }
+
+    #[tokio::test]
+    async fn transactional_runs_roll_back_failed_steps() {
+        let build = |transactional| {
+            AdvancedFlow::builder()
+                .initial_state(TestState::Start)
+                .on_state(TestState::Start, TestNode(TestState::Middle))
+                .on_state(
+                    TestState::Middle,
+                    crate::node::helpers::fn_node("charge", |_context: Context| async move {
+                        Err::<(Context, TestState), _>(FlowError::context("card declined"))
+                    }),
+                )
+                .with_middleware(Audit)
+                .transactional(transactional)
+                .build()
+                .unwrap()
+        };
+
+        let result = build(false).execute(Context::new()).await.unwrap();
+        assert_eq!(
+            result.context.get_json::<String>("last_node").unwrap(),
+            Some("charge".to_string())
+        );
+
+        let result = build(true).execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(
+            result.context.get_json::<String>("last_node").unwrap(),
+            Some("test_node_Middle".to_string())
+        );
+        assert_eq!(result.metadata["rolled_back_step"], "2");
+    }
+
+    #[tokio::test]
+    async fn shared_runs_cooperate_through_the_blackboard() {
+        let store = Arc::new(InMemoryCheckpointStore::new());
+        let writer = AdvancedFlow::builder()
+            .name("writer")
+            .initial_state(TestState::Start)
+            .on_state(
+                TestState::Start,
+                crate::node::helpers::fn_node("draft", |mut context: Context| async move {
+                    context.set("draft", "hello")?;
+                    Ok((context, TestState::Middle))
+                }),
+            )
+            .checkpoint_store(store.clone())
+            .type_registry(Arc::new(TypeRegistry::new()))
+            .build()
+            .unwrap();
+        let reviewer = AdvancedFlow::builder()
+            .name("reviewer")
+            .initial_state(TestState::Middle)
+            .on_state(
+                TestState::Middle,
+                crate::node::helpers::fn_node("review", |mut context: Context| async move {
+                    let shared = SharedFlowState::<TestState>::from_context(&context)
+                        .ok_or_else(|| FlowError::context("not a shared run"))?;
+                    let draft = shared.wait_for_key("draft").await?;
+                    context.set(
+                        "review",
+                        format!("{} looks good", draft.as_str().unwrap_or("")),
+                    )?;
+                    Ok((context, TestState::End))
+                }),
+            )
+            .build()
+            .unwrap();
+
+        let shared = SharedFlowState::new(TestState::Start, Context::new());
+        let (reviewed, written) = tokio::join!(
+            reviewer.execute_shared(&shared, CancellationToken::new()),
+            writer.execute_shared(&shared, CancellationToken::new()),
+        );
+        let (reviewed, written) = (reviewed.unwrap(), written.unwrap());
+
+        assert!(reviewed.success && written.success);
+        assert!(
+            reviewed
+                .context
+                .get::<SharedFlowState<TestState>>()
+                .is_none()
+        );
+        assert_eq!(store.list_runs().await.unwrap().len(), 1);
+        assert_eq!(written.trace.len(), 1);
+        assert_eq!(reviewed.trace.len(), 1);
+        assert_eq!(shared.get_state().await, TestState::End);
+        assert_eq!(
+            shared
+                .get_context()
+                .await
+                .get_json::<String>("review")
+                .unwrap(),
+            Some("hello looks good".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn shared_runs_discard_steps_that_lose_the_race() {
+        let build = |delay: u64, next: TestState| {
+            AdvancedFlow::builder()
+                .initial_state(TestState::Start)
+                .on_state(
+                    TestState::Start,
+                    crate::node::helpers::fn_node("claim", move |mut context: Context| {
+                        let next = next.clone();
+                        async move {
+                            tokio::time::sleep(Duration::from_millis(delay)).await;
+                            context.set("claimed_by", delay)?;
+                            Ok((context, next))
+                        }
+                    }),
+                )
+                .on_state(TestState::Middle, TestNode(TestState::End))
+                .build()
+                .unwrap()
+        };
+        let (slow, fast) = (build(50, TestState::Middle), build(0, TestState::End));
+
+        let shared = SharedFlowState::new(TestState::Start, Context::new());
+        let (slow, fast) = tokio::join!(
+            slow.execute_shared(&shared, CancellationToken::new()),
+            fast.execute_shared(&shared, CancellationToken::new()),
+        );
+        let (slow, fast) = (slow.unwrap(), fast.unwrap());
+
+        assert!(fast.success && !fast.metadata.contains_key("shared_conflicts"));
+        assert_eq!(slow.final_state, TestState::End);
+        assert_eq!(slow.metadata["shared_conflicts"], "1");
+        assert_eq!(
+            shared
+                .get_context()
+                .await
+                .get_json::<u64>("claimed_by")
+                .unwrap(),
+            Some(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_runs_compensate_completed_steps_in_reverse() {
+        use crate::node::helpers::{compensated, fn_node};
+
+        let undone = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let undo = |label: &'static str, fail: bool| {
+            let undone = undone.clone();
+            move |context: Context| {
+                let undone = undone.clone();
+                async move {
+                    let order = context.get_json::<String>("order")?;
+                    undone
+                        .lock()
+                        .unwrap()
+                        .push(format!("{label} {}", order.unwrap_or_default()));
+                    if fail {
+                        return Err(FlowError::context("refund rejected"));
+                    }
+                    Ok(())
+                }
+            }
+        };
+        let charge = SimpleState::Custom("charge".to_string());
+        let ship = SimpleState::Custom("ship".to_string());
+
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                compensated(
+                    fn_node("reserve", |mut context: Context| async move {
+                        context.set("order", "A-1")?;
+                        Ok((context, SimpleState::Custom("charge".to_string())))
+                    }),
+                    undo("release", false),
+                ),
+            )
+            .on_state(
+                charge.clone(),
+                compensated(
+                    PassthroughNode::new("charge", ship.clone()),
+                    undo("refund", true),
+                ),
+            )
+            .on_state(
+                ship.clone(),
+                fn_node("ship", |_context: Context| async move {
+                    Err::<(Context, SimpleState), _>(FlowError::context("carrier down"))
+                }),
+            )
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("carrier down"));
+        assert_eq!(*undone.lock().unwrap(), ["refund A-1", "release A-1"]);
+
+        let compensations: Vec<_> = result
+            .trace
+            .iter()
+            .filter(|step| step.kind == StepKind::Compensation)
+            .map(|step| {
+                (
+                    step.node_name.as_str(),
+                    step.from_state.clone(),
+                    step.error.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            compensations,
+            [
+                (
+                    "charge",
+                    charge,
+                    Some("Context error: refund rejected".to_string())
+                ),
+                ("reserve", SimpleState::Start, None),
+            ]
+        );
+
+        // Successful runs are left alone
+        undone.lock().unwrap().clear();
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                compensated(
+                    PassthroughNode::new("reserve", SimpleState::Success),
+                    undo("release", false),
+                ),
+            )
+            .build()
+            .unwrap();
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(result.success);
+        assert!(undone.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn error_exits_and_error_states_are_compensated() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use crate::node::helpers::compensated;
+
+        let undone = Arc::new(AtomicUsize::new(0));
+        let reserve = |next: SimpleState| {
+            let undone = undone.clone();
+            compensated(PassthroughNode::new("reserve", next), move |_context| {
+                let undone = undone.clone();
+                async move {
+                    undone.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            })
+        };
+
+        // No node handles the next state
+        let store = Arc::new(InMemoryCheckpointStore::new());
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                reserve(SimpleState::Custom("ship".to_string())),
+            )
+            .checkpoint_store(store.clone())
+            .build()
+            .unwrap();
+        let result = flow
+            .execute_with_run_id("order-1", Context::new())
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("No node found"));
+        assert_eq!(undone.load(Ordering::SeqCst), 1);
+
+        // The compensated run is closed for good
+        let checkpoint = store.load("order-1").await.unwrap().unwrap();
+        assert!(checkpoint.completed);
+        assert!(checkpoint.run_metadata.contains_key("compensated_at"));
+        let error = flow.resume("order-1").await.unwrap_err();
+        assert!(error.to_string().contains("compensated"));
+        assert_eq!(undone.load(Ordering::SeqCst), 1);
+
+        // Ending in an error state fails the run
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(SimpleState::Start, reserve(SimpleState::Error))
+            .build()
+            .unwrap();
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(result.final_state, SimpleState::Error);
+        assert_eq!(
+            result.error.as_deref(),
+            Some("Flow ended in error state Error")
+        );
+        assert_eq!(undone.load(Ordering::SeqCst), 2);
+        assert_eq!(result.trace.last().unwrap().kind, StepKind::Compensation);
+    }
 }
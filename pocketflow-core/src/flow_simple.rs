@@ -9,8 +9,12 @@ use std::{
 use crate::{
     context::Context,
     error::{FlowError, Result},
+    flow_advanced::ChildTrace,
+    graph::{EdgeKind, FlowGraph, GraphReport},
+    metrics,
     node::Node,
-    state::FlowState,
+    spans::Span,
+    state::{FlowState, TransitionRules, TransitionTable},
 };
 
 /// Workflow execution result.
@@ -35,6 +39,8 @@ pub struct SimpleFlow<S: FlowState> {
     nodes: HashMap<S, Arc<dyn Node<State = S>>>,
     initial_state: S,
     name: String,
+    transitions: TransitionRules<S>,
+    transactional: bool,
 }
 
 impl<S: FlowState> SimpleFlow<S> {
@@ -44,7 +50,34 @@ impl<S: FlowState> SimpleFlow<S> {
     }
 
     /// Execute the workflow.
-    pub async fn execute(&self, mut context: Context) -> Result<FlowResult<S>> {
+    pub async fn execute(&self, context: Context) -> Result<FlowResult<S>> {
+        let started = Instant::now();
+        let run_id = uuid::Uuid::new_v4().to_string();
+        let span = Span::flow(&self.name, &run_id, &self.initial_state);
+        metrics::flow_started(&self.name);
+        let result = span.instrument(self.run(context)).await;
+
+        let (state, success) = match &result {
+            Ok(result) => (Some(&result.final_state), result.success),
+            Err(_) => (None, false),
+        };
+        metrics::flow_finished(&self.name, state, success, started.elapsed());
+        if let Some(state) = state {
+            span.record("final_state", state);
+        }
+        match &result {
+            Ok(result) => {
+                span.record("steps", result.steps);
+                if let Some(error) = &result.error {
+                    span.record_error(error);
+                }
+            }
+            Err(error) => span.record_error(error),
+        }
+        result
+    }
+
+    async fn run(&self, mut context: Context) -> Result<FlowResult<S>> {
         let start_time = Instant::now();
         let mut current_state = self.initial_state.clone();
         let mut steps = 0;
@@ -74,33 +107,146 @@ impl<S: FlowState> SimpleFlow<S> {
                 FlowError::execution(format!("No node found for state: {current_state:?}"))
             })?;
 
-            // Execute the node
-            let node_result = node.execute(context).await;
+            let node_name = node.name();
+            let node_start = Instant::now();
+            let span = Span::node(&node_name, &current_state, steps);
+            metrics::node_started(&self.name, &node_name, &current_state);
+
+            // Prepare the node; cleanup still runs if preparation fails
+            if let Err(error) = span.instrument(node.prepare(&context)).await {
+                span.record_error(&error);
+                let elapsed = node_start.elapsed();
+                metrics::node_finished(&self.name, &node_name, &current_state, false, elapsed);
+                let error = format!("prepare hook failed for node '{node_name}': {error}");
+                let error =
+                    Self::cleanup_after_error(node.as_ref(), &context, &current_state, error).await;
+                return Ok(FlowResult {
+                    final_state: current_state,
+                    context,
+                    duration: start_time.elapsed(),
+                    steps,
+                    success: false,
+                    error: Some(error),
+                });
+            }
+
+            // Execute the node, keeping a copy of the input for error-path cleanup
+            let node_result = span.instrument(node.execute(context.clone())).await;
+            match &node_result {
+                Ok((_, state)) => span.record("to", state),
+                Err(error) => span.record_error(error),
+            }
+            metrics::node_finished(
+                &self.name,
+                &node_name,
+                &current_state,
+                node_result.is_ok(),
+                node_start.elapsed(),
+            );
             match node_result {
-                Ok((new_context, new_state)) => {
+                Ok((mut new_context, new_state)) => {
+                    // Simple flows keep no trace to nest sub-flow steps under
+                    new_context.remove::<ChildTrace>();
+                    if let Err(error) = node.cleanup(&new_context, &new_state).await {
+                        return Ok(FlowResult {
+                            final_state: current_state,
+                            context: if self.transactional {
+                                context
+                            } else {
+                                new_context
+                            },
+                            duration: start_time.elapsed(),
+                            steps,
+                            success: false,
+                            error: Some(format!(
+                                "cleanup hook failed for node '{node_name}': {error}"
+                            )),
+                        });
+                    }
+
+                    if let Err(error) =
+                        self.transitions
+                            .check(&node_name, &current_state, &new_state)
+                    {
+                        return Ok(FlowResult {
+                            final_state: current_state,
+                            context: if self.transactional {
+                                context
+                            } else {
+                                new_context
+                            },
+                            duration: start_time.elapsed(),
+                            steps,
+                            success: false,
+                            error: Some(error.to_string()),
+                        });
+                    }
                     context = new_context;
                     current_state = new_state;
                 }
                 Err(error) => {
-                    // We can't use context here since it was moved to node.execute
-                    // Create a new empty context for the error result
+                    let error = Self::cleanup_after_error(
+                        node.as_ref(),
+                        &context,
+                        &current_state,
+                        error.to_string(),
+                    )
+                    .await;
+
+                    // The node consumed its copy of the context, so the
+                    // error result carries the last good one
                     return Ok(FlowResult {
                         final_state: current_state,
-                        context: Context::new(),
+                        context,
                         duration: start_time.elapsed(),
                         steps,
                         success: false,
-                        error: Some(error.to_string()),
+                        error: Some(error),
                     });
                 }
             }
         }
     }
 
+    /// Run a node's cleanup hook on the error path, adding any failure to
+    /// the run's error.
+    async fn cleanup_after_error(
+        node: &dyn Node<State = S>,
+        context: &Context,
+        state: &S,
+        error: String,
+    ) -> String {
+        match node.cleanup(context, state).await {
+            Ok(()) => error,
+            Err(cleanup) => format!(
+                "{error}; cleanup hook failed for node '{}': {cleanup}",
+                node.name()
+            ),
+        }
+    }
+
     /// Get the flow name.
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    /// Build the static graph of this flow from the states its nodes emit.
+    pub fn graph(&self) -> FlowGraph<S> {
+        let table = self.transitions.enforced_table();
+        let mut graph = FlowGraph::new(&self.name, self.initial_state.clone());
+        for (state, node) in &self.nodes {
+            graph.add_handler(state.clone(), node.name());
+            graph.add_node_exits(state, node.as_ref(), EdgeKind::Node, table);
+        }
+        graph.finish()
+    }
+
+    /// Check the flow graph for structural issues.
+    ///
+    /// See [`AdvancedFlow::validate`](crate::flow_advanced::AdvancedFlow::validate).
+    pub fn validate(&self) -> GraphReport<S> {
+        self.graph().validate()
+    }
 }
 
 /// Builder for SimpleFlow.
@@ -108,6 +254,9 @@ pub struct SimpleFlowBuilder<S: FlowState> {
     nodes: HashMap<S, Arc<dyn Node<State = S>>>,
     initial_state: Option<S>,
     name: String,
+    transitions: TransitionRules<S>,
+    strict_graph: bool,
+    transactional: bool,
 }
 
 impl<S: FlowState> SimpleFlowBuilder<S> {
@@ -117,6 +266,9 @@ impl<S: FlowState> SimpleFlowBuilder<S> {
             nodes: HashMap::new(),
             initial_state: None,
             name: "simple_flow".to_string(),
+            transitions: TransitionRules::strict(),
+            strict_graph: false,
+            transactional: false,
         }
     }
 
@@ -138,6 +290,45 @@ impl<S: FlowState> SimpleFlowBuilder<S> {
         self
     }
 
+    /// Validate every transition returned by a node (enabled by default).
+    ///
+    /// Transitions are checked against [`FlowState::can_transition_to`], or
+    /// against the flow's transition table when one has been declared.
+    pub fn strict_transitions(mut self, strict: bool) -> Self {
+        self.transitions.strict = strict;
+        self
+    }
+
+    /// Declare an allowed transition in the flow's transition table.
+    pub fn allow_transition(mut self, from: S, to: S) -> Self {
+        self.transitions.allow(from, to);
+        self
+    }
+
+    /// Use a transition table instead of [`FlowState::can_transition_to`].
+    pub fn transition_table(mut self, table: TransitionTable<S>) -> Self {
+        self.transitions.table = Some(table);
+        self
+    }
+
+    /// Reject flows whose graph has structural issues when building.
+    pub fn strict_graph(mut self, strict: bool) -> Self {
+        self.strict_graph = strict;
+        self
+    }
+
+    /// Roll the context back to its state before the failing node when a
+    /// step fails.
+    ///
+    /// A node that returns an error never hands back its context, so failed
+    /// results always carry the context from before that node. In
+    /// transactional mode, writes of a node whose cleanup hook fails are
+    /// discarded too.
+    pub fn transactional(mut self, transactional: bool) -> Self {
+        self.transactional = transactional;
+        self
+    }
+
     /// Build the flow.
     pub fn build(self) -> Result<SimpleFlow<S>> {
         let initial_state = self
@@ -148,11 +339,18 @@ impl<S: FlowState> SimpleFlowBuilder<S> {
             return Err(FlowError::construction("No nodes added to flow"));
         }
 
-        Ok(SimpleFlow {
+        let flow = SimpleFlow {
             nodes: self.nodes,
             initial_state,
             name: self.name,
-        })
+            transitions: self.transitions,
+            transactional: self.transactional,
+        };
+
+        if self.strict_graph {
+            flow.validate().into_result(&flow.name)?;
+        }
+        Ok(flow)
     }
 }
 
@@ -212,6 +410,7 @@ mod tests {
         let flow = SimpleFlowBuilder::new()
             .initial_state(SimpleState::Processing)
             .node(SimpleState::Processing, looping)
+            .strict_transitions(false)
             .build()
             .unwrap();
 
@@ -221,4 +420,177 @@ mod tests {
         let msg = format!("{err}");
         assert!(msg.contains("maximum steps"));
     }
+
+    #[derive(Debug, Default)]
+    struct CountingHooks {
+        prepared: std::sync::atomic::AtomicUsize,
+        cleaned: std::sync::atomic::AtomicUsize,
+        fail_cleanup: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl Node for CountingHooks {
+        type State = SimpleState;
+
+        async fn prepare(&self, _context: &Context) -> Result<()> {
+            self.prepared
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn execute(&self, _context: Context) -> Result<(Context, Self::State)> {
+            Err(FlowError::context("execute failed"))
+        }
+
+        async fn cleanup(&self, _context: &Context, _state: &Self::State) -> Result<()> {
+            self.cleaned
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if self.fail_cleanup {
+                return Err(FlowError::context("teardown failed"));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn lifecycle_hooks_run_on_error_path() {
+        let node = Arc::new(CountingHooks::default());
+        let flow = SimpleFlowBuilder::new()
+            .initial_state(SimpleState::Start)
+            .node(SimpleState::Start, SharedNode(node.clone()))
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(node.prepared.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(node.cleaned.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // A failing cleanup is reported along with the original error
+        let node = Arc::new(CountingHooks {
+            fail_cleanup: true,
+            ..Default::default()
+        });
+        let flow = SimpleFlowBuilder::new()
+            .initial_state(SimpleState::Start)
+            .node(SimpleState::Start, SharedNode(node.clone()))
+            .build()
+            .unwrap();
+
+        let error = flow.execute(Context::new()).await.unwrap().error.unwrap();
+        assert!(error.starts_with("Context error: execute failed; cleanup hook failed"));
+        assert!(error.ends_with("Context error: teardown failed"));
+    }
+
+    #[derive(Debug)]
+    struct SharedNode(Arc<CountingHooks>);
+
+    #[async_trait::async_trait]
+    impl Node for SharedNode {
+        type State = SimpleState;
+
+        async fn prepare(&self, context: &Context) -> Result<()> {
+            self.0.prepare(context).await
+        }
+
+        async fn execute(&self, context: Context) -> Result<(Context, Self::State)> {
+            self.0.execute(context).await
+        }
+
+        async fn cleanup(&self, context: &Context, state: &Self::State) -> Result<()> {
+            self.0.cleanup(context, state).await
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_results_keep_last_good_context() {
+        let flow = SimpleFlowBuilder::new()
+            .initial_state(SimpleState::Start)
+            .node(
+                SimpleState::Start,
+                helpers::fn_node("load", |mut ctx: Context| async move {
+                    ctx.set("order", 42)?;
+                    Ok((ctx, SimpleState::Processing))
+                }),
+            )
+            .node(
+                SimpleState::Processing,
+                helpers::fn_node("charge", |mut ctx: Context| async move {
+                    ctx.set("charged", true)?;
+                    Err::<(Context, SimpleState), _>(FlowError::context("card declined"))
+                }),
+            )
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(result.final_state, SimpleState::Processing);
+        assert_eq!(result.context.get_json::<i32>("order").unwrap(), Some(42));
+        assert!(!result.context.contains_json("charged"));
+    }
+
+    #[tokio::test]
+    async fn illegal_transition_names_the_node() {
+        let flow = SimpleFlowBuilder::new()
+            .initial_state(SimpleState::Processing)
+            .node(
+                SimpleState::Processing,
+                helpers::passthrough("rewinder", SimpleState::Start),
+            )
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(result.final_state, SimpleState::Processing);
+        assert_eq!(result.steps, 1);
+        let error = FlowError::invalid_node_transition(
+            "rewinder",
+            &SimpleState::Processing,
+            &SimpleState::Start,
+        );
+        assert_eq!(result.error, Some(error.to_string()));
+    }
+
+    #[tokio::test]
+    async fn transition_table_overrides_state_rules() {
+        // SimpleState forbids Custom -> Success; the declared table allows it.
+        let flow = SimpleFlowBuilder::new()
+            .initial_state(SimpleState::Start)
+            .node(
+                SimpleState::Start,
+                helpers::passthrough("start", SimpleState::Custom("review".to_string())),
+            )
+            .node(
+                SimpleState::Custom("review".to_string()),
+                helpers::passthrough("review", SimpleState::Success),
+            )
+            .allow_transition(
+                SimpleState::Start,
+                SimpleState::Custom("review".to_string()),
+            )
+            .allow_transition(
+                SimpleState::Custom("review".to_string()),
+                SimpleState::Success,
+            )
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert_eq!(result.final_state, SimpleState::Success);
+
+        let rejected = SimpleFlowBuilder::new()
+            .initial_state(SimpleState::Start)
+            .node(
+                SimpleState::Start,
+                helpers::passthrough("start", SimpleState::Success),
+            )
+            .transition_table(
+                TransitionTable::new().allow(SimpleState::Start, SimpleState::Processing),
+            )
+            .build()
+            .unwrap();
+        assert!(!rejected.execute(Context::new()).await.unwrap().success);
+    }
 }
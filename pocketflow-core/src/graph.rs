@@ -0,0 +1,513 @@
+//! Static analysis of flow graphs.
+//!
+//! A [`FlowGraph`] describes the states a flow handles and the states each
+//! handler may move to, as declared by [`Node::emits`], routers, fallback
+//! policies and the flow's transition table. [`FlowGraph::validate`] checks
+//! the graph for missing handlers, unreachable states, states that can never
+//! reach a terminal state and cycles without an exit.
+//!
+//! Nodes that do not declare their exits are treated as able to move
+//! anywhere, so analysis never reports a problem it cannot prove.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{
+    error::{FlowError, Result},
+    node::Node,
+    state::{FlowState, TransitionTable},
+};
+
+/// What produced an edge of the graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    /// A state returned by the node handling the source state.
+    Node,
+    /// A branch of a conditional router.
+    Router,
+    /// A fallback state or a state returned by a fallback node.
+    Fallback,
+}
+
+/// A possible transition between two states.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphEdge<S> {
+    pub from: S,
+    pub to: S,
+    pub kind: EdgeKind,
+    /// Node name, router branch (`true`/`false`) or `fallback`.
+    pub label: String,
+}
+
+/// The states of a flow and the transitions between them.
+#[derive(Debug, Clone)]
+pub struct FlowGraph<S: FlowState> {
+    name: String,
+    initial_state: S,
+    states: Vec<S>,
+    handlers: HashMap<S, String>,
+    open: HashSet<S>,
+    edges: Vec<GraphEdge<S>>,
+}
+
+impl<S: FlowState> FlowGraph<S> {
+    pub(crate) fn new(name: impl Into<String>, initial_state: S) -> Self {
+        Self {
+            name: name.into(),
+            initial_state,
+            states: Vec::new(),
+            handlers: HashMap::new(),
+            open: HashSet::new(),
+            edges: Vec::new(),
+        }
+    }
+
+    pub(crate) fn add_handler(&mut self, state: S, name: impl Into<String>) {
+        self.handlers.insert(state, name.into());
+    }
+
+    pub(crate) fn add_edge(&mut self, from: S, to: S, kind: EdgeKind, label: impl Into<String>) {
+        let edge = GraphEdge {
+            from,
+            to,
+            kind,
+            label: label.into(),
+        };
+        if !self.edges.contains(&edge) {
+            self.edges.push(edge);
+        }
+    }
+
+    /// Add the exits of `node` running in `state`.
+    ///
+    /// Falls back to the transition table when the node does not declare its
+    /// exits, and marks the state as open when neither is available.
+    pub(crate) fn add_node_exits(
+        &mut self,
+        state: &S,
+        node: &dyn Node<State = S>,
+        kind: EdgeKind,
+        table: Option<&TransitionTable<S>>,
+    ) {
+        let targets = node.emits().or_else(|| {
+            table.map(|table| {
+                let mut targets: Vec<S> = table
+                    .iter()
+                    .filter(|(from, _)| *from == state)
+                    .map(|(_, to)| to.clone())
+                    .collect();
+                targets.sort_by_key(|target| format!("{target:?}"));
+                targets
+            })
+        });
+
+        match targets {
+            Some(targets) => {
+                let label = node.name();
+                for target in targets {
+                    self.add_edge(state.clone(), target, kind, label.clone());
+                }
+            }
+            None => {
+                self.open.insert(state.clone());
+            }
+        }
+    }
+
+    /// Fix a deterministic state and edge order: breadth-first from the
+    /// initial state, then any remaining states by name.
+    pub(crate) fn finish(mut self) -> Self {
+        let mut order: Vec<S> = Vec::new();
+        let mut seen: HashSet<S> = HashSet::new();
+        let mut queue = VecDeque::from([self.initial_state.clone()]);
+        seen.insert(self.initial_state.clone());
+        while let Some(state) = queue.pop_front() {
+            for edge in self.edges.iter().filter(|edge| edge.from == state) {
+                if seen.insert(edge.to.clone()) {
+                    queue.push_back(edge.to.clone());
+                }
+            }
+            order.push(state);
+        }
+
+        let mut rest: Vec<S> = self
+            .handlers
+            .keys()
+            .chain(self.edges.iter().map(|edge| &edge.to))
+            .filter(|state| !seen.contains(*state))
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        rest.sort_by_key(|state| format!("{state:?}"));
+        order.extend(rest);
+
+        let position: HashMap<&S, usize> = order.iter().enumerate().map(|(i, s)| (s, i)).collect();
+        let mut edges = std::mem::take(&mut self.edges);
+        edges.sort_by_key(|edge| position.get(&edge.from).copied().unwrap_or(usize::MAX));
+
+        self.edges = edges;
+        self.states = order;
+        self
+    }
+
+    /// Name of the flow the graph was built from.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The state a run starts in.
+    pub fn initial_state(&self) -> &S {
+        &self.initial_state
+    }
+
+    /// Every state handled by or reachable in the flow, initial state first.
+    pub fn states(&self) -> &[S] {
+        &self.states
+    }
+
+    /// Transitions in the graph, grouped by source state.
+    pub fn edges(&self) -> &[GraphEdge<S>] {
+        &self.edges
+    }
+
+    /// Name of the node or router handling `state`.
+    pub fn handler(&self, state: &S) -> Option<&str> {
+        self.handlers.get(state).map(String::as_str)
+    }
+
+    /// Whether the handler of `state` does not declare where it may go.
+    pub fn is_open(&self, state: &S) -> bool {
+        self.open.contains(state)
+    }
+
+    fn successors<'a>(&'a self, state: &'a S) -> impl Iterator<Item = &'a S> + 'a {
+        self.edges
+            .iter()
+            .filter(move |edge| &edge.from == state)
+            .map(|edge| &edge.to)
+    }
+
+    /// Check the graph for structural problems.
+    pub fn validate(&self) -> GraphReport<S> {
+        let mut issues = Vec::new();
+
+        let missing: HashSet<&S> = self
+            .states
+            .iter()
+            .filter(|state| !state.is_terminal() && !self.handlers.contains_key(*state))
+            .collect();
+        for state in &self.states {
+            if missing.contains(state) {
+                issues.push(GraphIssue::MissingHandler {
+                    state: state.clone(),
+                });
+            }
+        }
+
+        // An open handler may lead anywhere, so everything counts as reachable
+        let reachable = self.reachable();
+        if !reachable.iter().any(|state| self.open.contains(*state)) {
+            for state in &self.states {
+                if !reachable.contains(state) {
+                    issues.push(GraphIssue::Unreachable {
+                        state: state.clone(),
+                    });
+                }
+            }
+        }
+
+        // Missing handlers are already reported, so they don't poison their
+        // predecessors
+        let exits = self.can_exit(&missing);
+        let stuck: Vec<&S> = self
+            .states
+            .iter()
+            .filter(|state| !exits.contains(*state))
+            .collect();
+
+        let mut in_cycle: HashSet<&S> = HashSet::new();
+        for state in &stuck {
+            if in_cycle.contains(*state) {
+                continue;
+            }
+            let forward = self.reachable_from(state);
+            let component: Vec<S> = stuck
+                .iter()
+                .filter(|other| {
+                    forward.contains(**other) && self.reachable_from(other).contains(*state)
+                })
+                .map(|other| (*other).clone())
+                .collect();
+            if forward.contains(*state) {
+                in_cycle.extend(stuck.iter().filter(|other| component.contains(other)));
+                issues.push(GraphIssue::CycleWithoutExit { states: component });
+            }
+        }
+        for state in stuck {
+            if !in_cycle.contains(state) {
+                issues.push(GraphIssue::NoPathToTerminal {
+                    state: state.clone(),
+                });
+            }
+        }
+
+        GraphReport { issues }
+    }
+
+    fn reachable(&self) -> HashSet<&S> {
+        let mut reachable = self.reachable_from(&self.initial_state);
+        reachable.insert(&self.initial_state);
+        reachable
+    }
+
+    /// States reachable from `start` in one or more steps.
+    fn reachable_from<'a>(&'a self, start: &'a S) -> HashSet<&'a S> {
+        let mut seen = HashSet::new();
+        let mut queue: VecDeque<&S> = self.successors(start).collect();
+        while let Some(state) = queue.pop_front() {
+            if seen.insert(state) {
+                queue.extend(self.successors(state));
+            }
+        }
+        seen
+    }
+
+    /// States with a path to a terminal, open or `assumed` state.
+    fn can_exit<'a>(&'a self, assumed: &HashSet<&'a S>) -> HashSet<&'a S> {
+        let mut exits: HashSet<&S> = self
+            .states
+            .iter()
+            .filter(|state| state.is_terminal() || self.open.contains(*state))
+            .chain(assumed.iter().copied())
+            .collect();
+        loop {
+            let before = exits.len();
+            for edge in &self.edges {
+                if exits.contains(&edge.to) {
+                    exits.insert(&edge.from);
+                }
+            }
+            if exits.len() == before {
+                return exits;
+            }
+        }
+    }
+}
+
+/// A structural problem found by [`FlowGraph::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphIssue<S> {
+    /// A non-terminal state the flow can move to has no node or router.
+    MissingHandler { state: S },
+    /// A state that can never be reached from the initial state.
+    Unreachable { state: S },
+    /// A state from which no terminal state can be reached.
+    NoPathToTerminal { state: S },
+    /// States that only lead to each other.
+    CycleWithoutExit { states: Vec<S> },
+}
+
+impl<S: std::fmt::Debug> std::fmt::Display for GraphIssue<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphIssue::MissingHandler { state } => {
+                write!(f, "no node or router handles state {state:?}")
+            }
+            GraphIssue::Unreachable { state } => {
+                write!(f, "state {state:?} is unreachable from the initial state")
+            }
+            GraphIssue::NoPathToTerminal { state } => {
+                write!(f, "state {state:?} has no path to a terminal state")
+            }
+            GraphIssue::CycleWithoutExit { states } => {
+                write!(f, "states {states:?} form a cycle without exit")
+            }
+        }
+    }
+}
+
+/// Result of validating a [`FlowGraph`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphReport<S> {
+    issues: Vec<GraphIssue<S>>,
+}
+
+impl<S: std::fmt::Debug> GraphReport<S> {
+    /// Whether no issues were found.
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// The issues found, in state order.
+    pub fn issues(&self) -> &[GraphIssue<S>] {
+        &self.issues
+    }
+
+    /// Turn the report into a construction error naming `flow` if any
+    /// issues were found.
+    pub fn into_result(self, flow: &str) -> Result<()> {
+        if self.is_valid() {
+            Ok(())
+        } else {
+            Err(FlowError::construction(format!(
+                "Flow '{flow}' failed graph validation: {self}"
+            )))
+        }
+    }
+}
+
+impl<S: std::fmt::Debug> std::fmt::Display for GraphReport<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.issues.is_empty() {
+            return write!(f, "no issues");
+        }
+        for (i, issue) in self.issues.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{issue}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flow_advanced::AdvancedFlow, node::helpers, state::SimpleState};
+
+    fn custom(name: &str) -> SimpleState {
+        SimpleState::Custom(name.to_string())
+    }
+
+    #[test]
+    fn valid_flow_has_no_issues() {
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::passthrough("start", SimpleState::Processing),
+            )
+            .when_state(
+                SimpleState::Processing,
+                |_, _| true,
+                SimpleState::Success,
+                SimpleState::Error,
+            )
+            .strict_graph(true)
+            .build()
+            .unwrap();
+
+        let graph = flow.graph();
+        assert_eq!(
+            graph.states(),
+            &[
+                SimpleState::Start,
+                SimpleState::Processing,
+                SimpleState::Success,
+                SimpleState::Error
+            ]
+        );
+        assert_eq!(
+            graph.handler(&SimpleState::Processing),
+            Some("conditional_router")
+        );
+        assert!(graph.validate().is_valid());
+    }
+
+    #[test]
+    fn reports_every_kind_of_issue() {
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::conditional("split", |_| true, custom("loop_a"), custom("missing")),
+            )
+            .on_state(
+                custom("loop_a"),
+                helpers::passthrough("a", custom("loop_b")),
+            )
+            .on_state(
+                custom("loop_b"),
+                helpers::passthrough("b", custom("loop_a")),
+            )
+            .on_state(
+                custom("orphan"),
+                helpers::passthrough("orphan", SimpleState::Success),
+            )
+            .build()
+            .unwrap();
+
+        let issues = flow.validate().issues().to_vec();
+        assert!(issues.contains(&GraphIssue::MissingHandler {
+            state: custom("missing")
+        }));
+        assert!(issues.contains(&GraphIssue::Unreachable {
+            state: custom("orphan")
+        }));
+        assert!(issues.contains(&GraphIssue::CycleWithoutExit {
+            states: vec![custom("loop_a"), custom("loop_b")]
+        }));
+        // The start state can still end up in the missing state, which is
+        // reported on its own
+        assert!(!issues.contains(&GraphIssue::NoPathToTerminal {
+            state: SimpleState::Start
+        }));
+    }
+
+    #[test]
+    fn strict_build_rejects_invalid_graph_and_open_nodes_are_trusted() {
+        let err = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::passthrough("start", SimpleState::Processing),
+            )
+            .strict_graph(true)
+            .build()
+            .err()
+            .expect("graph should be rejected");
+        assert!(
+            err.to_string()
+                .contains("no node or router handles state Processing")
+        );
+
+        // A node that doesn't declare its exits may go anywhere
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::fn_node("opaque", |context| async move {
+                    Ok((context, SimpleState::Success))
+                }),
+            )
+            .on_state(
+                custom("later"),
+                helpers::passthrough("later", SimpleState::Success),
+            )
+            .strict_graph(true)
+            .build()
+            .unwrap();
+        assert!(flow.graph().is_open(&SimpleState::Start));
+
+        // A transition table bounds undeclared exits
+        let err = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::fn_node("opaque", |context| async move {
+                    Ok((context, SimpleState::Success))
+                }),
+            )
+            .on_state(
+                custom("later"),
+                helpers::passthrough("later", SimpleState::Success),
+            )
+            .allow_transition(SimpleState::Start, SimpleState::Success)
+            .strict_graph(true)
+            .build()
+            .err()
+            .expect("graph should be rejected");
+        assert!(err.to_string().contains("unreachable"));
+    }
+}
@@ -0,0 +1,281 @@
+//! Nodes that suspend a flow until a human (or another system) answers.
+//!
+//! A [`WaitForInputNode`] stops the run with a [`PendingInput`] request
+//! instead of returning a next state. [`AdvancedFlow`] checkpoints the run with
+//! the request in its context and returns; the caller shows the prompt and
+//! later hands the answer to [`AdvancedFlow::submit_input`], which stores it
+//! under the node's key and resumes the run:
+//!
+//! ```rust,ignore
+//! let flow = AdvancedFlow::builder()
+//!     .initial_state(MyState::Review)
+//!     .on_state(
+//!         MyState::Review,
+//!         WaitForInputNode::new("approval", "approved", "Ship this order?", MyState::Ship)
+//!             .timeout(Duration::from_secs(24 * 3600), MyState::Escalate),
+//!     )
+//!     .checkpoint_store(store)
+//!     .build()?;
+//!
+//! let result = flow.execute_with_run_id("order-42", context).await?;
+//! let pending = result.pending_input().unwrap(); // show pending.prompt
+//!
+//! // Later, possibly in another process
+//! let result = flow.submit_input("order-42", json!(true)).await?;
+//! ```
+//!
+//! Requests past their deadline are timed out by
+//! [`AdvancedFlow::expire_pending_inputs`], which resumes those runs in the
+//! node's timeout state.
+//!
+//! [`AdvancedFlow`]: crate::flow_advanced::AdvancedFlow
+//! [`AdvancedFlow::submit_input`]: crate::flow_advanced::AdvancedFlow::submit_input
+//! [`AdvancedFlow::expire_pending_inputs`]: crate::flow_advanced::AdvancedFlow::expire_pending_inputs
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    context::Context,
+    error::{FlowError, Result},
+    keys::ContextKey,
+    node::Node,
+    state::FlowState,
+};
+
+/// Context key holding the request a suspended run is waiting on.
+pub const PENDING_INPUT: ContextKey<PendingInput> = ContextKey::new("pocketflow.pending_input");
+
+/// Context key holding an answer submitted for the pending request.
+pub(crate) const SUBMITTED_INPUT: ContextKey<Value> = ContextKey::new("pocketflow.submitted_input");
+
+/// Input a suspended run is waiting for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingInput {
+    /// Node that asked for the input.
+    pub node: String,
+    /// Context key the answer is stored under.
+    pub key: String,
+    /// Question to show whoever answers.
+    pub prompt: String,
+    /// JSON schema describing the expected answer, for clients to render
+    /// and validate the input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Value>,
+    /// When the request times out, if it does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<DateTime<Utc>>,
+    pub requested_at: DateTime<Utc>,
+}
+
+impl PendingInput {
+    /// Whether the deadline has passed.
+    pub fn is_expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| deadline <= Utc::now())
+    }
+}
+
+/// Node that suspends the flow until input is submitted for it.
+///
+/// On its first execution the node fails with [`FlowError::Suspended`], which
+/// [`AdvancedFlow`](crate::flow_advanced::AdvancedFlow) turns into a
+/// checkpointed, suspended run. When the run is resumed with an answer, the
+/// answer is stored under the node's key and the flow moves to the next state;
+/// when it is resumed after the deadline, it moves to the timeout state.
+/// Suspending requires the flow to have a checkpoint store.
+#[derive(Debug, Clone)]
+pub struct WaitForInputNode<S: FlowState> {
+    name: String,
+    key: String,
+    prompt: String,
+    schema: Option<Value>,
+    next_state: S,
+    timeout: Option<(Duration, S)>,
+}
+
+impl<S: FlowState> WaitForInputNode<S> {
+    /// Ask for input stored under `key`, then continue in `next_state`.
+    pub fn new(
+        name: impl Into<String>,
+        key: impl Into<String>,
+        prompt: impl Into<String>,
+        next_state: S,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            key: key.into(),
+            prompt: prompt.into(),
+            schema: None,
+            next_state,
+            timeout: None,
+        }
+    }
+
+    /// Describe the expected answer with a JSON schema.
+    pub fn schema(mut self, schema: Value) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Move to `state` if no input arrives within `timeout`.
+    pub fn timeout(mut self, timeout: Duration, state: S) -> Self {
+        self.timeout = Some((timeout, state));
+        self
+    }
+
+    fn request(&self) -> PendingInput {
+        let requested_at = Utc::now();
+        PendingInput {
+            node: self.name.clone(),
+            key: self.key.clone(),
+            prompt: self.prompt.clone(),
+            schema: self.schema.clone(),
+            deadline: self
+                .timeout
+                .as_ref()
+                .and_then(|(timeout, _)| chrono::Duration::from_std(*timeout).ok())
+                .map(|timeout| requested_at + timeout),
+            requested_at,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: FlowState> Node for WaitForInputNode<S> {
+    type State = S;
+
+    async fn execute(&self, mut context: Context) -> Result<(Context, Self::State)> {
+        // A request from another node is stale, so ask again
+        let pending = context
+            .fetch(&PENDING_INPUT)?
+            .filter(|pending| pending.node == self.name);
+        let Some(pending) = pending else {
+            return Err(FlowError::Suspended(Box::new(self.request())));
+        };
+
+        if let Some(answer) = context.take(&SUBMITTED_INPUT)? {
+            context.take(&PENDING_INPUT)?;
+            context.set(self.key.clone(), answer)?;
+            return Ok((context, self.next_state.clone()));
+        }
+
+        if let Some((_, timeout_state)) = &self.timeout
+            && pending.is_expired()
+        {
+            context.take(&PENDING_INPUT)?;
+            return Ok((context, timeout_state.clone()));
+        }
+
+        Err(FlowError::Suspended(Box::new(pending)))
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn emits(&self) -> Option<Vec<Self::State>> {
+        let mut states = vec![self.next_state.clone()];
+        states.extend(self.timeout.as_ref().map(|(_, state)| state.clone()));
+        Some(states)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        checkpoint::{CheckpointStore, InMemoryCheckpointStore},
+        flow_advanced::AdvancedFlow,
+        node::PassthroughNode,
+        state::SimpleState,
+    };
+
+    fn approval_flow(
+        store: Arc<dyn CheckpointStore>,
+        timeout: Duration,
+    ) -> AdvancedFlow<SimpleState> {
+        AdvancedFlow::builder()
+            .name("approval")
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                PassthroughNode::new("draft", SimpleState::Processing),
+            )
+            .on_state(
+                SimpleState::Processing,
+                WaitForInputNode::new("review", "approved", "Ship it?", SimpleState::Success)
+                    .schema(json!({ "type": "boolean" }))
+                    .timeout(timeout, SimpleState::Error),
+            )
+            .checkpoint_store(store)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn submitted_input_resumes_the_run() {
+        let store: Arc<dyn CheckpointStore> = Arc::new(InMemoryCheckpointStore::new());
+        let flow = approval_flow(store.clone(), Duration::from_secs(3600));
+
+        let result = flow
+            .execute_with_run_id("order-42", Context::new())
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert_eq!(result.final_state, SimpleState::Processing);
+        let pending = result.pending_input().unwrap();
+        assert_eq!(pending.prompt, "Ship it?");
+        assert_eq!(pending.schema, Some(json!({ "type": "boolean" })));
+        assert!(!pending.is_expired());
+
+        // Resuming without an answer keeps waiting on the same request
+        let again = flow.resume("order-42").await.unwrap();
+        assert_eq!(again.pending_input(), Some(pending.clone()));
+        assert!(flow.expire_pending_inputs().await.unwrap().is_empty());
+
+        // A fresh flow instance, as after a restart
+        let flow = approval_flow(store, Duration::from_secs(3600));
+        assert_eq!(flow.pending_input("order-42").await.unwrap(), Some(pending));
+        let result = flow.submit_input("order-42", json!(true)).await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.final_state, SimpleState::Success);
+        assert_eq!(
+            result.context.get_json::<bool>("approved").unwrap(),
+            Some(true)
+        );
+        assert!(result.pending_input().is_none());
+        assert!(!result.context.contains_json(SUBMITTED_INPUT.name()));
+
+        let error = flow
+            .submit_input("order-42", json!(false))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("not waiting for input"));
+    }
+
+    #[tokio::test]
+    async fn expired_requests_move_to_the_timeout_state() {
+        let store: Arc<dyn CheckpointStore> = Arc::new(InMemoryCheckpointStore::new());
+        let flow = approval_flow(store, Duration::ZERO);
+
+        let result = flow
+            .execute_with_run_id("late", Context::new())
+            .await
+            .unwrap();
+        assert!(result.pending_input().unwrap().is_expired());
+
+        let results = flow.expire_pending_inputs().await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].final_state, SimpleState::Error);
+        assert!(results[0].context.get_raw("approved").is_none());
+        assert!(flow.pending_input("late").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,237 @@
+//! Typed context keys.
+//!
+//! A [`ContextKey`] ties a JSON context key to the type stored under it, so
+//! crates can publish their keys as constants instead of magic strings:
+//!
+//! ```rust
+//! use pocketflow_core::prelude::*;
+//!
+//! const RETRIES: ContextKey<u32> = ContextKey::new("retries");
+//!
+//! let mut context = Context::new();
+//! context.put(&RETRIES, 3)?;
+//! assert_eq!(context.fetch(&RETRIES)?, Some(3));
+//! # Ok::<(), FlowError>(())
+//! ```
+//!
+//! With the `schema` feature, a key's JSON Schema can be registered from its
+//! type's [`schemars::JsonSchema`] implementation; [`Context::put`] then
+//! rejects values that don't match it.
+
+use std::{borrow::Borrow, fmt, marker::PhantomData};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{context::Context, error::Result};
+
+/// A JSON context key holding values of type `T`.
+pub struct ContextKey<T> {
+    name: &'static str,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> ContextKey<T> {
+    /// Declare a key.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            _type: PhantomData,
+        }
+    }
+
+    /// The underlying string key.
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<T> Clone for ContextKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ContextKey<T> {}
+
+impl<T> fmt::Debug for ContextKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ContextKey")
+            .field(&self.name)
+            .field(&std::any::type_name::<T>())
+            .finish()
+    }
+}
+
+impl<T> fmt::Display for ContextKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl Context {
+    /// Store a value under a typed key.
+    ///
+    /// With the `schema` feature, the value is validated against the key's
+    /// registered schema, if any.
+    pub fn put<T: Serialize>(&mut self, key: &ContextKey<T>, value: impl Borrow<T>) -> Result<()> {
+        let value = serde_json::to_value(value.borrow())?;
+        #[cfg(feature = "schema")]
+        schema::validate(key.name, &value)?;
+        self.set(key.name, value)
+    }
+
+    /// Read the value stored under a typed key.
+    pub fn fetch<T: DeserializeOwned>(&self, key: &ContextKey<T>) -> Result<Option<T>> {
+        self.get_json(key.name)
+    }
+
+    /// Remove and return the value stored under a typed key.
+    pub fn take<T: DeserializeOwned>(&mut self, key: &ContextKey<T>) -> Result<Option<T>> {
+        self.remove_json(key.name)
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Check whether a value is stored under a typed key.
+    pub fn has<T>(&self, key: &ContextKey<T>) -> bool {
+        self.contains_json(key.name)
+    }
+}
+
+#[cfg(feature = "schema")]
+pub use schema::unregister_schema;
+
+#[cfg(feature = "schema")]
+mod schema {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, OnceLock, RwLock},
+    };
+
+    use serde_json::Value;
+
+    use super::ContextKey;
+    use crate::error::{FlowError, Result};
+
+    type Schemas = RwLock<HashMap<&'static str, Arc<jsonschema::Validator>>>;
+
+    fn schemas() -> &'static Schemas {
+        static SCHEMAS: OnceLock<Schemas> = OnceLock::new();
+        SCHEMAS.get_or_init(Default::default)
+    }
+
+    impl<T: schemars::JsonSchema> ContextKey<T> {
+        /// Register the JSON Schema of `T` for this key.
+        ///
+        /// Registration is process-wide: every later [`Context::put`]
+        /// (crate::context::Context::put) with a key of the same name is
+        /// validated.
+        pub fn register_schema(&self) -> Result<()> {
+            let schema = serde_json::to_value(schemars::schema_for!(T))?;
+            self.register_raw_schema(&schema)
+        }
+    }
+
+    impl<T> ContextKey<T> {
+        /// Register a hand-written JSON Schema for this key.
+        pub fn register_raw_schema(&self, schema: &Value) -> Result<()> {
+            let validator = jsonschema::Validator::new(schema).map_err(|error| {
+                FlowError::construction(format!(
+                    "Invalid JSON Schema for context key '{}': {error}",
+                    self.name
+                ))
+            })?;
+            schemas()
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .insert(self.name, Arc::new(validator));
+            Ok(())
+        }
+    }
+
+    /// Remove the schema registered for `name`, if any.
+    pub fn unregister_schema(name: &str) {
+        schemas()
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .remove(name);
+    }
+
+    pub(super) fn validate(name: &str, value: &Value) -> Result<()> {
+        let Some(validator) = schemas()
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(name)
+            .cloned()
+        else {
+            return Ok(());
+        };
+
+        let errors: Vec<String> = validator
+            .iter_errors(value)
+            .map(|error| error.to_string())
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(FlowError::context(format!(
+                "Value for context key '{name}' does not match its schema: {}",
+                errors.join("; ")
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+    struct Order {
+        id: u64,
+        items: Vec<String>,
+    }
+
+    const ORDER: ContextKey<Order> = ContextKey::new("order");
+
+    #[test]
+    fn typed_keys_round_trip() {
+        let order = Order {
+            id: 7,
+            items: vec!["book".to_string()],
+        };
+
+        let mut context = Context::new();
+        assert!(!context.has(&ORDER));
+        context.put(&ORDER, &order).unwrap();
+        assert_eq!(context.fetch(&ORDER).unwrap(), Some(order.clone()));
+        // Typed keys are ordinary JSON keys underneath
+        assert_eq!(context.get_raw("order").unwrap()["id"], 7);
+
+        assert_eq!(context.take(&ORDER).unwrap(), Some(order));
+        assert!(!context.has(&ORDER));
+        assert_eq!(format!("{ORDER}"), "order");
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn registered_schema_rejects_invalid_writes() {
+        const LIMIT: ContextKey<u8> = ContextKey::new("keys_test_limit");
+        const LOOSE: ContextKey<serde_json::Value> = ContextKey::new("keys_test_limit");
+
+        LIMIT.register_schema().unwrap();
+        let mut context = Context::new();
+        context.put(&LIMIT, 10).unwrap();
+
+        let err = context.put(&LOOSE, serde_json::json!("ten")).unwrap_err();
+        assert!(err.to_string().contains("does not match its schema"));
+        assert_eq!(context.fetch(&LIMIT).unwrap(), Some(10));
+
+        unregister_schema(LIMIT.name());
+        context.put(&LOOSE, serde_json::json!("ten")).unwrap();
+    }
+}
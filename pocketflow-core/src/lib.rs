@@ -31,13 +31,33 @@
 //! }
 //! ```
 
+pub mod analytics;
+pub mod batch;
+pub mod checkpoint;
 pub mod context;
 pub mod error;
+pub mod events;
 pub mod flow;
 pub mod flow_advanced;
 pub mod flow_simple;
+pub mod graph;
+pub mod input;
+pub mod keys;
+pub mod merge;
+pub mod metrics;
+pub mod middleware;
 pub mod node;
+pub mod parallel;
+pub mod persist;
+pub mod policy;
+pub mod replay;
+pub mod runner;
+pub mod shared;
+mod spans;
+pub mod spec;
 pub mod state;
+pub mod subflow;
+pub mod visualize;
 
 /// Convenient re-exports for common use.
 pub mod prelude {
@@ -46,15 +66,33 @@ pub mod prelude {
     pub use eyre;
     pub use serde::{Deserialize, Serialize};
     pub use tokio;
+    pub use tokio_util::sync::CancellationToken;
 
     pub use crate::{
-        context::{Context, ContextBuilder},
+        analytics::{AnalyticsSink, FlowAnalytics, JsonlAnalyticsSink, LatencyStats},
+        batch::{ItemError, MapBatchNode, PartialFailurePolicy},
+        checkpoint::{Checkpoint, CheckpointStore, FileCheckpointStore, InMemoryCheckpointStore},
+        context::{Context, ContextBuilder, ContextDiff, ContextSnapshot},
         error::{FlowError, Result},
+        events::{FlowEvent, FlowObserver},
         flow::{FlowResult, SimpleFlow, SimpleFlowBuilder},
         flow_advanced::{
-            AdvancedFlow, AdvancedFlowBuilder, AdvancedFlowResult, FlowRegistry, SharedFlowState,
+            AdvancedFlow, AdvancedFlowBuilder, AdvancedFlowResult, FlowRegistry, NestedStep,
         },
-        node::{BatchNode, ConditionalNode, FnNode, Node, PassthroughNode},
-        state::{FlowState, SimpleState},
+        graph::{FlowGraph, GraphIssue, GraphReport},
+        input::{PendingInput, WaitForInputNode},
+        keys::ContextKey,
+        merge::{MergeConflict, MergePolicy, MergeReport, MergeScope, MergeStrategy},
+        middleware::{FlowMiddleware, MiddlewareAction, NodeInfo},
+        node::{BatchNode, CompensatedNode, ConditionalNode, FnNode, Node, PassthroughNode},
+        parallel::{BranchOutcome, ConflictPolicy, ParallelNode},
+        persist::{PersistedContext, TypeRegistry},
+        policy::{Backoff, Fallback, NodePolicy, RetryPolicy},
+        replay::Recording,
+        runner::{FlowRunner, RunStatus},
+        shared::SharedFlowState,
+        spec::{FlowSpec, NodeRegistry, SpecState},
+        state::{FlowState, NamedState, SimpleState, TransitionTable},
+        subflow::SubFlowNode,
     };
 }
@@ -0,0 +1,442 @@
+//! Strategies for merging one context into another.
+//!
+//! [`Context::merge_with`] merges JSON data, metadata and typed entries
+//! according to a [`MergePolicy`], which picks a [`MergeStrategy`] per key
+//! prefix:
+//!
+//! ```rust
+//! use pocketflow_core::prelude::*;
+//! use serde_json::json;
+//!
+//! let mut context = Context::new();
+//! context.set("log", json!(["started"]))?;
+//! context.set("user", json!({"name": "Ada"}))?;
+//!
+//! let mut branch = Context::new();
+//! branch.set("log", json!(["charged"]))?;
+//! branch.set("user", json!({"plan": "pro"}))?;
+//!
+//! let policy = MergePolicy::new(MergeStrategy::DeepMerge).prefix("log", MergeStrategy::ArrayAppend);
+//! let report = context.merge_with(&branch, &policy)?;
+//!
+//! assert!(report.conflicts.is_empty());
+//! assert_eq!(context.get_raw("log"), Some(&json!(["started", "charged"])));
+//! assert_eq!(context.get_raw("user"), Some(&json!({"name": "Ada", "plan": "pro"})));
+//! # Ok::<(), FlowError>(())
+//! ```
+
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    context::Context,
+    error::{FlowError, Result},
+};
+
+/// How to combine an existing value with a different incoming one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// The incoming value replaces the existing one.
+    #[default]
+    Overwrite,
+    /// The existing value is kept.
+    KeepExisting,
+    /// Objects are merged key by key, recursively. Nested keys are addressed
+    /// as `key.field` when looking up their strategy.
+    DeepMerge,
+    /// Incoming array items are appended to the existing array.
+    ArrayAppend,
+    /// Incoming array items that aren't already present are appended.
+    ArrayUnion,
+    /// A conflicting value fails the whole merge.
+    ErrorOnConflict,
+}
+
+impl MergeStrategy {
+    /// Whether a conflict under this strategy keeps the existing value.
+    pub(crate) fn keeps_existing(self) -> bool {
+        matches!(
+            self,
+            MergeStrategy::KeepExisting | MergeStrategy::ErrorOnConflict
+        )
+    }
+}
+
+/// Merge strategies by key prefix.
+///
+/// The longest matching prefix wins; keys no prefix matches use the default
+/// strategy, as do typed entries.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergePolicy {
+    pub(crate) default: MergeStrategy,
+    prefixes: Vec<(String, MergeStrategy)>,
+}
+
+impl MergePolicy {
+    /// Create a policy using `default` for every key.
+    pub fn new(default: MergeStrategy) -> Self {
+        Self {
+            default,
+            prefixes: Vec::new(),
+        }
+    }
+
+    /// Use `strategy` for keys starting with `prefix`.
+    pub fn prefix(mut self, prefix: impl Into<String>, strategy: MergeStrategy) -> Self {
+        let prefix = prefix.into();
+        self.prefixes.retain(|(existing, _)| *existing != prefix);
+        self.prefixes.push((prefix, strategy));
+        self
+    }
+
+    /// The strategy applied to `key`.
+    pub fn strategy_for(&self, key: &str) -> MergeStrategy {
+        self.prefixes
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, strategy)| *strategy)
+    }
+
+    /// Combine two different values for `key`, recording any conflict.
+    fn resolve(
+        &self,
+        scope: MergeScope,
+        key: &str,
+        existing: &Value,
+        incoming: &Value,
+        conflicts: &mut Vec<MergeConflict>,
+    ) -> Value {
+        let strategy = self.strategy_for(key);
+        match (strategy, existing, incoming) {
+            (MergeStrategy::DeepMerge, Value::Object(existing), Value::Object(incoming)) => {
+                let mut merged = existing.clone();
+                for (field, value) in incoming {
+                    let path = format!("{key}.{field}");
+                    let value = match existing.get(field) {
+                        None => value.clone(),
+                        Some(current) if current == value => continue,
+                        Some(current) => self.resolve(scope, &path, current, value, conflicts),
+                    };
+                    merged.insert(field.clone(), value);
+                }
+                Value::Object(merged)
+            }
+            (MergeStrategy::ArrayAppend, Value::Array(existing), Value::Array(incoming)) => {
+                Value::Array(existing.iter().chain(incoming).cloned().collect())
+            }
+            (MergeStrategy::ArrayUnion, Value::Array(existing), Value::Array(incoming)) => {
+                let mut merged = existing.clone();
+                for item in incoming {
+                    if !merged.contains(item) {
+                        merged.push(item.clone());
+                    }
+                }
+                Value::Array(merged)
+            }
+            _ => {
+                conflicts.push(MergeConflict {
+                    scope,
+                    key: key.to_string(),
+                    strategy,
+                    existing: Some(existing.clone()),
+                    incoming: Some(incoming.clone()),
+                });
+                if strategy.keeps_existing() {
+                    existing.clone()
+                } else {
+                    incoming.clone()
+                }
+            }
+        }
+    }
+
+    /// The part of `incoming` that changed relative to `base`, as far as the
+    /// strategy for `key` combines parts: the items appended to an
+    /// [`MergeStrategy::ArrayAppend`] array and the changed fields of a
+    /// [`MergeStrategy::DeepMerge`] object. Any other value is returned whole.
+    pub(crate) fn changes_since(&self, key: &str, base: &Value, incoming: &Value) -> Value {
+        match (self.strategy_for(key), base, incoming) {
+            (MergeStrategy::ArrayAppend, Value::Array(base), Value::Array(incoming))
+                if incoming.starts_with(base) =>
+            {
+                Value::Array(incoming[base.len()..].to_vec())
+            }
+            (MergeStrategy::DeepMerge, Value::Object(base), Value::Object(incoming)) => incoming
+                .iter()
+                .filter_map(|(field, value)| {
+                    let value = match base.get(field) {
+                        None => value.clone(),
+                        Some(current) if current == value => return None,
+                        Some(current) => {
+                            self.changes_since(&format!("{key}.{field}"), current, value)
+                        }
+                    };
+                    Some((field.clone(), value))
+                })
+                .collect(),
+            _ => incoming.clone(),
+        }
+    }
+
+    /// Combine two different values for `key`, failing on a conflict under
+    /// [`MergeStrategy::ErrorOnConflict`].
+    pub(crate) fn merge_values(
+        &self,
+        scope: MergeScope,
+        key: &str,
+        existing: &Value,
+        incoming: &Value,
+    ) -> Result<Value> {
+        let mut conflicts = Vec::new();
+        let merged = self.resolve(scope, key, existing, incoming, &mut conflicts);
+        check_conflicts(&conflicts)?;
+        Ok(merged)
+    }
+}
+
+/// Part of a context a merge conflict occurred in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeScope {
+    /// JSON data.
+    Data,
+    /// Metadata.
+    Metadata,
+    /// Typed entries, keyed by type name.
+    Typed,
+}
+
+impl fmt::Display for MergeScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MergeScope::Data => "data",
+            MergeScope::Metadata => "metadata",
+            MergeScope::Typed => "typed",
+        })
+    }
+}
+
+/// A key both contexts held with different values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MergeConflict {
+    pub scope: MergeScope,
+    /// The key, `key.field` for nested fields of a deep merge, or the type
+    /// name of a typed entry.
+    pub key: String,
+    /// Strategy that resolved the conflict.
+    pub strategy: MergeStrategy,
+    /// Existing JSON value (`None` for typed entries).
+    pub existing: Option<Value>,
+    /// Incoming JSON value (`None` for typed entries).
+    pub incoming: Option<Value>,
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} key '{}'", self.scope, self.key)?;
+        if let (Some(existing), Some(incoming)) = (&self.existing, &self.incoming) {
+            write!(f, " ({existing} vs {incoming})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of [`Context::merge_with`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MergeReport {
+    /// Conflicts resolved by the policy, in key order.
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl MergeReport {
+    /// Whether no key conflicted.
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+fn check_conflicts(conflicts: &[MergeConflict]) -> Result<()> {
+    let fatal: Vec<String> = conflicts
+        .iter()
+        .filter(|conflict| conflict.strategy == MergeStrategy::ErrorOnConflict)
+        .map(ToString::to_string)
+        .collect();
+    if fatal.is_empty() {
+        Ok(())
+    } else {
+        Err(FlowError::context(format!(
+            "Merge conflict on {}",
+            fatal.join(", ")
+        )))
+    }
+}
+
+fn merge_map(
+    policy: &MergePolicy,
+    scope: MergeScope,
+    existing: &std::collections::HashMap<String, Value>,
+    incoming: &std::collections::HashMap<String, Value>,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Map<String, Value> {
+    let incoming: BTreeMap<_, _> = incoming.iter().collect();
+    let mut merged = Map::new();
+    for (key, value) in incoming {
+        let value = match existing.get(key) {
+            None => value.clone(),
+            Some(current) if current == value => continue,
+            Some(current) => policy.resolve(scope, key, current, value, conflicts),
+        };
+        merged.insert(key.clone(), value);
+    }
+    merged
+}
+
+impl Context {
+    /// Merge another context into this one according to `policy`.
+    ///
+    /// Typed entries are shared with `other` and resolved with the policy's
+    /// default strategy, since they have no keys. On a conflict under
+    /// [`MergeStrategy::ErrorOnConflict`] the context is left unchanged.
+    pub fn merge_with(&mut self, other: &Context, policy: &MergePolicy) -> Result<MergeReport> {
+        let mut conflicts = Vec::new();
+        let data = merge_map(
+            policy,
+            MergeScope::Data,
+            self.json_data(),
+            other.json_data(),
+            &mut conflicts,
+        );
+        let metadata = merge_map(
+            policy,
+            MergeScope::Metadata,
+            self.metadata(),
+            other.metadata(),
+            &mut conflicts,
+        );
+
+        let mut typed = Vec::new();
+        let mut typed_conflicts = Vec::new();
+        for (type_id, entry) in other.typed_entries() {
+            match self.typed_entry(type_id) {
+                None => typed.push((*type_id, entry.clone())),
+                Some(current) if Arc::ptr_eq(&current.value, &entry.value) => {}
+                Some(_) => {
+                    typed_conflicts.push(MergeConflict {
+                        scope: MergeScope::Typed,
+                        key: entry.type_name.to_string(),
+                        strategy: policy.default,
+                        existing: None,
+                        incoming: None,
+                    });
+                    if !policy.default.keeps_existing() {
+                        typed.push((*type_id, entry.clone()));
+                    }
+                }
+            }
+        }
+        typed_conflicts.sort_by(|a, b| a.key.cmp(&b.key));
+        conflicts.extend(typed_conflicts);
+
+        check_conflicts(&conflicts)?;
+
+        for (key, value) in data {
+            self.set(key, value)?;
+        }
+        for (key, value) in metadata {
+            self.set_metadata(key, value)?;
+        }
+        for (type_id, entry) in typed {
+            self.insert_entry(type_id, entry);
+        }
+        Ok(MergeReport { conflicts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn strategies_apply_by_longest_prefix() {
+        let mut context = Context::new();
+        context.set("tags", json!(["a", "b"])).unwrap();
+        context.set("tags_log", json!(["a"])).unwrap();
+        context
+            .set(
+                "profile",
+                json!({"name": "Ada", "langs": ["en"], "age": 36}),
+            )
+            .unwrap();
+        context.set("status", "draft").unwrap();
+        context.insert(1u8).unwrap();
+
+        let mut other = Context::new();
+        other.set("tags", json!(["b", "c"])).unwrap();
+        other.set("tags_log", json!(["a"])).unwrap();
+        other
+            .set("profile", json!({"langs": ["fr"], "age": 37}))
+            .unwrap();
+        other.set("status", "final").unwrap();
+        other.insert(2u8).unwrap();
+        other.insert("typed".to_string()).unwrap();
+
+        let policy = MergePolicy::new(MergeStrategy::KeepExisting)
+            .prefix("tags", MergeStrategy::ArrayUnion)
+            .prefix("profile", MergeStrategy::DeepMerge)
+            .prefix("profile.langs", MergeStrategy::ArrayAppend);
+        let report = context.merge_with(&other, &policy).unwrap();
+
+        assert_eq!(context.get_raw("tags"), Some(&json!(["a", "b", "c"])));
+        assert_eq!(context.get_raw("tags_log"), Some(&json!(["a"])));
+        assert_eq!(
+            context.get_raw("profile"),
+            Some(&json!({"name": "Ada", "langs": ["en", "fr"], "age": 37}))
+        );
+        assert_eq!(
+            context.get_json::<String>("status").unwrap().unwrap(),
+            "draft"
+        );
+        // Typed entries follow the default strategy
+        assert_eq!(context.get::<u8>(), Some(&1));
+        assert_eq!(context.get::<String>().map(String::as_str), Some("typed"));
+
+        let keys: Vec<_> = report
+            .conflicts
+            .iter()
+            .map(|conflict| (conflict.scope, conflict.key.as_str()))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (MergeScope::Data, "profile.age"),
+                (MergeScope::Data, "status"),
+                (MergeScope::Typed, "u8"),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_on_conflict_leaves_context_unchanged() {
+        let mut context = Context::new();
+        context.set("total", 10).unwrap();
+
+        let mut other = Context::new();
+        other.set("total", 12).unwrap();
+        other.set("currency", "EUR").unwrap();
+
+        let policy = MergePolicy::default().prefix("total", MergeStrategy::ErrorOnConflict);
+        let err = context.merge_with(&other, &policy).unwrap_err();
+        assert!(err.to_string().contains("data key 'total' (10 vs 12)"));
+        assert!(!context.contains_json("currency"));
+
+        // The plain merge overwrites
+        context.merge(&other);
+        assert_eq!(context.get_json::<i32>("total").unwrap(), Some(12));
+    }
+}
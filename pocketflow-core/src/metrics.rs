@@ -0,0 +1,368 @@
+//! Flow and node metrics, recorded with the `metrics` crate.
+//!
+//! With the `metrics` feature on, [`SimpleFlow`](crate::flow_simple::SimpleFlow)
+//! and [`AdvancedFlow`](crate::flow_advanced::AdvancedFlow) report to whatever
+//! recorder is installed, for example `metrics-exporter-prometheus`:
+//!
+//! | Metric | Kind | Labels |
+//! |--------|------|--------|
+//! | [`FLOW_RUNS`] | counter | `flow` |
+//! | [`FLOW_SUCCESSES`], [`FLOW_FAILURES`] | counter | `flow`, `state` (final) |
+//! | [`FLOW_DURATION`] | histogram (seconds) | `flow` |
+//! | [`FLOWS_IN_FLIGHT`] | gauge | `flow` |
+//! | [`NODE_RUNS`], [`NODE_SUCCESSES`], [`NODE_FAILURES`], [`NODE_RETRIES`] | counter | `flow`, `node`, `state` |
+//! | [`NODE_DURATION`] | histogram (seconds) | `flow`, `node`, `state` |
+//! | [`NODES_IN_FLIGHT`] | gauge | `flow`, `node`, `state` |
+//!
+//! States are labelled with their `Debug` representation. Node metrics use
+//! the state the node ran in; a node run covers all of its attempts. Call
+//! [`describe`] once after installing the recorder to attach help texts.
+//!
+//! Without the feature nothing is recorded and the `metrics` crate is not
+//! compiled in.
+
+/// Flow runs started.
+pub const FLOW_RUNS: &str = "pocketflow_flow_runs_total";
+/// Flow runs that finished successfully.
+pub const FLOW_SUCCESSES: &str = "pocketflow_flow_successes_total";
+/// Flow runs that failed, were cancelled or returned an error.
+pub const FLOW_FAILURES: &str = "pocketflow_flow_failures_total";
+/// Duration of flow runs.
+pub const FLOW_DURATION: &str = "pocketflow_flow_duration_seconds";
+/// Flow runs currently executing.
+pub const FLOWS_IN_FLIGHT: &str = "pocketflow_flows_in_flight";
+/// Node executions started.
+pub const NODE_RUNS: &str = "pocketflow_node_runs_total";
+/// Node executions that succeeded.
+pub const NODE_SUCCESSES: &str = "pocketflow_node_successes_total";
+/// Node executions that failed after their last attempt.
+pub const NODE_FAILURES: &str = "pocketflow_node_failures_total";
+/// Node attempts retried by a retry policy.
+pub const NODE_RETRIES: &str = "pocketflow_node_retries_total";
+/// Duration of node executions, retries included.
+pub const NODE_DURATION: &str = "pocketflow_node_duration_seconds";
+/// Node executions currently running.
+pub const NODES_IN_FLIGHT: &str = "pocketflow_nodes_in_flight";
+
+#[cfg(feature = "metrics")]
+pub use recorder::describe;
+pub(crate) use recorder::{flow_finished, flow_started, node_finished, node_retried, node_started};
+
+#[cfg(feature = "metrics")]
+mod recorder {
+    use std::{fmt::Debug, time::Duration};
+
+    use ::metrics::{
+        Unit, counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram,
+    };
+
+    use super::*;
+
+    /// Register units and help texts for every metric.
+    pub fn describe() {
+        describe_counter!(FLOW_RUNS, "Flow runs started");
+        describe_counter!(FLOW_SUCCESSES, "Flow runs that finished successfully");
+        describe_counter!(FLOW_FAILURES, "Flow runs that did not finish successfully");
+        describe_histogram!(FLOW_DURATION, Unit::Seconds, "Duration of flow runs");
+        describe_gauge!(FLOWS_IN_FLIGHT, "Flow runs currently executing");
+        describe_counter!(NODE_RUNS, "Node executions started");
+        describe_counter!(NODE_SUCCESSES, "Node executions that succeeded");
+        describe_counter!(NODE_FAILURES, "Node executions that failed");
+        describe_counter!(NODE_RETRIES, "Node attempts retried by a retry policy");
+        describe_histogram!(NODE_DURATION, Unit::Seconds, "Duration of node executions");
+        describe_gauge!(NODES_IN_FLIGHT, "Node executions currently running");
+    }
+
+    pub(crate) fn flow_started(flow: &str) {
+        counter!(FLOW_RUNS, "flow" => flow.to_string()).increment(1);
+        gauge!(FLOWS_IN_FLIGHT, "flow" => flow.to_string()).increment(1.0);
+    }
+
+    pub(crate) fn flow_finished<S: Debug>(
+        flow: &str,
+        state: Option<&S>,
+        success: bool,
+        duration: Duration,
+    ) {
+        let state = state.map_or_else(|| "unknown".to_string(), |state| format!("{state:?}"));
+        let name = if success {
+            FLOW_SUCCESSES
+        } else {
+            FLOW_FAILURES
+        };
+        counter!(name, "flow" => flow.to_string(), "state" => state).increment(1);
+        histogram!(FLOW_DURATION, "flow" => flow.to_string()).record(duration.as_secs_f64());
+        gauge!(FLOWS_IN_FLIGHT, "flow" => flow.to_string()).decrement(1.0);
+    }
+
+    fn node_labels<S: Debug>(flow: &str, node: &str, state: &S) -> [(&'static str, String); 3] {
+        [
+            ("flow", flow.to_string()),
+            ("node", node.to_string()),
+            ("state", format!("{state:?}")),
+        ]
+    }
+
+    pub(crate) fn node_started<S: Debug>(flow: &str, node: &str, state: &S) {
+        let labels = node_labels(flow, node, state);
+        counter!(NODE_RUNS, &labels).increment(1);
+        gauge!(NODES_IN_FLIGHT, &labels).increment(1.0);
+    }
+
+    pub(crate) fn node_finished<S: Debug>(
+        flow: &str,
+        node: &str,
+        state: &S,
+        success: bool,
+        duration: Duration,
+    ) {
+        let labels = node_labels(flow, node, state);
+        let name = if success {
+            NODE_SUCCESSES
+        } else {
+            NODE_FAILURES
+        };
+        counter!(name, &labels).increment(1);
+        histogram!(NODE_DURATION, &labels).record(duration.as_secs_f64());
+        gauge!(NODES_IN_FLIGHT, &labels).decrement(1.0);
+    }
+
+    pub(crate) fn node_retried<S: Debug>(flow: &str, node: &str, state: &S) {
+        counter!(NODE_RETRIES, &node_labels(flow, node, state)).increment(1);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod recorder {
+    use std::{fmt::Debug, time::Duration};
+
+    pub(crate) fn flow_started(_flow: &str) {}
+
+    pub(crate) fn flow_finished<S: Debug>(
+        _flow: &str,
+        _state: Option<&S>,
+        _success: bool,
+        _duration: Duration,
+    ) {
+    }
+
+    pub(crate) fn node_started<S: Debug>(_flow: &str, _node: &str, _state: &S) {}
+
+    pub(crate) fn node_finished<S: Debug>(
+        _flow: &str,
+        _node: &str,
+        _state: &S,
+        _success: bool,
+        _duration: Duration,
+    ) {
+    }
+
+    pub(crate) fn node_retried<S: Debug>(_flow: &str, _node: &str, _state: &S) {}
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+
+    use ::metrics::{
+        Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata,
+        Recorder, SharedString, Unit,
+    };
+
+    use super::*;
+    use crate::{
+        context::Context,
+        error::FlowError,
+        flow_advanced::AdvancedFlow,
+        flow_simple::SimpleFlow,
+        node::{PassthroughNode, helpers::fn_node},
+        policy::NodePolicy,
+        state::SimpleState,
+    };
+
+    /// Sums every metric by name and labels.
+    #[derive(Default)]
+    struct Totals(Mutex<BTreeMap<String, f64>>);
+
+    struct Handle {
+        key: String,
+        totals: Arc<Totals>,
+    }
+
+    impl Handle {
+        fn add(&self, value: f64) {
+            *self
+                .totals
+                .0
+                .lock()
+                .unwrap()
+                .entry(self.key.clone())
+                .or_default() += value;
+        }
+    }
+
+    impl CounterFn for Handle {
+        fn increment(&self, value: u64) {
+            self.add(value as f64);
+        }
+
+        fn absolute(&self, _value: u64) {}
+    }
+
+    impl GaugeFn for Handle {
+        fn increment(&self, value: f64) {
+            self.add(value);
+        }
+
+        fn decrement(&self, value: f64) {
+            self.add(-value);
+        }
+
+        fn set(&self, _value: f64) {}
+    }
+
+    impl HistogramFn for Handle {
+        fn record(&self, _value: f64) {
+            self.add(1.0);
+        }
+    }
+
+    struct TestRecorder(Arc<Totals>);
+
+    impl TestRecorder {
+        fn handle(&self, key: &Key) -> Arc<Handle> {
+            let labels: Vec<_> = key
+                .labels()
+                .map(|label| format!("{}={}", label.key(), label.value()))
+                .collect();
+            Arc::new(Handle {
+                key: format!("{}{{{}}}", key.name(), labels.join(",")),
+                totals: Arc::clone(&self.0),
+            })
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::from_arc(self.handle(key))
+        }
+
+        fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::from_arc(self.handle(key))
+        }
+
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::from_arc(self.handle(key))
+        }
+    }
+
+    #[test]
+    fn both_engines_report_flow_and_node_metrics() {
+        let totals = Arc::new(Totals::default());
+        let recorder = TestRecorder(Arc::clone(&totals));
+        let _guard = ::metrics::set_default_local_recorder(&recorder);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let simple = SimpleFlow::builder()
+                .name("simple")
+                .initial_state(SimpleState::Start)
+                .node(
+                    SimpleState::Start,
+                    PassthroughNode::new("pass", SimpleState::Success),
+                )
+                .build()
+                .unwrap();
+            assert!(simple.execute(Context::new()).await.unwrap().success);
+
+            let attempts = Arc::new(Mutex::new(0));
+            let flaky = fn_node("flaky", move |context: Context| {
+                let attempts = Arc::clone(&attempts);
+                async move {
+                    let mut attempts = attempts.lock().unwrap();
+                    *attempts += 1;
+                    if *attempts < 3 {
+                        return Err(FlowError::execution("not yet"));
+                    }
+                    Ok((context, SimpleState::Success))
+                }
+            });
+            let advanced = AdvancedFlow::builder()
+                .name("advanced")
+                .initial_state(SimpleState::Start)
+                .on_state_with_policy(SimpleState::Start, flaky, NodePolicy::new().max_retries(2))
+                .build()
+                .unwrap();
+            assert!(advanced.execute(Context::new()).await.unwrap().success);
+        });
+
+        let totals = totals.0.lock().unwrap();
+        let node = "{flow=advanced,node=flaky,state=Start}";
+        assert_eq!(totals[&format!("{FLOW_RUNS}{{flow=simple}}")], 1.0);
+        assert_eq!(
+            totals[&format!("{FLOW_SUCCESSES}{{flow=simple,state=Success}}")],
+            1.0
+        );
+        assert_eq!(totals[&format!("{FLOWS_IN_FLIGHT}{{flow=advanced}}")], 0.0);
+        assert_eq!(
+            totals[&format!("{NODE_RUNS}{{flow=simple,node=pass,state=Start}}")],
+            1.0
+        );
+        assert_eq!(totals[&format!("{NODE_RUNS}{node}")], 1.0);
+        assert_eq!(totals[&format!("{NODE_RETRIES}{node}")], 2.0);
+        assert_eq!(totals[&format!("{NODE_SUCCESSES}{node}")], 1.0);
+        assert_eq!(totals[&format!("{NODE_DURATION}{node}")], 1.0);
+        assert_eq!(totals[&format!("{NODES_IN_FLIGHT}{node}")], 0.0);
+    }
+
+    #[test]
+    fn with_metrics_records_each_histogram_once() {
+        let totals = Arc::new(Totals::default());
+        let recorder = TestRecorder(Arc::clone(&totals));
+        let _guard = ::metrics::set_default_local_recorder(&recorder);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let flow = AdvancedFlow::builder()
+                .name("metered")
+                .initial_state(SimpleState::Start)
+                .with_metrics()
+                .on_state(
+                    SimpleState::Start,
+                    PassthroughNode::new("first", SimpleState::Processing),
+                )
+                .on_state(
+                    SimpleState::Processing,
+                    PassthroughNode::new("second", SimpleState::Success),
+                )
+                .build()
+                .unwrap();
+            assert!(flow.execute(Context::new()).await.unwrap().success);
+        });
+
+        let totals = totals.0.lock().unwrap();
+        let histograms: Vec<_> = totals
+            .iter()
+            .filter(|(key, _)| key.starts_with(NODE_DURATION) || key.starts_with(FLOW_DURATION))
+            .collect();
+        assert_eq!(histograms.len(), 3, "{histograms:?}");
+        assert!(histograms.iter().all(|(_, count)| **count == 1.0));
+        assert!(totals.keys().all(|key| {
+            !key.starts_with("pocketflow_node_success_total")
+                && !key.starts_with("pocketflow_node_failure_total")
+        }));
+    }
+}
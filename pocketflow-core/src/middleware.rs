@@ -0,0 +1,403 @@
+//! Async middleware for advanced flows.
+//!
+//! A [`FlowMiddleware`] is called around every node execution. Hooks receive
+//! the context mutably and may redirect the flow to another state instead of
+//! letting it continue as planned.
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    context::Context,
+    error::{FlowError, Result},
+    flow_advanced::Middleware,
+    state::FlowState,
+};
+
+/// What the executor should do after a middleware hook returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MiddlewareAction<S> {
+    /// Carry on as planned.
+    Continue,
+    /// Move to this state instead.
+    ///
+    /// From `before_node` the node is skipped; from `after_node` the node's
+    /// next state is replaced; from `on_error` the error is recovered.
+    Redirect(S),
+}
+
+/// Information about the node a middleware hook is called for.
+#[derive(Debug, Clone, Copy)]
+pub struct NodeInfo<'a, S> {
+    /// Name of the flow being executed.
+    pub flow_name: &'a str,
+    /// Id of the current run.
+    pub run_id: &'a str,
+    /// Name of the node.
+    pub node_name: &'a str,
+    /// State the node was selected for.
+    pub state: &'a S,
+    /// Step number within the run.
+    pub step: usize,
+}
+
+/// Async middleware with before, after and error hooks.
+///
+/// Every hook defaults to [`MiddlewareAction::Continue`]. Returning an error
+/// from a hook fails the flow.
+#[async_trait]
+pub trait FlowMiddleware<S: FlowState>: Send + Sync {
+    /// Called before a node runs.
+    async fn before_node(
+        &self,
+        context: &mut Context,
+        node: NodeInfo<'_, S>,
+    ) -> Result<MiddlewareAction<S>> {
+        let _ = (context, node);
+        Ok(MiddlewareAction::Continue)
+    }
+
+    /// Called after a node succeeded, with its duration and next state.
+    async fn after_node(
+        &self,
+        context: &mut Context,
+        node: NodeInfo<'_, S>,
+        next_state: &S,
+        duration: Duration,
+    ) -> Result<MiddlewareAction<S>> {
+        let _ = (context, node, next_state, duration);
+        Ok(MiddlewareAction::Continue)
+    }
+
+    /// Called after a node failed, before any fallback is applied.
+    async fn on_error(
+        &self,
+        context: &mut Context,
+        node: NodeInfo<'_, S>,
+        error: &FlowError,
+    ) -> Result<MiddlewareAction<S>> {
+        let _ = (context, node, error);
+        Ok(MiddlewareAction::Continue)
+    }
+
+    /// Get the name of this middleware for traces and errors.
+    fn name(&self) -> String {
+        let path = std::any::type_name::<Self>();
+        let path = path.split('<').next().unwrap_or(path);
+        path.rsplit("::").next().unwrap_or(path).to_string()
+    }
+}
+
+#[async_trait]
+impl<S, M> FlowMiddleware<S> for Arc<M>
+where
+    S: FlowState,
+    M: FlowMiddleware<S> + ?Sized,
+{
+    async fn before_node(
+        &self,
+        context: &mut Context,
+        node: NodeInfo<'_, S>,
+    ) -> Result<MiddlewareAction<S>> {
+        (**self).before_node(context, node).await
+    }
+
+    async fn after_node(
+        &self,
+        context: &mut Context,
+        node: NodeInfo<'_, S>,
+        next_state: &S,
+        duration: Duration,
+    ) -> Result<MiddlewareAction<S>> {
+        (**self)
+            .after_node(context, node, next_state, duration)
+            .await
+    }
+
+    async fn on_error(
+        &self,
+        context: &mut Context,
+        node: NodeInfo<'_, S>,
+        error: &FlowError,
+    ) -> Result<MiddlewareAction<S>> {
+        (**self).on_error(context, node, error).await
+    }
+
+    fn name(&self) -> String {
+        (**self).name()
+    }
+}
+
+/// Adapter running a synchronous check before every node.
+pub struct FnMiddleware<S: FlowState> {
+    func: Middleware<S>,
+}
+
+impl<S: FlowState> FnMiddleware<S> {
+    /// Wrap a closure that can veto a node by returning an error.
+    pub fn new<F>(func: F) -> Self
+    where
+        F: Fn(&Context, &S) -> Result<()> + Send + Sync + 'static,
+    {
+        Self {
+            func: Arc::new(func),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: FlowState> FlowMiddleware<S> for FnMiddleware<S> {
+    async fn before_node(
+        &self,
+        context: &mut Context,
+        node: NodeInfo<'_, S>,
+    ) -> Result<MiddlewareAction<S>> {
+        (self.func)(context, node.state)?;
+        Ok(MiddlewareAction::Continue)
+    }
+}
+
+/// Per-node timing record written by [`TimingMiddleware`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeTiming {
+    pub node: String,
+    pub from_state: String,
+    pub to_state: String,
+    pub duration_ms: u64,
+}
+
+/// Records how long each node took in the context metadata.
+///
+/// Timings are appended to the `node_timings` metadata key as a list of
+/// [`NodeTiming`] records.
+#[derive(Debug, Default)]
+pub struct TimingMiddleware;
+
+impl TimingMiddleware {
+    /// Metadata key holding the timing records.
+    pub const KEY: &'static str = "node_timings";
+}
+
+#[async_trait]
+impl<S: FlowState> FlowMiddleware<S> for TimingMiddleware {
+    async fn after_node(
+        &self,
+        context: &mut Context,
+        node: NodeInfo<'_, S>,
+        next_state: &S,
+        duration: Duration,
+    ) -> Result<MiddlewareAction<S>> {
+        let mut timings: Vec<NodeTiming> = context.get_metadata(Self::KEY)?.unwrap_or_default();
+        timings.push(NodeTiming {
+            node: node.node_name.to_string(),
+            from_state: format!("{:?}", node.state),
+            to_state: format!("{next_state:?}"),
+            duration_ms: duration.as_millis() as u64,
+        });
+        context.set_metadata(Self::KEY, &timings)?;
+        Ok(MiddlewareAction::Continue)
+    }
+}
+
+/// Logs node starts, completions and failures.
+///
+/// Uses `tracing` when the feature is enabled and stdout otherwise.
+#[derive(Debug, Default)]
+pub struct LoggingMiddleware;
+
+impl LoggingMiddleware {
+    fn info(message: &str) {
+        #[cfg(feature = "tracing")]
+        tracing::info!("{message}");
+        #[cfg(not(feature = "tracing"))]
+        println!("{message}");
+    }
+
+    fn warn(message: &str) {
+        #[cfg(feature = "tracing")]
+        tracing::warn!("{message}");
+        #[cfg(not(feature = "tracing"))]
+        eprintln!("{message}");
+    }
+}
+
+#[async_trait]
+impl<S: FlowState> FlowMiddleware<S> for LoggingMiddleware {
+    async fn before_node(
+        &self,
+        _context: &mut Context,
+        node: NodeInfo<'_, S>,
+    ) -> Result<MiddlewareAction<S>> {
+        Self::info(&format!(
+            "🔄 [{}] step {}: executing '{}' in state {:?}",
+            node.flow_name, node.step, node.node_name, node.state
+        ));
+        Ok(MiddlewareAction::Continue)
+    }
+
+    async fn after_node(
+        &self,
+        _context: &mut Context,
+        node: NodeInfo<'_, S>,
+        next_state: &S,
+        duration: Duration,
+    ) -> Result<MiddlewareAction<S>> {
+        Self::info(&format!(
+            "✅ [{}] '{}' {:?} -> {next_state:?} in {duration:?}",
+            node.flow_name, node.node_name, node.state
+        ));
+        Ok(MiddlewareAction::Continue)
+    }
+
+    async fn on_error(
+        &self,
+        _context: &mut Context,
+        node: NodeInfo<'_, S>,
+        error: &FlowError,
+    ) -> Result<MiddlewareAction<S>> {
+        Self::warn(&format!(
+            "❌ [{}] '{}' failed in state {:?}: {error}",
+            node.flow_name, node.node_name, node.state
+        ));
+        Ok(MiddlewareAction::Continue)
+    }
+}
+
+/// Former per-node metrics middleware, kept so existing builders and specs still
+/// compile.
+///
+/// Both flow engines now report node metrics themselves when the `metrics`
+/// feature is on (see [`crate::metrics`]), so this middleware records nothing
+/// and adding it never counts a node twice.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default)]
+pub struct MetricsMiddleware;
+
+#[cfg(feature = "metrics")]
+impl<S: FlowState> FlowMiddleware<S> for MetricsMiddleware {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        flow_advanced::{AdvancedFlow, StepKind},
+        node::helpers,
+        state::SimpleState,
+    };
+
+    /// Skips nodes when `skip` is set and recovers failures to `Error`.
+    struct Guard;
+
+    #[async_trait]
+    impl FlowMiddleware<SimpleState> for Guard {
+        async fn before_node(
+            &self,
+            context: &mut Context,
+            _node: NodeInfo<'_, SimpleState>,
+        ) -> Result<MiddlewareAction<SimpleState>> {
+            if context.get_json::<bool>("skip")?.unwrap_or(false) {
+                return Ok(MiddlewareAction::Redirect(SimpleState::Success));
+            }
+            context.set("guarded", true)?;
+            Ok(MiddlewareAction::Continue)
+        }
+
+        async fn after_node(
+            &self,
+            context: &mut Context,
+            node: NodeInfo<'_, SimpleState>,
+            next_state: &SimpleState,
+            _duration: Duration,
+        ) -> Result<MiddlewareAction<SimpleState>> {
+            context.set("seen", format!("{} -> {next_state:?}", node.node_name))?;
+            Ok(MiddlewareAction::Continue)
+        }
+
+        async fn on_error(
+            &self,
+            context: &mut Context,
+            _node: NodeInfo<'_, SimpleState>,
+            error: &FlowError,
+        ) -> Result<MiddlewareAction<SimpleState>> {
+            context.set("recovered_from", error.to_string())?;
+            Ok(MiddlewareAction::Redirect(SimpleState::Error))
+        }
+    }
+
+    fn flow(fail: bool) -> AdvancedFlow<SimpleState> {
+        let worker = helpers::fn_node("worker", move |context: Context| async move {
+            if fail {
+                return Err(FlowError::context("boom"));
+            }
+            Ok((context, SimpleState::Success))
+        });
+        AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(SimpleState::Start, worker)
+            .with_middleware(Guard)
+            .with_timing()
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn hooks_mutate_context_and_record_timings() {
+        let result = flow(false).execute(Context::new()).await.unwrap();
+        assert!(result.success);
+        assert_eq!(
+            result.context.get_json::<bool>("guarded").unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            result.context.get_json::<String>("seen").unwrap(),
+            Some("worker -> Success".to_string())
+        );
+
+        let timings: Vec<NodeTiming> = result
+            .context
+            .get_metadata(TimingMiddleware::KEY)
+            .unwrap()
+            .unwrap();
+        assert_eq!(timings.len(), 1);
+        assert_eq!(timings[0].node, "worker");
+        assert_eq!(timings[0].to_state, "Success");
+    }
+
+    #[tokio::test]
+    async fn redirects_skip_nodes_and_recover_errors() {
+        let mut context = Context::new();
+        context.set("skip", true).unwrap();
+        let result = flow(false).execute(context).await.unwrap();
+        assert_eq!(result.final_state, SimpleState::Success);
+        assert_eq!(result.trace.len(), 1);
+        assert_eq!(result.trace[0].kind, StepKind::Middleware);
+        assert_eq!(result.trace[0].node_name, "Guard");
+
+        // Ending in an error state still fails the run
+        let result = flow(true).execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(result.final_state, SimpleState::Error);
+        assert!(result.context.contains_json("recovered_from"));
+        let kinds: Vec<_> = result.trace.iter().map(|step| step.kind).collect();
+        assert_eq!(kinds, vec![StepKind::Node, StepKind::Middleware]);
+    }
+
+    #[tokio::test]
+    async fn fn_middleware_errors_fail_the_flow() {
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::passthrough("noop", SimpleState::Success),
+            )
+            .middleware(|_context, _state| Err(FlowError::context("denied")))
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert!(result.error.unwrap().starts_with("Middleware error"));
+    }
+}
@@ -39,10 +39,37 @@ pub trait Node: Send + Sync + Debug {
         Ok(())
     }
 
+    /// Optional compensation for a completed execution.
+    ///
+    /// When an [`AdvancedFlow`](crate::flow_advanced::AdvancedFlow) run fails,
+    /// every node that completed a step is asked to undo its side effects,
+    /// most recent step first, with the context of the failed run. Return
+    /// `Ok(true)` once the effects are undone and `Ok(false)` (the default)
+    /// if there is nothing to undo; only compensating nodes appear in the
+    /// trace.
+    ///
+    /// A [`ParallelNode`](crate::parallel::ParallelNode) forwards the call
+    /// to all of its branches. [`SubFlowNode`](crate::subflow::SubFlowNode)
+    /// and [`MapBatchNode`](crate::batch::MapBatchNode) do not undo the work
+    /// of their child run or items; wrap them with
+    /// [`helpers::compensated`] to compensate it.
+    async fn compensate(&self, context: &Context) -> Result<bool> {
+        let _ = context;
+        Ok(false)
+    }
+
     /// Get the name of this node for debugging/logging.
     fn name(&self) -> String {
         format!("{self:?}")
     }
+
+    /// States this node may return, used for static graph analysis.
+    ///
+    /// `None` (the default) means the node does not declare its exits and
+    /// may return any state.
+    fn emits(&self) -> Option<Vec<Self::State>> {
+        None
+    }
 }
 
 /// A simple functional node that wraps a closure.
@@ -58,6 +85,7 @@ where
 {
     func: F,
     name: String,
+    emits: Option<Vec<S>>,
     _phantom: std::marker::PhantomData<S>,
 }
 
@@ -91,9 +119,16 @@ where
         Self {
             func,
             name: name.into(),
+            emits: None,
             _phantom: std::marker::PhantomData,
         }
     }
+
+    /// Declare the states the function may return.
+    pub fn emits(mut self, states: impl IntoIterator<Item = S>) -> Self {
+        self.emits = Some(states.into_iter().collect());
+        self
+    }
 }
 
 #[async_trait]
@@ -116,6 +151,10 @@ where
     fn name(&self) -> String {
         self.name.clone()
     }
+
+    fn emits(&self) -> Option<Vec<Self::State>> {
+        self.emits.clone()
+    }
 }
 
 /// A no-op node that simply transitions to a specified state.
@@ -146,6 +185,87 @@ impl<S: FlowState> Node for PassthroughNode<S> {
     fn name(&self) -> String {
         self.name.clone()
     }
+
+    fn emits(&self) -> Option<Vec<Self::State>> {
+        Some(vec![self.target_state.clone()])
+    }
+}
+
+/// A node paired with a compensating action, see [`Node::compensate`].
+///
+/// Lets nodes with side effects, such as tool or MCP calls, be undone without
+/// implementing [`Node`] by hand. The action receives a copy of the failed
+/// run's context.
+pub struct CompensatedNode<N, F>
+where
+    N: Node,
+    F: Fn(Context) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>>
+        + Send
+        + Sync,
+{
+    inner: N,
+    compensate: F,
+}
+
+impl<N, F> std::fmt::Debug for CompensatedNode<N, F>
+where
+    N: Node,
+    F: Fn(Context) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>>
+        + Send
+        + Sync,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CompensatedNode").field(&self.inner).finish()
+    }
+}
+
+impl<N, F> CompensatedNode<N, F>
+where
+    N: Node,
+    F: Fn(Context) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>>
+        + Send
+        + Sync,
+{
+    /// Wrap `inner`, undoing it with `compensate`.
+    pub fn new(inner: N, compensate: F) -> Self {
+        Self { inner, compensate }
+    }
+}
+
+#[async_trait]
+impl<N, F> Node for CompensatedNode<N, F>
+where
+    N: Node,
+    F: Fn(Context) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>>
+        + Send
+        + Sync,
+{
+    type State = N::State;
+
+    async fn execute(&self, context: Context) -> Result<(Context, Self::State)> {
+        self.inner.execute(context).await
+    }
+
+    async fn prepare(&self, context: &Context) -> Result<()> {
+        self.inner.prepare(context).await
+    }
+
+    async fn cleanup(&self, context: &Context, state: &Self::State) -> Result<()> {
+        self.inner.cleanup(context, state).await
+    }
+
+    async fn compensate(&self, context: &Context) -> Result<bool> {
+        (self.compensate)(context.clone()).await?;
+        Ok(true)
+    }
+
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn emits(&self) -> Option<Vec<Self::State>> {
+        self.inner.emits()
+    }
 }
 
 /// A conditional node that chooses between states based on a predicate.
@@ -212,6 +332,10 @@ where
     fn name(&self) -> String {
         self.name.clone()
     }
+
+    fn emits(&self) -> Option<Vec<Self::State>> {
+        Some(vec![self.true_state.clone(), self.false_state.clone()])
+    }
 }
 
 /// A batch processing node that applies a function to a collection of items.
@@ -328,6 +452,25 @@ pub mod helpers {
         FnNode::new(name, move |ctx| Box::pin(f(ctx)))
     }
 
+    /// Pair a node with an async compensating action.
+    #[allow(clippy::type_complexity)]
+    pub fn compensated<N, F, Fut>(
+        node: N,
+        f: F,
+    ) -> CompensatedNode<
+        N,
+        impl Fn(Context) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>>
+        + Send
+        + Sync,
+    >
+    where
+        N: Node,
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        CompensatedNode::new(node, move |ctx| Box::pin(f(ctx)))
+    }
+
     /// Create a passthrough node that transitions to a specific state.
     pub fn passthrough<S: FlowState>(name: impl Into<String>, state: S) -> PassthroughNode<S> {
         PassthroughNode::new(name, state)
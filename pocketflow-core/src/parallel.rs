@@ -0,0 +1,684 @@
+//! Parallel fan-out / fan-in node.
+//!
+//! A [`ParallelNode`] runs several child nodes concurrently, each on its own
+//! clone of the incoming [`Context`], then merges the children's changes back
+//! into a single context and picks the next state from the combined outcomes.
+
+use std::{any::TypeId, collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use futures::{StreamExt, future::BoxFuture, stream};
+use serde_json::Value;
+
+use crate::{
+    context::Context,
+    error::{FlowError, Result},
+    merge::{MergePolicy, MergeScope, MergeStrategy},
+    node::Node,
+    state::FlowState,
+};
+
+/// Custom merge function: `(key, existing, incoming) -> merged`.
+pub type MergeFn = Arc<dyn Fn(&str, &Value, &Value) -> Result<Value> + Send + Sync>;
+
+/// Function choosing the next state from all branch outcomes.
+pub type OutcomeReducer<S> = Arc<dyn Fn(&[BranchOutcome<S>]) -> S + Send + Sync>;
+
+/// How to resolve two branches writing different values to the same key.
+#[derive(Clone, Default)]
+pub enum ConflictPolicy {
+    /// The branch declared last wins.
+    #[default]
+    LastWins,
+    /// Fail the node with a context error.
+    Error,
+    /// Resolve the conflict with a custom function.
+    Custom(MergeFn),
+    /// Resolve the conflict with per-key merge strategies.
+    Merge(MergePolicy),
+}
+
+impl std::fmt::Debug for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConflictPolicy::LastWins => f.write_str("LastWins"),
+            ConflictPolicy::Error => f.write_str("Error"),
+            ConflictPolicy::Custom(_) => f.write_str("Custom"),
+            ConflictPolicy::Merge(policy) => f.debug_tuple("Merge").field(policy).finish(),
+        }
+    }
+}
+
+/// Result of running one branch of a [`ParallelNode`].
+#[derive(Debug, Clone)]
+pub struct BranchOutcome<S: FlowState> {
+    /// Name of the child node.
+    pub node_name: String,
+    /// State returned by the child, or its error message.
+    pub result: std::result::Result<S, String>,
+}
+
+impl<S: FlowState> BranchOutcome<S> {
+    /// Whether the branch completed without error.
+    pub fn is_success(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// Outcome of a branch plus its context when it succeeded.
+type BranchResult<S> = (BranchOutcome<S>, Option<Context>);
+
+/// Node that fans out to several children and fans their results back in.
+pub struct ParallelNode<S: FlowState> {
+    name: String,
+    branches: Vec<Arc<dyn Node<State = S>>>,
+    max_concurrency: Option<usize>,
+    conflict_policy: ConflictPolicy,
+    reducer: OutcomeReducer<S>,
+    emits: Option<Vec<S>>,
+}
+
+impl<S: FlowState> std::fmt::Debug for ParallelNode<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParallelNode")
+            .field("name", &self.name)
+            .field("branches", &self.branches.len())
+            .field("max_concurrency", &self.max_concurrency)
+            .field("conflict_policy", &self.conflict_policy)
+            .finish()
+    }
+}
+
+impl<S: FlowState> ParallelNode<S> {
+    /// Create a new parallel node builder.
+    pub fn builder(name: impl Into<String>) -> ParallelNodeBuilder<S> {
+        ParallelNodeBuilder::new(name)
+    }
+
+    async fn run_branch(node: Arc<dyn Node<State = S>>, context: Context) -> BranchResult<S> {
+        let node_name = node.name();
+        let outcome = |result| BranchOutcome {
+            node_name: node_name.clone(),
+            result,
+        };
+
+        if let Err(error) = node.prepare(&context).await {
+            return (outcome(Err(error.to_string())), None);
+        }
+
+        match node.execute(context).await {
+            Ok((new_context, state)) => {
+                if let Err(error) = node.cleanup(&new_context, &state).await {
+                    return (outcome(Err(error.to_string())), None);
+                }
+                (outcome(Ok(state)), Some(new_context))
+            }
+            Err(error) => (outcome(Err(error.to_string())), None),
+        }
+    }
+
+    /// Merge the changes each branch made relative to `base`.
+    fn merge(&self, base: &Context, branches: &[(String, Context)]) -> Result<Context> {
+        let mut merged = base.clone();
+        let mut json_writers: HashMap<String, String> = HashMap::new();
+        let mut meta_writers: HashMap<String, String> = HashMap::new();
+        let mut typed_writers: HashMap<TypeId, String> = HashMap::new();
+
+        for (branch, context) in branches {
+            for (key, change) in changes(base.json_data(), context.json_data()) {
+                let current = merged.get_raw(&key).cloned();
+                let value = self.resolve(
+                    &mut json_writers,
+                    MergeScope::Data,
+                    branch,
+                    &key,
+                    base.get_raw(&key),
+                    current,
+                    change,
+                )?;
+                match value {
+                    Some(value) => merged.set(key, value)?,
+                    None => {
+                        merged.remove_json(&key);
+                    }
+                }
+            }
+
+            for (key, change) in changes(base.metadata(), context.metadata()) {
+                let current = merged.get_metadata_raw(&key).cloned();
+                let value = self.resolve(
+                    &mut meta_writers,
+                    MergeScope::Metadata,
+                    branch,
+                    &key,
+                    base.get_metadata_raw(&key),
+                    current,
+                    change,
+                )?;
+                match value {
+                    Some(value) => merged.set_metadata(key, value)?,
+                    None => {
+                        merged.remove_metadata(&key);
+                    }
+                }
+            }
+
+            // Typed entries can't be combined, only kept or replaced whole
+            for (type_id, entry) in context.typed_entries() {
+                let unchanged = base
+                    .typed_entry(type_id)
+                    .is_some_and(|current| Arc::ptr_eq(&current.value, &entry.value));
+                if unchanged {
+                    continue;
+                }
+                if let Some(previous_writer) = typed_writers.insert(*type_id, branch.clone()) {
+                    let conflict = || {
+                        FlowError::context(format!(
+                            "Parallel node '{}': branches '{previous_writer}' and '{branch}' wrote conflicting values for type '{}'",
+                            self.name, entry.type_name
+                        ))
+                    };
+                    match &self.conflict_policy {
+                        ConflictPolicy::Error => return Err(conflict()),
+                        ConflictPolicy::Merge(policy) => match policy.default {
+                            MergeStrategy::ErrorOnConflict => return Err(conflict()),
+                            strategy if strategy.keeps_existing() => continue,
+                            _ => {}
+                        },
+                        ConflictPolicy::LastWins | ConflictPolicy::Custom(_) => {}
+                    }
+                }
+                merged.insert_entry(*type_id, entry.clone());
+            }
+        }
+
+        Ok(merged)
+    }
+
+    /// Decide the merged value for a key changed by `branch`.
+    #[allow(clippy::too_many_arguments)]
+    fn resolve(
+        &self,
+        writers: &mut HashMap<String, String>,
+        scope: MergeScope,
+        branch: &str,
+        key: &str,
+        base: Option<&Value>,
+        current: Option<Value>,
+        incoming: Option<Value>,
+    ) -> Result<Option<Value>> {
+        let Some(previous_writer) = writers.insert(key.to_string(), branch.to_string()) else {
+            return Ok(incoming);
+        };
+
+        if current == incoming {
+            return Ok(incoming);
+        }
+
+        match &self.conflict_policy {
+            ConflictPolicy::LastWins => Ok(incoming),
+            ConflictPolicy::Error => Err(FlowError::context(format!(
+                "Parallel node '{}': branches '{previous_writer}' and '{branch}' wrote conflicting values for key '{key}'",
+                self.name
+            ))),
+            ConflictPolicy::Custom(merge) => match (current, incoming) {
+                (Some(current), Some(incoming)) => merge(key, &current, &incoming).map(Some),
+                // A removal racing with a write keeps the written value
+                (current, incoming) => Ok(current.or(incoming)),
+            },
+            ConflictPolicy::Merge(policy) => match (current, incoming) {
+                (Some(current), Some(incoming)) => {
+                    // Both values build on the base, so only this branch's
+                    // changes to it are merged in
+                    let incoming = match base {
+                        Some(base) => policy.changes_since(key, base, &incoming),
+                        None => incoming,
+                    };
+                    policy
+                        .merge_values(scope, key, &current, &incoming)
+                        .map(Some)
+                        .map_err(|error| {
+                            FlowError::context(format!(
+                                "Parallel node '{}': branches '{previous_writer}' and '{branch}' could not be merged: {error}",
+                                self.name
+                            ))
+                        })
+                }
+                (current, incoming) => Ok(current.or(incoming)),
+            },
+        }
+    }
+}
+
+/// Keys added, changed (`Some`) or removed (`None`) in `after` relative to `before`.
+fn changes(
+    before: &HashMap<String, Value>,
+    after: &HashMap<String, Value>,
+) -> Vec<(String, Option<Value>)> {
+    let mut changes: Vec<_> = after
+        .iter()
+        .filter(|(key, value)| before.get(*key) != Some(*value))
+        .map(|(key, value)| (key.clone(), Some(value.clone())))
+        .collect();
+    changes.extend(
+        before
+            .keys()
+            .filter(|key| !after.contains_key(*key))
+            .map(|key| (key.clone(), None)),
+    );
+    changes.sort_by(|a, b| a.0.cmp(&b.0));
+    changes
+}
+
+#[async_trait]
+impl<S: FlowState> Node for ParallelNode<S> {
+    type State = S;
+
+    async fn execute(&self, context: Context) -> Result<(Context, Self::State)> {
+        let limit = self.max_concurrency.unwrap_or(self.branches.len()).max(1);
+
+        // `buffered` keeps branch order, which makes last-wins deterministic
+        let branches: Vec<BoxFuture<'static, BranchResult<S>>> = self
+            .branches
+            .iter()
+            .map(|node| {
+                let future = Self::run_branch(node.clone(), context.clone());
+                Box::pin(future) as BoxFuture<'static, BranchResult<S>>
+            })
+            .collect();
+        let results: Vec<_> = stream::iter(branches).buffered(limit).collect().await;
+
+        let mut outcomes = Vec::with_capacity(results.len());
+        let mut contexts = Vec::new();
+        for (outcome, branch_context) in results {
+            if let Some(branch_context) = branch_context {
+                contexts.push((outcome.node_name.clone(), branch_context));
+            }
+            outcomes.push(outcome);
+        }
+
+        let merged = self.merge(&context, &contexts)?;
+        let next_state = (self.reducer)(&outcomes);
+        Ok((merged, next_state))
+    }
+
+    /// Compensate every branch, last declared first. Branches that failed
+    /// are asked as well, since they may have had partial effects.
+    async fn compensate(&self, context: &Context) -> Result<bool> {
+        let mut compensated = false;
+        let mut errors = Vec::new();
+        for branch in self.branches.iter().rev() {
+            match branch.compensate(context).await {
+                Ok(done) => compensated |= done,
+                Err(error) => errors.push(format!("branch '{}': {error}", branch.name())),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(FlowError::execution(format!(
+                "Parallel node '{}' could not compensate {}",
+                self.name,
+                errors.join("; ")
+            )));
+        }
+        Ok(compensated)
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn emits(&self) -> Option<Vec<Self::State>> {
+        self.emits.clone()
+    }
+}
+
+/// Builder for [`ParallelNode`].
+pub struct ParallelNodeBuilder<S: FlowState> {
+    name: String,
+    branches: Vec<Arc<dyn Node<State = S>>>,
+    max_concurrency: Option<usize>,
+    conflict_policy: ConflictPolicy,
+    reducer: Option<OutcomeReducer<S>>,
+    on_success: Option<S>,
+    on_error: Option<S>,
+}
+
+impl<S: FlowState> ParallelNodeBuilder<S> {
+    /// Create a new builder.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            branches: Vec::new(),
+            max_concurrency: None,
+            conflict_policy: ConflictPolicy::default(),
+            reducer: None,
+            on_success: None,
+            on_error: None,
+        }
+    }
+
+    /// Add a child node run as one branch.
+    pub fn branch(mut self, node: impl Node<State = S> + 'static) -> Self {
+        self.branches.push(Arc::new(node));
+        self
+    }
+
+    /// Add an already shared child node.
+    pub fn branch_arc(mut self, node: Arc<dyn Node<State = S>>) -> Self {
+        self.branches.push(node);
+        self
+    }
+
+    /// Limit how many branches run at the same time.
+    pub fn max_concurrency(mut self, limit: usize) -> Self {
+        self.max_concurrency = Some(limit);
+        self
+    }
+
+    /// Set how conflicting writes are resolved.
+    pub fn conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.conflict_policy = policy;
+        self
+    }
+
+    /// Resolve conflicts with per-key merge strategies.
+    ///
+    /// Each branch's changes to the incoming context are merged, so an
+    /// [`MergeStrategy::ArrayAppend`] key collects the items every branch
+    /// appended. Typed entries use the policy's default strategy.
+    pub fn merge_policy(self, policy: MergePolicy) -> Self {
+        self.conflict_policy(ConflictPolicy::Merge(policy))
+    }
+
+    /// Resolve conflicts with a custom merge function.
+    pub fn merge_with<F>(self, merge: F) -> Self
+    where
+        F: Fn(&str, &Value, &Value) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.conflict_policy(ConflictPolicy::Custom(Arc::new(merge)))
+    }
+
+    /// State to move to when every branch succeeds.
+    pub fn on_success(mut self, state: S) -> Self {
+        self.on_success = Some(state);
+        self
+    }
+
+    /// State to move to when any branch fails.
+    pub fn on_error(mut self, state: S) -> Self {
+        self.on_error = Some(state);
+        self
+    }
+
+    /// Choose the next state from the branch outcomes.
+    ///
+    /// Takes precedence over `on_success` / `on_error`.
+    pub fn next_state<F>(mut self, reducer: F) -> Self
+    where
+        F: Fn(&[BranchOutcome<S>]) -> S + Send + Sync + 'static,
+    {
+        self.reducer = Some(Arc::new(reducer));
+        self
+    }
+
+    /// Build the node.
+    pub fn build(self) -> Result<ParallelNode<S>> {
+        if self.branches.is_empty() {
+            return Err(FlowError::construction(format!(
+                "Parallel node '{}' has no branches",
+                self.name
+            )));
+        }
+
+        let emits = match (&self.reducer, &self.on_success, &self.on_error) {
+            (None, Some(success), Some(error)) => Some(vec![success.clone(), error.clone()]),
+            _ => None,
+        };
+        let reducer = match (self.reducer, self.on_success, self.on_error) {
+            (Some(reducer), _, _) => reducer,
+            (None, Some(success), Some(error)) => {
+                Arc::new(move |outcomes: &[BranchOutcome<S>]| {
+                    if outcomes.iter().all(BranchOutcome::is_success) {
+                        success.clone()
+                    } else {
+                        error.clone()
+                    }
+                })
+            }
+            _ => {
+                return Err(FlowError::construction(format!(
+                    "Parallel node '{}' needs either next_state or both on_success and on_error",
+                    self.name
+                )));
+            }
+        };
+
+        Ok(ParallelNode {
+            name: self.name,
+            branches: self.branches,
+            max_concurrency: self.max_concurrency,
+            conflict_policy: self.conflict_policy,
+            reducer,
+            emits,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{node::helpers, state::SimpleState};
+
+    fn writer(
+        name: &'static str,
+        key: &'static str,
+        value: impl Into<Value>,
+    ) -> impl Node<State = SimpleState> + 'static {
+        let value = value.into();
+        helpers::fn_node(name, move |mut ctx: Context| {
+            let value = value.clone();
+            async move {
+                ctx.set(key, value)?;
+                Ok((ctx, SimpleState::Success))
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn merges_branches_with_concurrency_limit() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let mut builder = ParallelNode::builder("fan_out")
+            .max_concurrency(2)
+            .on_success(SimpleState::Success)
+            .on_error(SimpleState::Error);
+        for i in 0..5 {
+            let in_flight = in_flight.clone();
+            let peak = peak.clone();
+            builder = builder.branch(helpers::fn_node("worker", move |mut ctx: Context| {
+                let in_flight = in_flight.clone();
+                let peak = peak.clone();
+                async move {
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    ctx.set(format!("result_{i}"), i)?;
+                    Ok((ctx, SimpleState::Success))
+                }
+            }));
+        }
+        let node = builder.build().unwrap();
+
+        let mut context = Context::new();
+        context.set("input", "shared").unwrap();
+        let (merged, state) = node.execute(context).await.unwrap();
+
+        assert_eq!(state, SimpleState::Success);
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        for i in 0..5 {
+            assert_eq!(
+                merged.get_json::<i32>(&format!("result_{i}")).unwrap(),
+                Some(i)
+            );
+        }
+        assert_eq!(
+            merged.get_json::<String>("input").unwrap(),
+            Some("shared".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn conflict_policies() {
+        let last_wins = ParallelNode::builder("last_wins")
+            .branch(writer("a", "total", 1))
+            .branch(writer("b", "total", 2))
+            .on_success(SimpleState::Success)
+            .on_error(SimpleState::Error)
+            .build()
+            .unwrap();
+        let (merged, _) = last_wins.execute(Context::new()).await.unwrap();
+        assert_eq!(merged.get_json::<i64>("total").unwrap(), Some(2));
+
+        let strict = ParallelNode::builder("strict")
+            .branch(writer("a", "total", 1))
+            .branch(writer("b", "total", 2))
+            .conflict_policy(ConflictPolicy::Error)
+            .on_success(SimpleState::Success)
+            .on_error(SimpleState::Error)
+            .build()
+            .unwrap();
+        let err = strict.execute(Context::new()).await.unwrap_err();
+        assert!(err.to_string().contains("'total'"));
+
+        let summed = ParallelNode::builder("summed")
+            .branch(writer("a", "total", 1))
+            .branch(writer("b", "total", 2))
+            .merge_with(|_key, existing, incoming| {
+                let sum = existing.as_i64().unwrap_or(0) + incoming.as_i64().unwrap_or(0);
+                Ok(Value::from(sum))
+            })
+            .on_success(SimpleState::Success)
+            .on_error(SimpleState::Error)
+            .build()
+            .unwrap();
+        let (merged, _) = summed.execute(Context::new()).await.unwrap();
+        assert_eq!(merged.get_json::<i64>("total").unwrap(), Some(3));
+
+        let appender = |name: &'static str, item: u32| {
+            helpers::fn_node(name, move |mut ctx: Context| async move {
+                let mut items = ctx.get_json::<Vec<u32>>("items")?.unwrap_or_default();
+                items.push(item);
+                ctx.set("items", items)?;
+                ctx.insert(item)?;
+                Ok((ctx, SimpleState::Success))
+            })
+        };
+        let appended = ParallelNode::builder("appended")
+            .branch(appender("a", 1))
+            .branch(appender("b", 2))
+            .branch(writer("c", "total", 3))
+            .branch(writer("d", "total", 4))
+            .merge_policy(
+                MergePolicy::new(MergeStrategy::KeepExisting)
+                    .prefix("items", MergeStrategy::ArrayAppend),
+            )
+            .on_success(SimpleState::Success)
+            .on_error(SimpleState::Error)
+            .build()
+            .unwrap();
+        let mut base = Context::new();
+        base.set("items", serde_json::json!([0])).unwrap();
+        let (merged, _) = appended.execute(base).await.unwrap();
+        assert_eq!(merged.get_raw("items"), Some(&serde_json::json!([0, 1, 2])));
+        assert_eq!(merged.get_json::<i64>("total").unwrap(), Some(3));
+        // Typed entries follow the policy's default strategy
+        assert_eq!(merged.get::<u32>(), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn failed_branch_routes_via_reducer() {
+        let failing = helpers::fn_node("failing", |_ctx: Context| async move {
+            Err::<(Context, SimpleState), _>(FlowError::context("nope"))
+        });
+
+        let node = ParallelNode::builder("mixed")
+            .branch(writer("ok", "ok", 1))
+            .branch(failing)
+            .on_success(SimpleState::Success)
+            .on_error(SimpleState::Error)
+            .build()
+            .unwrap();
+        let (merged, state) = node.execute(Context::new()).await.unwrap();
+        assert_eq!(state, SimpleState::Error);
+        assert_eq!(merged.get_json::<i64>("ok").unwrap(), Some(1));
+
+        let custom = ParallelNode::builder("custom")
+            .branch(writer("ok", "ok", 1))
+            .next_state(|outcomes| {
+                if outcomes.iter().any(BranchOutcome::is_success) {
+                    SimpleState::Custom("partial".to_string())
+                } else {
+                    SimpleState::Error
+                }
+            })
+            .build()
+            .unwrap();
+        let (_, state) = custom.execute(Context::new()).await.unwrap();
+        assert_eq!(state, SimpleState::Custom("partial".to_string()));
+
+        assert!(
+            ParallelNode::<SimpleState>::builder("empty")
+                .on_success(SimpleState::Success)
+                .on_error(SimpleState::Error)
+                .build()
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn compensation_reaches_every_branch() {
+        let undone = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let branch = |name: &'static str| {
+            let undone = undone.clone();
+            helpers::compensated(writer(name, name, 1), move |_context| {
+                let undone = undone.clone();
+                async move {
+                    undone.lock().unwrap().push(name);
+                    Ok(())
+                }
+            })
+        };
+        let fan_out = ParallelNode::builder("fan_out")
+            .branch(branch("a"))
+            .branch(branch("b"))
+            .branch(writer("plain", "plain", 1))
+            .next_state(|_| SimpleState::Processing)
+            .build()
+            .unwrap();
+        let flow = crate::flow_advanced::AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(SimpleState::Start, fan_out)
+            .on_state(
+                SimpleState::Processing,
+                helpers::fn_node("ship", |_context: Context| async move {
+                    Err::<(Context, SimpleState), _>(FlowError::context("carrier down"))
+                }),
+            )
+            .build()
+            .unwrap();
+
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(*undone.lock().unwrap(), ["b", "a"]);
+        let step = result.trace.last().unwrap();
+        assert_eq!(step.kind, crate::flow_advanced::StepKind::Compensation);
+        assert_eq!(step.node_name, "fan_out");
+    }
+}
@@ -0,0 +1,332 @@
+//! Persisting whole contexts, typed entries included.
+//!
+//! Typed entries ([`Context::insert`]) are invisible to [`Context::to_json`]
+//! and the context's own `Serialize` implementation. To persist them, register
+//! their types under stable names in a [`TypeRegistry`]:
+//!
+//! ```rust
+//! use pocketflow_core::prelude::*;
+//!
+//! #[derive(Debug, PartialEq, Serialize, Deserialize)]
+//! struct Session {
+//!     user: String,
+//! }
+//!
+//! let mut registry = TypeRegistry::new();
+//! registry.register::<Session>("session");
+//!
+//! let mut context = Context::new();
+//! context.insert(Session {
+//!     user: "ada".to_string(),
+//! })?;
+//! context.set("step", 3)?;
+//!
+//! let json = context.persist(&registry)?.to_json()?;
+//! let restored = PersistedContext::from_json(&json)?.into_context(&registry)?;
+//! assert_eq!(
+//!     restored.get::<Session>().map(|s| s.user.as_str()),
+//!     Some("ada")
+//! );
+//! # Ok::<(), FlowError>(())
+//! ```
+//!
+//! Type names are part of the persisted format, so keep them stable across
+//! releases. With the `msgpack` feature, [`PersistedContext`] can also be
+//! written as MessagePack.
+//!
+//! An [`AdvancedFlow`](crate::flow_advanced::AdvancedFlow) given a registry
+//! with [`type_registry`](crate::flow_advanced::AdvancedFlowBuilder::type_registry)
+//! checkpoints typed entries too.
+
+use std::{
+    any::TypeId,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{
+    context::{Context, TypedEntry},
+    error::{FlowError, Result},
+};
+
+/// Serializer and deserializer for one registered type.
+#[derive(Clone)]
+struct TypeCodec {
+    name: String,
+    encode: fn(&TypedEntry) -> Result<Value>,
+    decode: fn(Value) -> Result<TypedEntry>,
+}
+
+fn encode<T: Serialize + 'static>(entry: &TypedEntry) -> Result<Value> {
+    let value = entry.value.downcast_ref::<T>().ok_or_else(|| {
+        FlowError::context(format!(
+            "Typed context entry `{}` does not hold its registered type",
+            entry.type_name
+        ))
+    })?;
+    serde_json::to_value(value).map_err(FlowError::from)
+}
+
+fn decode<T: DeserializeOwned + Send + Sync + 'static>(value: Value) -> Result<TypedEntry> {
+    let value: T = serde_json::from_value(value)?;
+    Ok(TypedEntry {
+        type_name: std::any::type_name::<T>(),
+        value: Arc::new(value),
+    })
+}
+
+/// Registry of typed context entries that can be persisted, keyed by a stable
+/// type name.
+#[derive(Clone, Default)]
+pub struct TypeRegistry {
+    codecs: HashMap<TypeId, TypeCodec>,
+    names: HashMap<String, TypeId>,
+}
+
+impl TypeRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Persist typed entries of type `T` under `name`.
+    ///
+    /// Registering a type or a name again replaces the earlier registration.
+    pub fn register<T>(&mut self, name: impl Into<String>) -> &mut Self
+    where
+        T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    {
+        let name = name.into();
+        let type_id = TypeId::of::<T>();
+        let codec = TypeCodec {
+            name: name.clone(),
+            encode: encode::<T>,
+            decode: decode::<T>,
+        };
+
+        if let Some(previous) = self.codecs.insert(type_id, codec) {
+            self.names.remove(&previous.name);
+        }
+        if let Some(previous) = self.names.insert(name, type_id)
+            && previous != type_id
+        {
+            self.codecs.remove(&previous);
+        }
+        self
+    }
+
+    /// The name `T` is registered under.
+    pub fn name_of<T: 'static>(&self) -> Option<&str> {
+        self.codecs
+            .get(&TypeId::of::<T>())
+            .map(|codec| codec.name.as_str())
+    }
+
+    /// Whether a type is registered under `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains_key(name)
+    }
+
+    /// Serialize every typed entry of `context` by registered name.
+    pub(crate) fn encode(&self, context: &Context) -> Result<BTreeMap<String, Value>> {
+        context
+            .typed_entries()
+            .map(|(type_id, entry)| {
+                let codec = self.codecs.get(type_id).ok_or_else(|| {
+                    FlowError::context(format!(
+                        "Typed context entry `{}` cannot be persisted: its type is not registered \
+                         (call `TypeRegistry::register::<{}>(name)`)",
+                        entry.type_name, entry.type_name
+                    ))
+                })?;
+                Ok((codec.name.clone(), (codec.encode)(entry)?))
+            })
+            .collect()
+    }
+
+    /// Restore typed entries serialized by [`TypeRegistry::encode`].
+    pub(crate) fn decode(
+        &self,
+        typed: BTreeMap<String, Value>,
+        context: &mut Context,
+    ) -> Result<()> {
+        for (name, value) in typed {
+            let codec = self
+                .names
+                .get(&name)
+                .and_then(|type_id| self.codecs.get(type_id).map(|codec| (type_id, codec)));
+            let Some((type_id, codec)) = codec else {
+                return Err(FlowError::context(format!(
+                    "Typed context entry '{name}' cannot be restored: no type is registered \
+                     under this name"
+                )));
+            };
+            let entry = (codec.decode)(value).map_err(|error| {
+                FlowError::context(format!(
+                    "Typed context entry '{name}' could not be decoded: {error}"
+                ))
+            })?;
+            context.insert_entry(*type_id, entry);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for TypeRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<_> = self.names.keys().collect();
+        names.sort();
+        f.debug_struct("TypeRegistry")
+            .field("types", &names)
+            .finish()
+    }
+}
+
+/// A context with its typed entries serialized, see [`Context::persist`].
+///
+/// It serializes as `{"data": {..}, "metadata": {..}, "typed": {..}}`, which
+/// [`Context`]'s own `Deserialize` implementation also accepts (ignoring the
+/// typed entries).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PersistedContext {
+    #[serde(default)]
+    pub data: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, Value>,
+    /// Typed entries by registered type name.
+    #[serde(default)]
+    pub typed: BTreeMap<String, Value>,
+}
+
+impl PersistedContext {
+    /// Rebuild the context, restoring typed entries through `registry`.
+    pub fn into_context(self, registry: &TypeRegistry) -> Result<Context> {
+        let mut context = Context::from_parts(
+            self.data.into_iter().collect(),
+            self.metadata.into_iter().collect(),
+        );
+        registry.decode(self.typed, &mut context)?;
+        Ok(context)
+    }
+
+    /// Serialize as JSON.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(FlowError::from)
+    }
+
+    /// Parse from JSON.
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(FlowError::from)
+    }
+
+    /// Serialize as MessagePack.
+    #[cfg(feature = "msgpack")]
+    pub fn to_msgpack(&self) -> Result<Vec<u8>> {
+        rmp_serde::to_vec_named(self).map_err(FlowError::storage)
+    }
+
+    /// Parse from MessagePack.
+    #[cfg(feature = "msgpack")]
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Self> {
+        rmp_serde::from_slice(bytes).map_err(FlowError::storage)
+    }
+}
+
+impl Context {
+    /// Serialize the whole context, typed entries included.
+    ///
+    /// Fails if a typed entry's type is not registered in `registry`.
+    pub fn persist(&self, registry: &TypeRegistry) -> Result<PersistedContext> {
+        Ok(PersistedContext {
+            data: self
+                .json_data()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            metadata: self
+                .metadata()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            typed: registry.encode(self)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Cart {
+        items: Vec<String>,
+        total_cents: u64,
+    }
+
+    fn sample() -> Context {
+        let mut context = Context::new();
+        context.set("customer", "ada").unwrap();
+        context.set_metadata("source", "web").unwrap();
+        context
+            .insert(Cart {
+                items: vec!["book".to_string()],
+                total_cents: 1250,
+            })
+            .unwrap();
+        context.insert(7u32).unwrap();
+        context
+    }
+
+    #[test]
+    fn round_trips_typed_entries() {
+        let mut registry = TypeRegistry::new();
+        registry
+            .register::<Cart>("cart")
+            .register::<u32>("attempts");
+        assert_eq!(registry.name_of::<Cart>(), Some("cart"));
+
+        let persisted = sample().persist(&registry).unwrap();
+        let json = persisted.to_json().unwrap();
+        assert!(json.contains(r#""typed":{"attempts":7,"cart":"#));
+
+        let restored = PersistedContext::from_json(&json)
+            .unwrap()
+            .into_context(&registry)
+            .unwrap();
+        assert_eq!(restored.get::<Cart>().unwrap().total_cents, 1250);
+        assert_eq!(restored.get::<u32>(), Some(&7));
+        assert_eq!(restored.json_data(), sample().json_data());
+        assert_eq!(restored.metadata(), sample().metadata());
+
+        #[cfg(feature = "msgpack")]
+        {
+            let bytes = persisted.to_msgpack().unwrap();
+            let restored = PersistedContext::from_msgpack(&bytes)
+                .unwrap()
+                .into_context(&registry)
+                .unwrap();
+            assert_eq!(restored.get::<Cart>().unwrap().items, vec!["book"]);
+        }
+    }
+
+    #[test]
+    fn missing_registrations_are_reported() {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Cart>("cart");
+
+        let err = sample().persist(&registry).unwrap_err();
+        assert!(err.to_string().contains("`u32` cannot be persisted"));
+
+        registry.register::<u32>("attempts");
+        let persisted = sample().persist(&registry).unwrap();
+
+        let mut reader = TypeRegistry::new();
+        reader.register::<Cart>("cart");
+        let err = persisted.into_context(&reader).unwrap_err();
+        assert!(err.to_string().contains("'attempts' cannot be restored"));
+    }
+}
@@ -0,0 +1,233 @@
+//! Retry and backoff policies shared by nodes and flow engines.
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{node::Node, state::FlowState};
+
+/// Delay schedule between retry attempts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Backoff {
+    /// Retry immediately.
+    #[default]
+    None,
+    /// Wait the same amount of time before every retry.
+    Fixed(Duration),
+    /// Double the delay after every attempt, up to `max`.
+    Exponential {
+        /// Delay before the first retry.
+        initial: Duration,
+        /// Upper bound for a single delay.
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// Delay before retry number `retry` (1-based).
+    pub fn delay(&self, retry: u32) -> Duration {
+        match self {
+            Backoff::None => Duration::ZERO,
+            Backoff::Fixed(delay) => *delay,
+            Backoff::Exponential { initial, max } => {
+                let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+                initial.saturating_mul(factor).min(*max)
+            }
+        }
+    }
+}
+
+/// How many times to retry a failed operation and how long to wait in between.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt.
+    pub max_retries: u32,
+    /// Delay schedule between attempts.
+    pub backoff: Backoff,
+    /// Random extra delay added to each backoff, as a fraction of it (0.0 - 1.0).
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Never retry.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Retry up to `max_retries` times with the given backoff.
+    pub fn new(max_retries: u32, backoff: Backoff) -> Self {
+        Self {
+            max_retries,
+            backoff,
+            jitter: 0.0,
+        }
+    }
+
+    /// Add random jitter to each delay.
+    ///
+    /// Values outside 0.0 - 1.0 are clamped, and NaN or infinite values
+    /// disable jitter.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = clamp_jitter(jitter);
+        self
+    }
+
+    /// Total number of attempts, including the first one.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_retries.saturating_add(1)
+    }
+
+    /// Delay before retry number `retry` (1-based), including jitter.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let base = self.backoff.delay(retry);
+        // The field is public, so it may not have gone through `with_jitter`
+        let jitter = clamp_jitter(self.jitter);
+        if jitter == 0.0 || base.is_zero() {
+            return base;
+        }
+        base.saturating_add(base.mul_f64(jitter * fastrand::f64()))
+    }
+}
+
+/// Limit a jitter fraction to 0.0 - 1.0, treating NaN and infinities as 0.
+fn clamp_jitter(jitter: f64) -> f64 {
+    if jitter.is_finite() {
+        jitter.clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// What to do once a node has exhausted its retries.
+pub enum Fallback<S: FlowState> {
+    /// Move to this state instead of failing the flow.
+    State(S),
+    /// Run this node instead and continue with its result.
+    Node(Arc<dyn Node<State = S>>),
+}
+
+impl<S: FlowState> Clone for Fallback<S> {
+    fn clone(&self) -> Self {
+        match self {
+            Fallback::State(state) => Fallback::State(state.clone()),
+            Fallback::Node(node) => Fallback::Node(node.clone()),
+        }
+    }
+}
+
+impl<S: FlowState> std::fmt::Debug for Fallback<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fallback::State(state) => f.debug_tuple("State").field(state).finish(),
+            Fallback::Node(node) => f.debug_tuple("Node").field(&node.name()).finish(),
+        }
+    }
+}
+
+/// Execution policy for a single node in an advanced flow.
+///
+/// Each attempt is bounded by `timeout`, failed attempts are retried
+/// according to `retry`, and once retries are exhausted the `fallback`
+/// decides how the flow continues.
+#[derive(Debug, Clone)]
+pub struct NodePolicy<S: FlowState> {
+    /// Maximum duration of a single attempt.
+    pub timeout: Option<Duration>,
+    /// Retry behaviour for failed or timed out attempts.
+    pub retry: RetryPolicy,
+    /// Fallback used when every attempt failed.
+    pub fallback: Option<Fallback<S>>,
+}
+
+impl<S: FlowState> Default for NodePolicy<S> {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            retry: RetryPolicy::none(),
+            fallback: None,
+        }
+    }
+}
+
+impl<S: FlowState> NodePolicy<S> {
+    /// Create a policy with no timeout, retries or fallback.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail an attempt that takes longer than `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retry a failed attempt up to `max_retries` times.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.retry.max_retries = max_retries;
+        self
+    }
+
+    /// Delay schedule between attempts.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.retry.backoff = backoff;
+        self
+    }
+
+    /// Random extra delay added to each backoff, as a fraction of it.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.retry = self.retry.with_jitter(jitter);
+        self
+    }
+
+    /// Move to `state` when every attempt failed.
+    pub fn fallback_state(mut self, state: S) -> Self {
+        self.fallback = Some(Fallback::State(state));
+        self
+    }
+
+    /// Run `node` when every attempt failed.
+    pub fn fallback_node(mut self, node: impl Node<State = S> + 'static) -> Self {
+        self.fallback = Some(Fallback::Node(Arc::new(node)));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_backoff_is_capped() {
+        let backoff = Backoff::Exponential {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(10));
+        assert_eq!(backoff.delay(2), Duration::from_millis(20));
+        assert_eq!(backoff.delay(3), Duration::from_millis(40));
+        assert_eq!(backoff.delay(4), Duration::from_millis(50));
+        assert_eq!(backoff.delay(40), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let policy =
+            RetryPolicy::new(3, Backoff::Fixed(Duration::from_millis(100))).with_jitter(0.5);
+        assert_eq!(policy.max_attempts(), 4);
+        for retry in 1..=3 {
+            let delay = policy.delay(retry);
+            assert!(delay >= Duration::from_millis(100));
+            assert!(delay <= Duration::from_millis(150));
+        }
+
+        let base = Duration::from_millis(100);
+        for jitter in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let policy = RetryPolicy::new(1, Backoff::Fixed(base)).with_jitter(jitter);
+            assert_eq!(policy.jitter, 0.0);
+            assert_eq!(policy.delay(1), base);
+        }
+        let unchecked = RetryPolicy {
+            jitter: f64::NAN,
+            ..RetryPolicy::new(1, Backoff::Fixed(Duration::MAX))
+        };
+        assert_eq!(unchecked.delay(1), Duration::MAX);
+    }
+}
@@ -0,0 +1,536 @@
+//! Recorded runs and deterministic replay for advanced flows.
+//!
+//! [`AdvancedFlow::record`] runs a flow with context recording enabled and
+//! returns a [`Recording`]: the input context plus the full result, where
+//! every node step carries the [`ContextDiff`] the node produced. Recordings
+//! serialize to a versioned JSON format that is stable across releases.
+//!
+//! [`AdvancedFlow::replay`] re-runs the flow from the recorded input, but
+//! instead of calling nodes it returns their recorded outputs (or errors).
+//! Routers, middleware, policies and transition checks run for real, so a
+//! production run can be reproduced in a test and the engine's behaviour
+//! around it inspected step by step. Replay fails if the run takes a
+//! different path than the recording.
+
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    context::{Context, ContextDiff},
+    error::{FlowError, Result},
+    flow_advanced::{AdvancedFlow, AdvancedFlowResult, ExecutionStep, StepKind},
+    node::Node,
+    state::FlowState,
+};
+
+/// Version of the on-disk recording format written by this release.
+pub const RECORDING_FORMAT_VERSION: u32 = 1;
+
+/// A recorded flow run: its input and its result with per-step context diffs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording<S: FlowState> {
+    /// Format version, see [`RECORDING_FORMAT_VERSION`].
+    pub format_version: u32,
+    pub flow_name: String,
+    pub run_id: String,
+    pub recorded_at: DateTime<Utc>,
+    /// State the run started in.
+    pub initial_state: S,
+    /// Context the run started with (JSON data and metadata).
+    pub input: Context,
+    pub result: AdvancedFlowResult<S>,
+}
+
+impl<S> Recording<S>
+where
+    S: FlowState + Serialize + DeserializeOwned,
+{
+    /// Serialize the recording as pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(FlowError::from)
+    }
+
+    /// Parse a recording, rejecting formats newer than this release.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        let version = value
+            .get("format_version")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or_else(|| FlowError::storage("Recording has no format_version"))?;
+        if version > u64::from(RECORDING_FORMAT_VERSION) {
+            return Err(FlowError::storage(format!(
+                "Recording format version {version} is newer than supported version {RECORDING_FORMAT_VERSION}"
+            )));
+        }
+        serde_json::from_value(value).map_err(FlowError::from)
+    }
+
+    /// Write the recording to a JSON file.
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        tokio::fs::write(path, self.to_json()?).await?;
+        Ok(())
+    }
+
+    /// Read a recording from a JSON file.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let json = tokio::fs::read_to_string(path).await?;
+        Self::from_json(&json)
+    }
+}
+
+impl<S: FlowState> AdvancedFlow<S> {
+    /// Execute the flow with context recording enabled and return the run
+    /// as a [`Recording`].
+    pub async fn record(&self, context: Context) -> Result<Recording<S>> {
+        let input = context.clone();
+        let run_id = uuid::Uuid::new_v4().to_string();
+        let recorded_at = Utc::now();
+        let result = self.execute_recorded(run_id.clone(), context, None).await?;
+
+        Ok(Recording {
+            format_version: RECORDING_FORMAT_VERSION,
+            flow_name: self.name().to_string(),
+            run_id,
+            recorded_at,
+            initial_state: self.initial_state().clone(),
+            input,
+            result,
+        })
+    }
+
+    /// Re-run a recorded run, returning recorded node outputs instead of
+    /// calling nodes.
+    ///
+    /// Fails if the recording belongs to another flow, lacks context diffs,
+    /// or if the replayed run diverges from the recorded one. The replay
+    /// writes no checkpoints, so the recorded run's checkpoint is kept.
+    pub async fn replay(&self, recording: &Recording<S>) -> Result<AdvancedFlowResult<S>> {
+        if recording.flow_name != self.name() {
+            return Err(FlowError::execution(format!(
+                "Recording belongs to flow '{}', not '{}'",
+                recording.flow_name,
+                self.name()
+            )));
+        }
+        if &recording.initial_state != self.initial_state() {
+            return Err(FlowError::execution(format!(
+                "Recording starts in state {:?}, but flow '{}' starts in {:?}",
+                recording.initial_state,
+                self.name(),
+                self.initial_state()
+            )));
+        }
+
+        let cursor = Arc::new(ReplayCursor::new(&recording.result.trace)?);
+        let result = self
+            .execute_recorded(
+                recording.run_id.clone(),
+                recording.input.clone(),
+                Some(cursor.clone()),
+            )
+            .await?;
+
+        cursor.finish()?;
+        Ok(result)
+    }
+}
+
+/// A node outcome taken from a recorded trace.
+#[derive(Debug)]
+struct RecordedOutput<S> {
+    step_number: usize,
+    kind: StepKind,
+    state: S,
+    node_name: String,
+    outcome: std::result::Result<(S, ContextDiff), String>,
+}
+
+/// Recorded node outcomes, consumed in order while replaying.
+#[derive(Debug)]
+pub(crate) struct ReplayCursor<S> {
+    outputs: Mutex<VecDeque<RecordedOutput<S>>>,
+    divergence: Mutex<Option<String>>,
+}
+
+impl<S: FlowState> ReplayCursor<S> {
+    fn new(trace: &[ExecutionStep<S>]) -> Result<Self> {
+        let mut outputs = VecDeque::new();
+        for step in trace {
+            let kind = match (step.kind, &step.error) {
+                (StepKind::Node, _) => StepKind::Node,
+                // Fallback node outputs; a fallback *state* is not a node output
+                (StepKind::Fallback, None) => StepKind::Node,
+                (StepKind::Fallback, Some(_)) if step.from_state == step.to_state => StepKind::Node,
+                (StepKind::Prepare | StepKind::Cleanup, Some(_)) => step.kind,
+                (StepKind::Compensation, _) => step.kind,
+                _ => continue,
+            };
+
+            let outcome = match &step.error {
+                Some(error) => Err(error.clone()),
+                None if kind == StepKind::Compensation => {
+                    Ok((step.to_state.clone(), ContextDiff::default()))
+                }
+                None => {
+                    let diff = step.context_diff.clone().ok_or_else(|| {
+                        FlowError::execution(format!(
+                            "Step {} of the recording has no context diff; record the run with AdvancedFlow::record",
+                            step.step_number
+                        ))
+                    })?;
+                    Ok((step.to_state.clone(), diff))
+                }
+            };
+
+            outputs.push_back(RecordedOutput {
+                step_number: step.step_number,
+                kind,
+                state: step.from_state.clone(),
+                node_name: step.node_name.clone(),
+                outcome,
+            });
+        }
+
+        Ok(Self {
+            outputs: Mutex::new(outputs),
+            divergence: Mutex::new(None),
+        })
+    }
+
+    /// Wrap `node` so that it replays recorded outputs for `state`.
+    pub(crate) fn node(
+        self: &Arc<Self>,
+        node: &Arc<dyn Node<State = S>>,
+        state: &S,
+    ) -> Arc<dyn Node<State = S>> {
+        Arc::new(ReplayNode {
+            name: node.name(),
+            state: state.clone(),
+            cursor: self.clone(),
+        })
+    }
+
+    fn outputs(&self) -> std::sync::MutexGuard<'_, VecDeque<RecordedOutput<S>>> {
+        self.outputs
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Take the next output if it is a `kind` output of this node.
+    fn take_next(&self, kind: StepKind, node_name: &str, state: &S) -> Option<RecordedOutput<S>> {
+        let mut outputs = self.outputs();
+        let next = outputs.front()?;
+        if next.kind != kind || next.node_name != node_name || &next.state != state {
+            return None;
+        }
+        outputs.pop_front()
+    }
+
+    /// Take the next output if it is a failed `kind` hook of this node.
+    fn take_hook_failure(&self, kind: StepKind, node_name: &str, state: &S) -> Option<String> {
+        self.take_next(kind, node_name, state)
+            .and_then(|output| output.outcome.err())
+    }
+
+    fn take_output(&self, node_name: &str, state: &S) -> Result<RecordedOutput<S>> {
+        let mut outputs = self.outputs();
+        let diverged = match outputs.front() {
+            Some(next)
+                if next.kind == StepKind::Node
+                    && next.node_name == node_name
+                    && &next.state == state =>
+            {
+                return outputs
+                    .pop_front()
+                    .ok_or_else(|| FlowError::execution("Replay cursor is empty"));
+            }
+            Some(next) => format!(
+                "Replay diverged: node '{node_name}' ran in state {state:?}, but the recording has {} step {} of node '{}' in state {:?}",
+                next.kind, next.step_number, next.node_name, next.state
+            ),
+            None => format!(
+                "Replay diverged: node '{node_name}' ran in state {state:?} after the recording ended"
+            ),
+        };
+        drop(outputs);
+
+        self.divergence
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get_or_insert_with(|| diverged.clone());
+        Err(FlowError::execution(diverged))
+    }
+
+    /// Check that the replay consumed the whole recording.
+    fn finish(&self) -> Result<()> {
+        if let Some(divergence) = self
+            .divergence
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take()
+        {
+            return Err(FlowError::execution(divergence));
+        }
+        if let Some(next) = self.outputs().front() {
+            return Err(FlowError::execution(format!(
+                "Replay diverged: the run ended before step {} of node '{}' in state {:?}",
+                next.step_number, next.node_name, next.state
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Stand-in for a node that returns its recorded outputs.
+struct ReplayNode<S> {
+    name: String,
+    state: S,
+    cursor: Arc<ReplayCursor<S>>,
+}
+
+impl<S: FlowState> std::fmt::Debug for ReplayNode<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplayNode")
+            .field("name", &self.name)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+/// Rebuild a recorded error, keeping the variants the engine treats specially.
+fn recorded_error(message: String) -> FlowError {
+    if message == FlowError::Timeout.to_string() {
+        FlowError::Timeout
+    } else if message == FlowError::Cancelled.to_string() {
+        FlowError::Cancelled
+    } else {
+        FlowError::Replayed(message)
+    }
+}
+
+#[async_trait]
+impl<S: FlowState> Node for ReplayNode<S> {
+    type State = S;
+
+    async fn execute(&self, mut context: Context) -> Result<(Context, Self::State)> {
+        let output = self.cursor.take_output(&self.name, &self.state)?;
+        match output.outcome {
+            Ok((state, diff)) => {
+                diff.apply(&mut context);
+                Ok((context, state))
+            }
+            Err(error) => Err(recorded_error(error)),
+        }
+    }
+
+    async fn prepare(&self, _context: &Context) -> Result<()> {
+        match self
+            .cursor
+            .take_hook_failure(StepKind::Prepare, &self.name, &self.state)
+        {
+            Some(error) => Err(recorded_error(error)),
+            None => Ok(()),
+        }
+    }
+
+    async fn cleanup(&self, _context: &Context, _state: &Self::State) -> Result<()> {
+        match self
+            .cursor
+            .take_hook_failure(StepKind::Cleanup, &self.name, &self.state)
+        {
+            Some(error) => Err(recorded_error(error)),
+            None => Ok(()),
+        }
+    }
+
+    async fn compensate(&self, _context: &Context) -> Result<bool> {
+        let Some(output) = self
+            .cursor
+            .take_next(StepKind::Compensation, &self.name, &self.state)
+        else {
+            return Ok(false);
+        };
+        output.outcome.map(|_| true).map_err(recorded_error)
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{
+        checkpoint::{CheckpointStore, InMemoryCheckpointStore},
+        node::helpers,
+        policy::NodePolicy,
+        state::SimpleState,
+    };
+
+    fn counting_flow(calls: Arc<AtomicUsize>) -> AdvancedFlow<SimpleState> {
+        let flaky_calls = calls.clone();
+        AdvancedFlow::builder()
+            .name("pricing")
+            .initial_state(SimpleState::Start)
+            .on_state_with_policy(
+                SimpleState::Start,
+                helpers::fn_node("quote", move |mut context: Context| {
+                    let call = flaky_calls.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        if call == 0 {
+                            return Err(FlowError::context("upstream unavailable"));
+                        }
+                        context.set("price", 42)?;
+                        context.remove_json("draft");
+                        Ok((context, SimpleState::Processing))
+                    }
+                }),
+                NodePolicy::new().max_retries(1),
+            )
+            .on_state(
+                SimpleState::Processing,
+                helpers::fn_node("finish", move |mut context: Context| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        context.set_metadata("finished", true)?;
+                        Ok((context, SimpleState::Success))
+                    }
+                }),
+            )
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn replay_returns_recorded_outputs_without_calling_nodes() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let flow = counting_flow(calls.clone());
+
+        let mut input = Context::new();
+        input.set("draft", true).unwrap();
+        let recording = flow.record(input).await.unwrap();
+        assert!(recording.result.success);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let quote = &recording.result.trace[1];
+        let diff = quote.context_diff.as_ref().unwrap();
+        assert_eq!(diff.data.added["price"], 42);
+        assert!(diff.data.removed.contains_key("draft"));
+
+        let restored = Recording::from_json(&recording.to_json().unwrap()).unwrap();
+        let replayed = flow.replay(&restored).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(replayed.final_state, SimpleState::Success);
+        assert_eq!(
+            replayed.context.json_data(),
+            recording.result.context.json_data()
+        );
+        assert_eq!(
+            replayed.context.get_metadata::<bool>("finished").unwrap(),
+            Some(true)
+        );
+        // The failed first attempt is replayed and retried as well
+        assert_eq!(replayed.trace.len(), recording.result.trace.len());
+        assert_eq!(
+            replayed.trace[0].error.as_deref(),
+            Some("Context error: upstream unavailable")
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_detects_divergence() {
+        let flow = counting_flow(Arc::new(AtomicUsize::new(1)));
+        let recording = flow.record(Context::new()).await.unwrap();
+
+        let other = AdvancedFlow::builder()
+            .name("pricing")
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::passthrough("shortcut", SimpleState::Success),
+            )
+            .build()
+            .unwrap();
+        let err = other.replay(&recording).await.unwrap_err();
+        assert!(err.to_string().contains("Replay diverged"));
+
+        let mut newer: serde_json::Value =
+            serde_json::from_str(&recording.to_json().unwrap()).unwrap();
+        newer["format_version"] = serde_json::json!(RECORDING_FORMAT_VERSION + 1);
+        assert!(Recording::<SimpleState>::from_json(&newer.to_string()).is_err());
+    }
+
+    #[tokio::test]
+    async fn replay_leaves_the_recorded_checkpoint_alone() {
+        let store = Arc::new(InMemoryCheckpointStore::new());
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::passthrough("step", SimpleState::Success),
+            )
+            .checkpoint_store(store.clone())
+            .build()
+            .unwrap();
+
+        let recording = flow.record(Context::new()).await.unwrap();
+        let recorded = store.load(&recording.run_id).await.unwrap().unwrap();
+        flow.replay(&recording).await.unwrap();
+
+        let checkpoint = store.load(&recording.run_id).await.unwrap().unwrap();
+        assert_eq!(checkpoint.updated_at, recorded.updated_at);
+        assert!(!checkpoint.run_metadata.contains_key("replayed_at"));
+        assert_eq!(store.list_runs().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn replay_reproduces_compensations_without_running_them() {
+        let compensations = Arc::new(AtomicUsize::new(0));
+        let counter = compensations.clone();
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                helpers::compensated(
+                    helpers::passthrough("reserve", SimpleState::Processing),
+                    move |_context| {
+                        let count = counter.fetch_add(1, Ordering::SeqCst);
+                        async move {
+                            if count > 0 {
+                                return Err(FlowError::context("already released"));
+                            }
+                            Ok(())
+                        }
+                    },
+                ),
+            )
+            .on_state(
+                SimpleState::Processing,
+                helpers::fn_node("charge", |_context: Context| async move {
+                    Err::<(Context, SimpleState), _>(FlowError::context("card declined"))
+                }),
+            )
+            .build()
+            .unwrap();
+
+        let recording = flow.record(Context::new()).await.unwrap();
+        let compensation = recording.result.trace.last().unwrap();
+        assert_eq!(compensation.kind, StepKind::Compensation);
+        assert!(compensation.error.is_none());
+
+        let replayed = flow.replay(&recording).await.unwrap();
+        assert_eq!(compensations.load(Ordering::SeqCst), 1);
+        assert_eq!(replayed.trace.last().unwrap().kind, StepKind::Compensation);
+        assert!(replayed.trace.last().unwrap().error.is_none());
+    }
+}
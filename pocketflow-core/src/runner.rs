@@ -0,0 +1,607 @@
+//! Queued, prioritized execution of registered flows.
+//!
+//! A [`FlowRunner`] runs the flows of a [`FlowRegistry`] on a fixed pool of
+//! workers. Submitted runs wait in a bounded priority queue; once it is full,
+//! [`FlowRunner::submit`] waits for room and [`FlowRunner::try_submit`] fails,
+//! so a busy caller is slowed down instead of piling up tasks.
+//!
+//! ```rust,ignore
+//! let runner = FlowRunner::builder(registry)
+//!     .workers(8)
+//!     .queue_capacity(500)
+//!     .build()?;
+//!
+//! let run_id = runner.submit("ingest", context).await?;
+//! let urgent = runner.submit_with_priority("ingest", other, 10).await?;
+//!
+//! let result = runner.wait(&run_id).await?;
+//! assert_eq!(runner.status(&run_id), Some(RunStatus::Succeeded));
+//! ```
+
+use std::{
+    any::Any,
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, VecDeque},
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{Notify, Semaphore, TryAcquireError, watch},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    context::Context,
+    error::{FlowError, Result},
+    flow_advanced::{AdvancedFlowResult, FlowRegistry},
+    state::FlowState,
+};
+
+/// Lifecycle of a submitted run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// Waiting in the queue.
+    Queued,
+    /// Being executed by a worker.
+    Running,
+    /// Finished successfully.
+    Succeeded,
+    /// Finished unsuccessfully.
+    Failed,
+    /// Cancelled while queued or running.
+    Cancelled,
+}
+
+impl RunStatus {
+    /// Whether the run has finished.
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Succeeded | Self::Failed | Self::Cancelled)
+    }
+}
+
+/// A submitted run waiting for a worker.
+struct Job {
+    priority: i32,
+    sequence: u64,
+    run_id: String,
+    flow: String,
+    context: Context,
+}
+
+impl Ord for Job {
+    /// Higher priorities first, then in submission order.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Job {}
+
+struct RunEntry<S: FlowState> {
+    status: watch::Sender<RunStatus>,
+    cancel: CancellationToken,
+    outcome: Option<std::result::Result<AdvancedFlowResult<S>, String>>,
+}
+
+struct Queue<S: FlowState> {
+    jobs: BinaryHeap<Job>,
+    runs: HashMap<String, RunEntry<S>>,
+    /// Finished runs, oldest first, for result retention.
+    finished: VecDeque<String>,
+    next_sequence: u64,
+}
+
+struct Shared<S: FlowState> {
+    registry: FlowRegistry<S>,
+    queue: Mutex<Queue<S>>,
+    /// Signalled whenever a job is queued.
+    ready: Notify,
+    /// Free queue slots.
+    slots: Semaphore,
+    capacity: usize,
+    retain: usize,
+    /// Lets idle workers exit once the queue is empty.
+    draining: CancellationToken,
+    /// Stops the workers and cancels every run.
+    stop: CancellationToken,
+}
+
+impl<S: FlowState> Shared<S> {
+    fn lock(&self) -> MutexGuard<'_, Queue<S>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Record a run's outcome and drop the oldest results over the limit.
+    fn finish(
+        &self,
+        queue: &mut Queue<S>,
+        run_id: &str,
+        status: RunStatus,
+        outcome: std::result::Result<AdvancedFlowResult<S>, String>,
+    ) {
+        let Some(entry) = queue.runs.get_mut(run_id) else {
+            return;
+        };
+        entry.outcome = Some(outcome);
+        entry.status.send_replace(status);
+
+        queue.finished.push_back(run_id.to_string());
+        while queue.finished.len() > self.retain {
+            if let Some(evicted) = queue.finished.pop_front() {
+                queue.runs.remove(&evicted);
+            }
+        }
+    }
+}
+
+/// Runs registered flows on a bounded pool of workers.
+///
+/// Dropping the runner cancels queued and running flows; call
+/// [`FlowRunner::shutdown`] to let the queue drain first.
+pub struct FlowRunner<S: FlowState> {
+    shared: Arc<Shared<S>>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl<S: FlowState> FlowRunner<S> {
+    /// Create a runner builder for the flows in `registry`.
+    pub fn builder(registry: FlowRegistry<S>) -> FlowRunnerBuilder<S> {
+        FlowRunnerBuilder::new(registry)
+    }
+
+    /// Queue a run of the named flow, waiting while the queue is full.
+    pub async fn submit(&self, flow: &str, context: Context) -> Result<String> {
+        self.submit_with_priority(flow, context, 0).await
+    }
+
+    /// Queue a run with a priority; higher priorities run first.
+    pub async fn submit_with_priority(
+        &self,
+        flow: &str,
+        context: Context,
+        priority: i32,
+    ) -> Result<String> {
+        self.check_flow(flow)?;
+        self.shared
+            .slots
+            .acquire()
+            .await
+            .map_err(|_| FlowError::execution("FlowRunner is shut down"))?
+            .forget();
+        Ok(self.enqueue(flow, context, priority))
+    }
+
+    /// Queue a run without waiting, failing if the queue is full.
+    pub fn try_submit(&self, flow: &str, context: Context, priority: i32) -> Result<String> {
+        self.check_flow(flow)?;
+        match self.shared.slots.try_acquire() {
+            Ok(permit) => permit.forget(),
+            Err(TryAcquireError::NoPermits) => {
+                return Err(FlowError::execution(format!(
+                    "FlowRunner queue is full ({} runs)",
+                    self.shared.capacity
+                )));
+            }
+            Err(TryAcquireError::Closed) => {
+                return Err(FlowError::execution("FlowRunner is shut down"));
+            }
+        }
+        Ok(self.enqueue(flow, context, priority))
+    }
+
+    /// Status of a run, or `None` if it is unknown or no longer retained.
+    pub fn status(&self, run_id: &str) -> Option<RunStatus> {
+        self.shared
+            .lock()
+            .runs
+            .get(run_id)
+            .map(|entry| *entry.status.borrow())
+    }
+
+    /// Number of runs waiting for a worker.
+    pub fn queued(&self) -> usize {
+        self.shared.lock().jobs.len()
+    }
+
+    /// Result of a finished run, without waiting.
+    pub fn result(&self, run_id: &str) -> Option<Result<AdvancedFlowResult<S>>> {
+        let queue = self.shared.lock();
+        let entry = queue.runs.get(run_id)?;
+        let outcome = entry.outcome.as_ref()?;
+        Some(match outcome {
+            Ok(result) => Ok(result.clone()),
+            Err(_) if *entry.status.borrow() == RunStatus::Cancelled => Err(FlowError::Cancelled),
+            Err(error) => Err(FlowError::execution(error.clone())),
+        })
+    }
+
+    /// Wait for a run to finish and return its result.
+    ///
+    /// A run that was cancelled before it started fails with
+    /// [`FlowError::Cancelled`]; one cancelled while running returns its
+    /// partial result.
+    pub async fn wait(&self, run_id: &str) -> Result<AdvancedFlowResult<S>> {
+        let mut status = self
+            .shared
+            .lock()
+            .runs
+            .get(run_id)
+            .map(|entry| entry.status.subscribe())
+            .ok_or_else(|| FlowError::execution(format!("Unknown run '{run_id}'")))?;
+        // Fails only if the run was evicted, which the lookup below reports
+        let _ = status.wait_for(|status| status.is_finished()).await;
+
+        self.result(run_id).unwrap_or_else(|| {
+            Err(FlowError::execution(format!(
+                "Result of run '{run_id}' is no longer retained"
+            )))
+        })
+    }
+
+    /// Cancel a queued or running run.
+    ///
+    /// Returns `false` if the run is unknown or already finished.
+    pub fn cancel(&self, run_id: &str) -> bool {
+        let mut queue = self.shared.lock();
+        let Some(entry) = queue.runs.get(run_id) else {
+            return false;
+        };
+        let status = *entry.status.borrow();
+        match status {
+            RunStatus::Running => {
+                entry.cancel.cancel();
+                true
+            }
+            RunStatus::Queued => {
+                entry.cancel.cancel();
+                queue.jobs.retain(|job| job.run_id != run_id);
+                self.shared.slots.add_permits(1);
+                let cancelled = Err(FlowError::Cancelled.to_string());
+                self.shared
+                    .finish(&mut queue, run_id, RunStatus::Cancelled, cancelled);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Stop accepting runs, let the queued ones finish and wait for the
+    /// workers to exit.
+    pub async fn shutdown(&self) {
+        self.shared.slots.close();
+        self.shared.draining.cancel();
+        let workers =
+            std::mem::take(&mut *self.workers.lock().unwrap_or_else(PoisonError::into_inner));
+        for worker in workers {
+            let _ = worker.await;
+        }
+    }
+
+    fn check_flow(&self, flow: &str) -> Result<()> {
+        match self.shared.registry.get(flow) {
+            Some(_) => Ok(()),
+            None => Err(FlowError::construction(format!("Flow '{flow}' not found"))),
+        }
+    }
+
+    fn enqueue(&self, flow: &str, context: Context, priority: i32) -> String {
+        let run_id = uuid::Uuid::new_v4().to_string();
+        {
+            let mut queue = self.shared.lock();
+            let sequence = queue.next_sequence;
+            queue.next_sequence += 1;
+            queue.runs.insert(
+                run_id.clone(),
+                RunEntry {
+                    status: watch::Sender::new(RunStatus::Queued),
+                    cancel: self.shared.stop.child_token(),
+                    outcome: None,
+                },
+            );
+            queue.jobs.push(Job {
+                priority,
+                sequence,
+                run_id: run_id.clone(),
+                flow: flow.to_string(),
+                context,
+            });
+        }
+        self.shared.ready.notify_one();
+        run_id
+    }
+}
+
+impl<S: FlowState> Drop for FlowRunner<S> {
+    fn drop(&mut self) {
+        self.shared.stop.cancel();
+    }
+}
+
+/// Take queued jobs and run them until the runner stops.
+async fn work<S: FlowState>(shared: Arc<Shared<S>>) {
+    loop {
+        if shared.stop.is_cancelled() {
+            return;
+        }
+
+        let next = {
+            let mut queue = shared.lock();
+            queue.jobs.pop().and_then(|job| {
+                let entry = queue.runs.get(&job.run_id)?;
+                entry.status.send_replace(RunStatus::Running);
+                Some((job, entry.cancel.clone()))
+            })
+        };
+        let Some((job, cancel)) = next else {
+            if shared.draining.is_cancelled() {
+                return;
+            }
+            tokio::select! {
+                () = shared.ready.notified() => {}
+                () = shared.draining.cancelled() => {}
+                () = shared.stop.cancelled() => {}
+            }
+            continue;
+        };
+        shared.slots.add_permits(1);
+
+        let outcome = match shared.registry.get(&job.flow) {
+            Some(flow) => {
+                let run = flow.execute_run(job.run_id.clone(), job.context, cancel.clone());
+                // A panicking node fails its run instead of stopping the worker
+                match AssertUnwindSafe(run).catch_unwind().await {
+                    Ok(outcome) => outcome.map_err(|error| error.to_string()),
+                    Err(panic) => Err(format!("Run panicked: {}", panic_message(&*panic))),
+                }
+            }
+            None => Err(format!("Flow '{}' not found", job.flow)),
+        };
+        let status = match &outcome {
+            Ok(result) if result.success => RunStatus::Succeeded,
+            _ if cancel.is_cancelled() => RunStatus::Cancelled,
+            _ => RunStatus::Failed,
+        };
+
+        let mut queue = shared.lock();
+        shared.finish(&mut queue, &job.run_id, status, outcome);
+    }
+}
+
+/// The message a panic was raised with.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Builder for [`FlowRunner`].
+pub struct FlowRunnerBuilder<S: FlowState> {
+    registry: FlowRegistry<S>,
+    workers: usize,
+    queue_capacity: usize,
+    retain: usize,
+}
+
+impl<S: FlowState> FlowRunnerBuilder<S> {
+    fn new(registry: FlowRegistry<S>) -> Self {
+        Self {
+            registry,
+            workers: 4,
+            queue_capacity: 1024,
+            retain: 1024,
+        }
+    }
+
+    /// Number of runs executed at once (default 4).
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Number of runs that may wait in the queue (default 1024).
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Number of finished runs whose results are kept (default 1024).
+    ///
+    /// The oldest finished runs are forgotten first.
+    pub fn retain_results(mut self, retain: usize) -> Self {
+        self.retain = retain;
+        self
+    }
+
+    /// Start the workers. Must be called from within a Tokio runtime.
+    pub fn build(self) -> Result<FlowRunner<S>> {
+        if self.workers == 0 {
+            return Err(FlowError::construction(
+                "FlowRunner needs at least one worker",
+            ));
+        }
+        if self.queue_capacity == 0 {
+            return Err(FlowError::construction(
+                "FlowRunner queue capacity must be at least 1",
+            ));
+        }
+
+        let shared = Arc::new(Shared {
+            registry: self.registry,
+            queue: Mutex::new(Queue {
+                jobs: BinaryHeap::new(),
+                runs: HashMap::new(),
+                finished: VecDeque::new(),
+                next_sequence: 0,
+            }),
+            ready: Notify::new(),
+            slots: Semaphore::new(self.queue_capacity),
+            capacity: self.queue_capacity,
+            retain: self.retain,
+            draining: CancellationToken::new(),
+            stop: CancellationToken::new(),
+        });
+        let workers = (0..self.workers)
+            .map(|_| tokio::spawn(work(Arc::clone(&shared))))
+            .collect();
+
+        Ok(FlowRunner {
+            shared,
+            workers: Mutex::new(workers),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{flow_advanced::AdvancedFlow, node::helpers::fn_node, state::SimpleState};
+
+    fn registry() -> FlowRegistry<SimpleState> {
+        let flow = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                fn_node("work", |mut context: Context| async move {
+                    let delay = context.get_json::<u64>("delay_ms")?.unwrap_or(0);
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    context.set("done", true)?;
+                    Ok((context, SimpleState::Success))
+                }),
+            )
+            .build()
+            .unwrap();
+
+        let broken = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                fn_node(
+                    "explode",
+                    |_context: Context| async move { panic!("node bug") },
+                ),
+            )
+            .build()
+            .unwrap();
+
+        let mut registry = FlowRegistry::new();
+        registry.register("work".to_string(), flow);
+        registry.register("broken".to_string(), broken);
+        registry
+    }
+
+    fn delayed(ms: u64) -> Context {
+        let mut context = Context::new();
+        context.set("delay_ms", ms).unwrap();
+        context
+    }
+
+    #[tokio::test]
+    async fn runs_higher_priorities_first() {
+        let runner = FlowRunner::builder(registry()).workers(1).build().unwrap();
+
+        // Occupies the only worker while the others queue up
+        let blocker = runner.submit("work", delayed(50)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let low = runner.submit("work", Context::new()).await.unwrap();
+        let high = runner
+            .submit_with_priority("work", Context::new(), 5)
+            .await
+            .unwrap();
+        assert_eq!(runner.status(&low), Some(RunStatus::Queued));
+        assert_eq!(runner.queued(), 2);
+
+        let low_result = runner.wait(&low).await.unwrap();
+        let high_result = runner.wait(&high).await.unwrap();
+        assert!(runner.wait(&blocker).await.unwrap().success);
+        assert!(high_result.trace[0].timestamp <= low_result.trace[0].timestamp);
+        assert_eq!(low_result.metadata["run_id"], low);
+        assert_eq!(runner.status(&high), Some(RunStatus::Succeeded));
+    }
+
+    #[tokio::test]
+    async fn full_queues_push_back_and_cancelled_runs_free_their_slot() {
+        let runner = FlowRunner::builder(registry())
+            .workers(1)
+            .queue_capacity(1)
+            .build()
+            .unwrap();
+
+        let running = runner.try_submit("work", delayed(50), 0).unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let queued = runner.try_submit("work", Context::new(), 0).unwrap();
+        let err = runner.try_submit("work", Context::new(), 0).unwrap_err();
+        assert!(err.to_string().contains("queue is full"));
+
+        assert!(runner.cancel(&queued));
+        assert_eq!(runner.status(&queued), Some(RunStatus::Cancelled));
+        assert!(matches!(
+            runner.wait(&queued).await.err(),
+            Some(FlowError::Cancelled)
+        ));
+        runner.try_submit("work", Context::new(), 0).unwrap();
+
+        assert!(runner.cancel(&running));
+        let result = runner.wait(&running).await.unwrap();
+        assert!(!result.success);
+        assert_eq!(runner.status(&running), Some(RunStatus::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn retains_a_bounded_number_of_results() {
+        let runner = FlowRunner::builder(registry())
+            .workers(2)
+            .retain_results(1)
+            .build()
+            .unwrap();
+
+        let first = runner.submit("work", Context::new()).await.unwrap();
+        runner.wait(&first).await.unwrap();
+        let second = runner.submit("work", Context::new()).await.unwrap();
+        runner.wait(&second).await.unwrap();
+
+        assert_eq!(runner.status(&first), None);
+        assert!(runner.wait(&first).await.is_err());
+        assert!(runner.submit("missing", Context::new()).await.is_err());
+
+        runner.shutdown().await;
+        assert!(runner.submit("work", Context::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn panicking_runs_fail_without_stopping_the_worker() {
+        let runner = FlowRunner::builder(registry()).workers(1).build().unwrap();
+
+        let broken = runner.submit("broken", Context::new()).await.unwrap();
+        let err = runner.wait(&broken).await.unwrap_err();
+        assert!(err.to_string().contains("Run panicked: node bug"));
+        assert_eq!(runner.status(&broken), Some(RunStatus::Failed));
+
+        let next = runner.submit("work", Context::new()).await.unwrap();
+        assert!(runner.wait(&next).await.unwrap().success);
+    }
+}
@@ -0,0 +1,343 @@
+//! State and context shared by cooperating flows.
+//!
+//! A [`SharedFlowState`] holds one state machine position and one context
+//! that several flows, or several nodes, work on at once. State transitions
+//! are compare-and-swap, so two writers can never both move the state away
+//! from the same position, and readers can watch the state or individual
+//! context keys for changes. That is enough for blackboard-style workflows,
+//! where each participant waits for the keys it needs and publishes its own:
+//!
+//! ```rust
+//! use pocketflow_core::prelude::*;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<()> {
+//! let shared = SharedFlowState::new(SimpleState::Start, Context::new());
+//!
+//! let reader = shared.clone();
+//! let waiting = tokio::spawn(async move { reader.wait_for_key("draft").await });
+//!
+//! shared
+//!     .update_context(|context| context.set("draft", "hello"))
+//!     .await?;
+//! assert_eq!(waiting.await.unwrap()?, serde_json::json!("hello"));
+//!
+//! assert!(
+//!     shared
+//!         .compare_exchange_state(&SimpleState::Start, SimpleState::Success)
+//!         .await
+//!         .is_ok()
+//! );
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`AdvancedFlow::execute_shared`](crate::flow_advanced::AdvancedFlow::execute_shared)
+//! runs a whole flow against a shared state.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::sync::{RwLock, watch};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    context::{Context, ContextSnapshot},
+    error::{FlowError, Result},
+    keys::ContextKey,
+    state::FlowState,
+};
+
+struct Inner<S: FlowState> {
+    state: watch::Sender<S>,
+    context: RwLock<Context>,
+    /// Watchers of individual context keys, holding each key's last value.
+    keys: Mutex<HashMap<String, watch::Sender<Option<Value>>>>,
+    metadata: RwLock<HashMap<String, String>>,
+}
+
+/// Shared flow state for concurrent access.
+///
+/// Cloning is cheap and every clone refers to the same state.
+pub struct SharedFlowState<S: FlowState> {
+    inner: Arc<Inner<S>>,
+}
+
+impl<S: FlowState> Clone for SharedFlowState<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<S: FlowState> SharedFlowState<S> {
+    pub fn new(initial_state: S, context: Context) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: watch::Sender::new(initial_state),
+                context: RwLock::new(context),
+                keys: Mutex::new(HashMap::new()),
+                metadata: RwLock::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// The shared state a node is running against, if its flow was started
+    /// with [`AdvancedFlow::execute_shared`](crate::flow_advanced::AdvancedFlow::execute_shared).
+    pub fn from_context(context: &Context) -> Option<&Self> {
+        context.get::<Self>()
+    }
+
+    pub async fn get_state(&self) -> S {
+        self.inner.state.borrow().clone()
+    }
+
+    /// Set the state unconditionally.
+    pub async fn set_state(&self, new_state: S) {
+        self.inner.state.send_if_modified(|state| {
+            let changed = *state != new_state;
+            *state = new_state;
+            changed
+        });
+    }
+
+    /// Move to `new` if the state is still `current`.
+    ///
+    /// Returns the previous state on success and the actual state otherwise.
+    pub async fn compare_exchange_state(&self, current: &S, new: S) -> std::result::Result<S, S> {
+        let mut outcome = Err(current.clone());
+        self.inner.state.send_if_modified(|state| {
+            if state != current {
+                outcome = Err(state.clone());
+                return false;
+            }
+            let changed = *state != new;
+            outcome = Ok(std::mem::replace(state, new));
+            changed
+        });
+        outcome
+    }
+
+    /// Subscribe to state changes.
+    pub fn watch_state(&self) -> watch::Receiver<S> {
+        self.inner.state.subscribe()
+    }
+
+    /// Wait until the state satisfies `predicate` and return it.
+    pub async fn wait_for_state(&self, mut predicate: impl FnMut(&S) -> bool) -> S {
+        let mut receiver = self.watch_state();
+        if let Ok(state) = receiver.wait_for(|state| predicate(state)).await {
+            return state.clone();
+        }
+        // Unreachable in practice: the sender lives as long as `self`
+        receiver.borrow().clone()
+    }
+
+    pub async fn get_context(&self) -> Context {
+        self.inner.context.read().await.clone()
+    }
+
+    /// Modify the context, notifying watchers of the keys that changed.
+    pub async fn update_context<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Context) -> Result<()>,
+    {
+        let mut context = self.inner.context.write().await;
+        let result = f(&mut context);
+        self.notify_keys(&context);
+        result
+    }
+
+    /// Subscribe to the value of a context key; `None` while it is absent.
+    pub async fn watch_key(&self, key: &str) -> watch::Receiver<Option<Value>> {
+        // Hold the context so no update slips in before the watcher exists
+        let context = self.inner.context.read().await;
+        let mut keys = self.lock_keys();
+        keys.entry(key.to_string())
+            .or_insert_with(|| watch::Sender::new(context.get_raw(key).cloned()))
+            .subscribe()
+    }
+
+    /// Wait until `key` is present in the context and return its value.
+    pub async fn wait_for_key(&self, key: &str) -> Result<Value> {
+        let mut receiver = self.watch_key(key).await;
+        let value = receiver
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| FlowError::context(format!("Stopped watching context key '{key}'")))?;
+        value
+            .clone()
+            .ok_or_else(|| FlowError::context(format!("Context key '{key}' has no value")))
+    }
+
+    /// Wait until a typed key is present in the context and return its value.
+    pub async fn wait_for<T: DeserializeOwned>(&self, key: &ContextKey<T>) -> Result<T> {
+        let value = self.wait_for_key(key.name()).await?;
+        serde_json::from_value(value).map_err(Into::into)
+    }
+
+    pub async fn set_metadata(&self, key: String, value: String) {
+        self.inner.metadata.write().await.insert(key, value);
+    }
+
+    pub async fn get_metadata(&self, key: &str) -> Option<String> {
+        self.inner.metadata.read().await.get(key).cloned()
+    }
+
+    fn lock_keys(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<String, watch::Sender<Option<Value>>>> {
+        self.inner
+            .keys
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Publish the current value of every watched key, dropping watchers
+    /// nobody listens to anymore.
+    fn notify_keys(&self, context: &Context) {
+        self.lock_keys().retain(|key, sender| {
+            let value = context.get_raw(key);
+            sender.send_if_modified(|current| {
+                let changed = current.as_ref() != value;
+                if changed {
+                    *current = value.cloned();
+                }
+                changed
+            });
+            sender.receiver_count() > 0
+        });
+    }
+}
+
+/// A flow run's view of a [`SharedFlowState`]: the state and context it last
+/// read, against which its own changes are published.
+pub(crate) struct SharedRun<S: FlowState> {
+    shared: SharedFlowState<S>,
+    base: Option<(S, ContextSnapshot)>,
+}
+
+impl<S: FlowState> SharedRun<S> {
+    pub(crate) fn new(shared: SharedFlowState<S>) -> Self {
+        Self { shared, base: None }
+    }
+
+    /// Publish the run's state and context changes since the last refresh.
+    ///
+    /// The state moves with a compare-and-swap from the state the run read;
+    /// if another writer moved it first, nothing is published and `false` is
+    /// returned. Context changes are applied key by key, so writers touching
+    /// different keys do not overwrite each other.
+    pub(crate) async fn publish(&mut self, state: &S, context: &Context) -> bool {
+        let Some((base_state, base_context)) = self.base.take() else {
+            return true;
+        };
+        let changes = base_context.changes(context);
+        if *state == base_state && changes.is_empty() {
+            return true;
+        }
+
+        let mut shared_context = self.shared.inner.context.write().await;
+        if self
+            .shared
+            .compare_exchange_state(&base_state, state.clone())
+            .await
+            .is_err()
+        {
+            return false;
+        }
+        changes.apply(&mut shared_context);
+        self.shared.notify_keys(&shared_context);
+        true
+    }
+
+    /// Read the shared state and context, first waiting until the state is
+    /// one `handles` accepts or `cancel` fires.
+    ///
+    /// The returned context carries the shared state as a typed entry, see
+    /// [`SharedFlowState::from_context`].
+    pub(crate) async fn refresh(
+        &mut self,
+        cancel: &CancellationToken,
+        handles: impl Fn(&S) -> bool,
+    ) -> Result<(S, Context)> {
+        loop {
+            tokio::select! {
+                biased;
+                () = cancel.cancelled() => {}
+                _ = self.shared.wait_for_state(&handles) => {}
+            }
+
+            let context = self.shared.inner.context.read().await.clone();
+            let state = self.shared.get_state().await;
+            if !handles(&state) && !cancel.is_cancelled() {
+                continue;
+            }
+
+            self.base = Some((state.clone(), context.snapshot()));
+            let mut context = context;
+            context.insert(self.shared.clone())?;
+            return Ok((state, context));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SimpleState;
+
+    #[tokio::test]
+    async fn compare_exchange_only_moves_from_expected_state() {
+        let shared = SharedFlowState::new(SimpleState::Start, Context::new());
+        let mut watcher = shared.watch_state();
+
+        assert_eq!(
+            shared
+                .compare_exchange_state(&SimpleState::Start, SimpleState::Processing)
+                .await,
+            Ok(SimpleState::Start)
+        );
+        assert!(watcher.has_changed().unwrap());
+        watcher.mark_unchanged();
+
+        assert_eq!(
+            shared
+                .compare_exchange_state(&SimpleState::Start, SimpleState::Success)
+                .await,
+            Err(SimpleState::Processing)
+        );
+        assert!(!watcher.has_changed().unwrap());
+        assert_eq!(shared.get_state().await, SimpleState::Processing);
+    }
+
+    #[tokio::test]
+    async fn key_watchers_see_only_their_key() {
+        const SCORE: ContextKey<u32> = ContextKey::new("score");
+        let shared = SharedFlowState::new(SimpleState::Start, Context::new());
+        let score = shared.watch_key("score").await;
+
+        let waiting = tokio::spawn({
+            let shared = shared.clone();
+            async move { shared.wait_for(&SCORE).await }
+        });
+
+        shared
+            .update_context(|context| context.set("other", 1))
+            .await
+            .unwrap();
+        assert!(!score.has_changed().unwrap());
+
+        shared
+            .update_context(|context| context.put(&SCORE, 42))
+            .await
+            .unwrap();
+        assert!(score.has_changed().unwrap());
+        assert_eq!(waiting.await.unwrap().unwrap(), 42);
+    }
+}
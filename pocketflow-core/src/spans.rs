@@ -0,0 +1,227 @@
+//! Tracing spans for flow runs and node executions.
+//!
+//! With the `tracing` feature on, every flow run opens a `flow` span and
+//! every node execution a child `node` span. Work a node awaits, such as a
+//! sub-flow, an MCP call or a tool execution, nests under the node's span.
+//! Errors are recorded in the span's `error` field, with `otel.status_code`
+//! set to `ERROR` for OpenTelemetry subscribers.
+
+use std::{
+    fmt::{Debug, Display},
+    future::Future,
+};
+
+pub(crate) use imp::Span;
+
+#[cfg(feature = "tracing")]
+mod imp {
+    use tracing::{Instrument, field::Empty};
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    pub(crate) struct Span(tracing::Span);
+
+    impl Span {
+        /// Open the span of a flow run.
+        pub(crate) fn flow<S: Debug>(flow: &str, run_id: &str, initial_state: &S) -> Self {
+            Self(tracing::info_span!(
+                "flow",
+                flow,
+                run_id,
+                initial_state = ?initial_state,
+                final_state = Empty,
+                steps = Empty,
+                error = Empty,
+                otel.status_code = Empty,
+            ))
+        }
+
+        /// Open the span of a node execution, as a child of the current span.
+        pub(crate) fn node<S: Debug>(node: &str, from: &S, step: usize) -> Self {
+            Self(tracing::info_span!(
+                "node",
+                node,
+                from = ?from,
+                to = Empty,
+                step,
+                error = Empty,
+                otel.status_code = Empty,
+            ))
+        }
+
+        /// Record a field declared when the span was opened.
+        pub(crate) fn record(&self, field: &'static str, value: impl Debug) {
+            self.0.record(field, tracing::field::debug(value));
+        }
+
+        /// Mark the span as failed.
+        pub(crate) fn record_error(&self, error: impl Display) {
+            self.0.record("error", tracing::field::display(&error));
+            self.0.record("otel.status_code", "ERROR");
+            tracing::error!(parent: &self.0, "{error}");
+        }
+
+        /// Run `future` inside the span.
+        pub(crate) fn instrument<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+            future.instrument(self.0.clone())
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod imp {
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    pub(crate) struct Span;
+
+    impl Span {
+        pub(crate) fn flow<S: Debug>(_flow: &str, _run_id: &str, _initial_state: &S) -> Self {
+            Self
+        }
+
+        pub(crate) fn node<S: Debug>(_node: &str, _from: &S, _step: usize) -> Self {
+            Self
+        }
+
+        pub(crate) fn record(&self, _field: &'static str, _value: impl Debug) {}
+
+        pub(crate) fn record_error(&self, _error: impl Display) {}
+
+        pub(crate) fn instrument<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+            future
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing::{
+        Event, Id, Metadata, Subscriber,
+        field::{Field, Visit},
+        span::{Attributes, Record},
+    };
+
+    use crate::{
+        context::Context, error::FlowError, flow_advanced::AdvancedFlow, node::helpers::fn_node,
+        state::SimpleState,
+    };
+
+    #[derive(Debug, Default)]
+    struct SpanData {
+        name: &'static str,
+        parent: Option<u64>,
+        fields: BTreeMap<String, String>,
+    }
+
+    impl Visit for SpanData {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.fields
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.fields
+                .insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    /// Keeps every span with its parent and recorded fields.
+    #[derive(Clone, Default)]
+    struct Collector {
+        spans: Arc<Mutex<Vec<SpanData>>>,
+        stack: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl Subscriber for Collector {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+            let parent = match attrs.parent() {
+                Some(parent) => Some(parent.into_u64()),
+                None if attrs.is_contextual() => self.stack.lock().unwrap().last().copied(),
+                None => None,
+            };
+            let mut data = SpanData {
+                name: attrs.metadata().name(),
+                parent,
+                ..SpanData::default()
+            };
+            attrs.record(&mut data);
+
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(data);
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut spans[span.into_u64() as usize - 1]);
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, span: &Id) {
+            self.stack.lock().unwrap().push(span.into_u64());
+        }
+
+        fn exit(&self, _: &Id) {
+            self.stack.lock().unwrap().pop();
+        }
+    }
+
+    #[test]
+    fn node_spans_nest_under_the_flow_span() {
+        let collector = Collector::default();
+        let _guard = tracing::subscriber::set_default(collector.clone());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let flow = AdvancedFlow::builder()
+            .name("checkout")
+            .initial_state(SimpleState::Start)
+            .on_state(
+                SimpleState::Start,
+                fn_node("reserve", |context: Context| async move {
+                    tracing::info_span!("tool").in_scope(|| ());
+                    Ok((context, SimpleState::Processing))
+                }),
+            )
+            .on_state(
+                SimpleState::Processing,
+                fn_node("charge", |_context: Context| async move {
+                    Err::<(Context, SimpleState), _>(FlowError::context("card declined"))
+                }),
+            )
+            .build()
+            .unwrap();
+        let result = runtime.block_on(flow.execute(Context::new())).unwrap();
+        assert!(!result.success);
+
+        let spans = collector.spans.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|span| span.name).collect();
+        assert_eq!(names, ["flow", "node", "tool", "node"]);
+
+        let (flow, reserve, tool, charge) = (&spans[0], &spans[1], &spans[2], &spans[3]);
+        assert_eq!(flow.fields["flow"], "checkout");
+        assert_eq!(flow.fields["run_id"], result.metadata["run_id"]);
+        assert_eq!(flow.fields["initial_state"], "Start");
+        assert_eq!(flow.fields["otel.status_code"], "ERROR");
+        assert_eq!(reserve.parent, Some(1));
+        assert_eq!(reserve.fields["to"], "Processing");
+        assert_eq!(tool.parent, Some(2));
+        assert_eq!(charge.fields["step"], "2");
+        assert!(charge.fields["error"].contains("card declined"));
+    }
+}
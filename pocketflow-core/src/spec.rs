@@ -0,0 +1,661 @@
+//! Declarative flow definitions.
+//!
+//! A [`FlowSpec`] describes an advanced flow in YAML or JSON: its states, the
+//! node handling each state, conditional routes, `max_steps` and middleware.
+//! Nodes, conditions and middleware are looked up by name in a
+//! [`NodeRegistry`], so a workflow can be changed without recompiling.
+//!
+//! ```yaml
+//! name: order_review
+//! initial_state: start
+//! max_steps: 50
+//! middleware: [logging, timing]
+//! states:
+//!   - name: start
+//!     node: fetch_order
+//!   - name: check
+//!     route:
+//!       when: { key: approved, equals: true }
+//!       then: approved
+//!       otherwise: rejected
+//!   - name: approved
+//!     terminal: true
+//!   - name: rejected
+//!     terminal: true
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    context::Context,
+    error::{FlowError, Result},
+    flow_advanced::AdvancedFlow,
+    middleware::{FlowMiddleware, LoggingMiddleware, TimingMiddleware},
+    node::Node,
+    state::{FlowState, NamedState, SimpleState},
+};
+
+/// Serializable description of an advanced flow.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlowSpec {
+    /// Flow name.
+    pub name: String,
+    /// State the flow starts in.
+    pub initial_state: String,
+    /// Maximum number of steps before the flow is aborted.
+    #[serde(default = "default_max_steps")]
+    pub max_steps: usize,
+    /// Validate node transitions against the state type's rules.
+    #[serde(default = "default_strict")]
+    pub strict_transitions: bool,
+    /// Names of registered middleware, applied in order.
+    #[serde(default)]
+    pub middleware: Vec<String>,
+    /// Every state of the flow.
+    pub states: Vec<StateSpec>,
+}
+
+fn default_max_steps() -> usize {
+    1000
+}
+
+fn default_strict() -> bool {
+    true
+}
+
+/// A state and how it is handled.
+///
+/// Each non-terminal state has either a `node` or a `route`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateSpec {
+    /// State name.
+    pub name: String,
+    /// Name of the registered node handling this state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    /// Configuration passed to the node factory.
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub config: Value,
+    /// Conditional route taken from this state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<RouteSpec>,
+    /// Whether the flow stops in this state.
+    #[serde(default)]
+    pub terminal: bool,
+}
+
+/// Conditional route, the declarative form of `when_state`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteSpec {
+    /// Condition deciding between the two targets.
+    pub when: ConditionSpec,
+    /// State to move to when the condition holds.
+    pub then: String,
+    /// State to move to otherwise.
+    pub otherwise: String,
+}
+
+/// Condition of a [`RouteSpec`].
+///
+/// Either checks a context key (truthy, or equal to `equals`) or names a
+/// condition registered in the [`NodeRegistry`].
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ConditionSpec {
+    /// Context key to inspect.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Value the key must equal; without it the key must be truthy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<Value>,
+    /// Name of a registered condition.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+}
+
+impl FlowSpec {
+    /// Parse a spec from YAML.
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        serde_yaml::from_str(yaml)
+            .map_err(|e| FlowError::construction(format!("Invalid flow spec: {e}")))
+    }
+
+    /// Parse a spec from JSON.
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| FlowError::construction(format!("Invalid flow spec: {e}")))
+    }
+
+    /// Load a spec from a `.yaml`, `.yml` or `.json` file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Self::from_json(&contents),
+            Some("yaml" | "yml") => Self::from_yaml(&contents),
+            _ => Err(FlowError::construction(format!(
+                "Unsupported flow spec format: {}",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Serialize the spec to YAML.
+    pub fn to_yaml(&self) -> Result<String> {
+        serde_yaml::to_string(self)
+            .map_err(|e| FlowError::construction(format!("Invalid flow spec: {e}")))
+    }
+
+    /// Check the spec against a registry without building it.
+    pub fn validate<S: SpecState>(&self, registry: &NodeRegistry<S>) -> Result<()> {
+        let invalid = |message: String| {
+            Err(FlowError::construction(format!(
+                "Invalid flow spec '{}': {message}",
+                self.name
+            )))
+        };
+
+        if self.max_steps == 0 {
+            return invalid("max_steps must be greater than zero".to_string());
+        }
+
+        let mut names = HashSet::new();
+        for state in &self.states {
+            if !names.insert(state.name.as_str()) {
+                return invalid(format!("state '{}' is declared twice", state.name));
+            }
+        }
+        if !names.contains(self.initial_state.as_str()) {
+            return invalid(format!(
+                "initial state '{}' is not declared",
+                self.initial_state
+            ));
+        }
+
+        for state in &self.states {
+            S::from_spec(&state.name, state.terminal)?;
+
+            if state.terminal && (state.node.is_some() || state.route.is_some()) {
+                return invalid(format!(
+                    "terminal state '{}' cannot have a node or route",
+                    state.name
+                ));
+            }
+            if state.node.is_some() && state.route.is_some() {
+                return invalid(format!(
+                    "state '{}' has both a node and a route",
+                    state.name
+                ));
+            }
+            if !state.terminal && state.node.is_none() && state.route.is_none() {
+                return invalid(format!(
+                    "state '{}' has no node or route and is not terminal",
+                    state.name
+                ));
+            }
+
+            if let Some(node) = &state.node
+                && !registry.factories.contains_key(node)
+            {
+                return invalid(format!(
+                    "state '{}' uses unregistered node '{node}'",
+                    state.name
+                ));
+            }
+
+            if let Some(route) = &state.route {
+                for target in [&route.then, &route.otherwise] {
+                    if !names.contains(target.as_str()) {
+                        return invalid(format!(
+                            "route from '{}' targets undeclared state '{target}'",
+                            state.name
+                        ));
+                    }
+                }
+                match (&route.when.key, &route.when.condition) {
+                    (Some(_), None) => {}
+                    (None, Some(condition)) if registry.conditions.contains_key(condition) => {}
+                    (None, Some(condition)) => {
+                        return invalid(format!(
+                            "route from '{}' uses unregistered condition '{condition}'",
+                            state.name
+                        ));
+                    }
+                    _ => {
+                        return invalid(format!(
+                            "route from '{}' needs exactly one of `key` or `condition`",
+                            state.name
+                        ));
+                    }
+                }
+            }
+        }
+
+        for middleware in &self.middleware {
+            if !registry.middleware.contains_key(middleware) {
+                return invalid(format!("unregistered middleware '{middleware}'"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate the spec and build the flow it describes.
+    pub fn build<S: SpecState>(&self, registry: &NodeRegistry<S>) -> Result<AdvancedFlow<S>> {
+        self.validate(registry)?;
+
+        let mut states = HashMap::new();
+        for state in &self.states {
+            states.insert(
+                state.name.clone(),
+                S::from_spec(&state.name, state.terminal)?,
+            );
+        }
+        let states = Arc::new(states);
+        let lookup = |name: &str| -> Result<S> {
+            states
+                .get(name)
+                .cloned()
+                .ok_or_else(|| FlowError::construction(format!("State '{name}' is not declared")))
+        };
+
+        let mut builder = AdvancedFlow::builder()
+            .name(self.name.clone())
+            .initial_state(lookup(&self.initial_state)?)
+            .max_steps(self.max_steps)
+            .strict_transitions(self.strict_transitions);
+
+        for state in &self.states {
+            let current = lookup(&state.name)?;
+
+            if let Some(node) = &state.node {
+                let node = registry.create(node, &state.config)?;
+                builder = builder.on_state(
+                    current.clone(),
+                    SpecNode {
+                        inner: node,
+                        states: states.clone(),
+                    },
+                );
+            }
+
+            if let Some(route) = &state.route {
+                let condition = registry.condition(&route.when)?;
+                builder = builder.when_state(
+                    current,
+                    move |context, state| condition(context, state),
+                    lookup(&route.then)?,
+                    lookup(&route.otherwise)?,
+                );
+            }
+        }
+
+        for name in &self.middleware {
+            if let Some(factory) = registry.middleware.get(name) {
+                builder = builder.with_middleware(factory());
+            }
+        }
+
+        builder.build()
+    }
+}
+
+/// States that can be named in a [`FlowSpec`].
+pub trait SpecState: FlowState {
+    /// Build the state called `name`; `terminal` is whether the spec marks it terminal.
+    fn from_spec(name: &str, terminal: bool) -> Result<Self>;
+
+    /// Name of the state as written in a spec.
+    fn spec_name(&self) -> String;
+}
+
+impl SpecState for NamedState {
+    fn from_spec(name: &str, terminal: bool) -> Result<Self> {
+        Ok(if terminal {
+            NamedState::terminal(name)
+        } else {
+            NamedState::new(name)
+        })
+    }
+
+    fn spec_name(&self) -> String {
+        self.name().to_string()
+    }
+}
+
+impl SpecState for SimpleState {
+    fn from_spec(name: &str, terminal: bool) -> Result<Self> {
+        let state = match name.to_ascii_lowercase().as_str() {
+            "start" => SimpleState::Start,
+            "processing" => SimpleState::Processing,
+            "success" => SimpleState::Success,
+            "error" => SimpleState::Error,
+            _ => SimpleState::Custom(name.to_string()),
+        };
+        if state.is_terminal() != terminal {
+            return Err(FlowError::construction(format!(
+                "SimpleState '{name}' must {}be marked terminal",
+                if state.is_terminal() { "" } else { "not " }
+            )));
+        }
+        Ok(state)
+    }
+
+    fn spec_name(&self) -> String {
+        match self {
+            SimpleState::Custom(name) => name.clone(),
+            other => format!("{other:?}").to_ascii_lowercase(),
+        }
+    }
+}
+
+/// Factory creating a node from its spec configuration.
+pub type NodeFactory<S> = Arc<dyn Fn(&Value) -> Result<Arc<dyn Node<State = S>>> + Send + Sync>;
+
+/// Named condition usable in routes.
+pub type NamedCondition<S> = Arc<dyn Fn(&Context, &S) -> bool + Send + Sync>;
+
+/// Factory creating a middleware instance.
+pub type MiddlewareFactory<S> = Arc<dyn Fn() -> Arc<dyn FlowMiddleware<S>> + Send + Sync>;
+
+/// Named nodes, conditions and middleware available to flow specs.
+///
+/// The built-in `logging` and `timing` middleware (and `metrics` with the
+/// `metrics` feature) are registered by default.
+pub struct NodeRegistry<S: FlowState> {
+    factories: HashMap<String, NodeFactory<S>>,
+    conditions: HashMap<String, NamedCondition<S>>,
+    middleware: HashMap<String, MiddlewareFactory<S>>,
+}
+
+impl<S: FlowState> NodeRegistry<S> {
+    /// Create a registry with the built-in middleware.
+    pub fn new() -> Self {
+        let mut registry = Self {
+            factories: HashMap::new(),
+            conditions: HashMap::new(),
+            middleware: HashMap::new(),
+        };
+        registry.register_middleware("logging", || Arc::new(LoggingMiddleware));
+        registry.register_middleware("timing", || Arc::new(TimingMiddleware));
+        #[cfg(feature = "metrics")]
+        registry.register_middleware("metrics", || Arc::new(crate::middleware::MetricsMiddleware));
+        registry
+    }
+
+    /// Register a node instance shared by every state using it.
+    pub fn register_node(
+        &mut self,
+        name: impl Into<String>,
+        node: impl Node<State = S> + 'static,
+    ) -> &mut Self {
+        let node: Arc<dyn Node<State = S>> = Arc::new(node);
+        self.register_factory(name, move |_config| Ok(node.clone()))
+    }
+
+    /// Register a factory building a node from the state's `config`.
+    pub fn register_factory<F>(&mut self, name: impl Into<String>, factory: F) -> &mut Self
+    where
+        F: Fn(&Value) -> Result<Arc<dyn Node<State = S>>> + Send + Sync + 'static,
+    {
+        self.factories.insert(name.into(), Arc::new(factory));
+        self
+    }
+
+    /// Register a condition usable as `when: { condition: <name> }`.
+    pub fn register_condition<F>(&mut self, name: impl Into<String>, condition: F) -> &mut Self
+    where
+        F: Fn(&Context, &S) -> bool + Send + Sync + 'static,
+    {
+        self.conditions.insert(name.into(), Arc::new(condition));
+        self
+    }
+
+    /// Register middleware usable in the spec's `middleware` list.
+    pub fn register_middleware<F>(&mut self, name: impl Into<String>, factory: F) -> &mut Self
+    where
+        F: Fn() -> Arc<dyn FlowMiddleware<S>> + Send + Sync + 'static,
+    {
+        self.middleware.insert(name.into(), Arc::new(factory));
+        self
+    }
+
+    /// Names of the registered nodes.
+    pub fn node_names(&self) -> Vec<&str> {
+        self.factories.keys().map(|name| name.as_str()).collect()
+    }
+
+    fn create(&self, name: &str, config: &Value) -> Result<Arc<dyn Node<State = S>>> {
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| FlowError::construction(format!("Node '{name}' is not registered")))?;
+        factory(config)
+    }
+
+    fn condition(&self, spec: &ConditionSpec) -> Result<NamedCondition<S>> {
+        if let Some(name) = &spec.condition {
+            return self.conditions.get(name).cloned().ok_or_else(|| {
+                FlowError::construction(format!("Condition '{name}' is not registered"))
+            });
+        }
+
+        let key = spec
+            .key
+            .clone()
+            .ok_or_else(|| FlowError::construction("Route condition needs a key"))?;
+        let expected = spec.equals.clone();
+        Ok(Arc::new(move |context, _state| {
+            match (context.get_raw(&key), &expected) {
+                (Some(value), Some(expected)) => value == expected,
+                (Some(value), None) => is_truthy(value),
+                (None, _) => false,
+            }
+        }))
+    }
+}
+
+impl<S: FlowState> Default for NodeRegistry<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(flag) => *flag,
+        Value::Number(number) => number.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(fields) => !fields.is_empty(),
+    }
+}
+
+/// Registry node restricted to the states declared in the spec.
+///
+/// Returned states are replaced by their declared counterpart, so a node
+/// returning `NamedState::new("done")` ends the flow if `done` is terminal.
+struct SpecNode<S: FlowState> {
+    inner: Arc<dyn Node<State = S>>,
+    states: Arc<HashMap<String, S>>,
+}
+
+impl<S: FlowState> std::fmt::Debug for SpecNode<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SpecNode").field(&self.inner).finish()
+    }
+}
+
+#[async_trait]
+impl<S: SpecState> Node for SpecNode<S> {
+    type State = S;
+
+    async fn execute(&self, context: Context) -> Result<(Context, Self::State)> {
+        let (context, state) = self.inner.execute(context).await?;
+        let name = state.spec_name();
+        let state = self.states.get(&name).cloned().ok_or_else(|| {
+            FlowError::execution(format!(
+                "Node '{}' returned undeclared state '{name}'",
+                self.inner.name()
+            ))
+        })?;
+        Ok((context, state))
+    }
+
+    async fn prepare(&self, context: &Context) -> Result<()> {
+        self.inner.prepare(context).await
+    }
+
+    async fn cleanup(&self, context: &Context, state: &Self::State) -> Result<()> {
+        self.inner.cleanup(context, state).await
+    }
+
+    async fn compensate(&self, context: &Context) -> Result<bool> {
+        self.inner.compensate(context).await
+    }
+
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn emits(&self) -> Option<Vec<Self::State>> {
+        let emits = self.inner.emits()?;
+        Some(
+            emits
+                .iter()
+                .map(|state| {
+                    self.states
+                        .get(&state.spec_name())
+                        .cloned()
+                        .unwrap_or_else(|| state.clone())
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::helpers;
+
+    const REVIEW_FLOW: &str = r#"
+name: order_review
+initial_state: start
+max_steps: 20
+middleware: [timing]
+states:
+  - name: start
+    node: score
+    config: { bonus: 10 }
+  - name: check
+    route:
+      when: { key: approved, equals: true }
+      then: approved
+      otherwise: rejected
+  - name: approved
+    terminal: true
+  - name: rejected
+    terminal: true
+"#;
+
+    fn registry() -> NodeRegistry<NamedState> {
+        let mut registry = NodeRegistry::new();
+        registry.register_factory("score", |config| {
+            let bonus = config.get("bonus").and_then(Value::as_i64).unwrap_or(0);
+            let node = helpers::fn_node("score", move |mut context: Context| async move {
+                let amount: i64 = context.get_json("amount")?.unwrap_or_default();
+                context.set("approved", amount + bonus >= 100)?;
+                Ok((context, NamedState::new("check")))
+            });
+            Ok(Arc::new(node) as Arc<dyn Node<State = NamedState>>)
+        });
+        registry
+    }
+
+    #[tokio::test]
+    async fn yaml_spec_builds_a_runnable_flow() {
+        let spec = FlowSpec::from_yaml(REVIEW_FLOW).unwrap();
+        let flow = spec.build(&registry()).unwrap();
+        assert_eq!(flow.name(), "order_review");
+
+        let mut context = Context::new();
+        context.set("amount", 95).unwrap();
+        let result = flow.execute(context).await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.final_state.name(), "approved");
+        assert!(result.context.get_metadata_raw("node_timings").is_some());
+
+        let mut context = Context::new();
+        context.set("amount", 5).unwrap();
+        let result = flow.execute(context).await.unwrap();
+        assert_eq!(result.final_state, NamedState::new("rejected"));
+
+        // Specs survive a round trip through YAML and JSON
+        let yaml = spec.to_yaml().unwrap();
+        assert_eq!(FlowSpec::from_yaml(&yaml).unwrap(), spec);
+        let json = serde_json::to_string(&spec).unwrap();
+        assert_eq!(FlowSpec::from_json(&json).unwrap(), spec);
+    }
+
+    #[test]
+    fn validation_reports_bad_references() {
+        let registry = registry();
+        let mut spec = FlowSpec::from_yaml(REVIEW_FLOW).unwrap();
+
+        spec.states[0].node = Some("missing".to_string());
+        let error = spec.validate(&registry).unwrap_err().to_string();
+        assert!(error.contains("unregistered node 'missing'"), "{error}");
+
+        let mut spec = FlowSpec::from_yaml(REVIEW_FLOW).unwrap();
+        spec.states[1].route.as_mut().unwrap().then = "nowhere".to_string();
+        let error = spec.validate(&registry).unwrap_err().to_string();
+        assert!(error.contains("undeclared state 'nowhere'"), "{error}");
+
+        let mut spec = FlowSpec::from_yaml(REVIEW_FLOW).unwrap();
+        spec.middleware.push("unknown".to_string());
+        assert!(spec.validate(&registry).is_err());
+
+        let mut spec = FlowSpec::from_yaml(REVIEW_FLOW).unwrap();
+        spec.states[2].terminal = false;
+        let error = spec.validate(&registry).unwrap_err().to_string();
+        assert!(error.contains("no node or route"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn simple_state_specs_and_undeclared_results() {
+        let json = r#"{
+            "name": "simple",
+            "initial_state": "start",
+            "states": [
+                { "name": "start", "node": "finish" },
+                { "name": "success", "terminal": true }
+            ]
+        }"#;
+
+        let mut registry = NodeRegistry::new();
+        registry.register_node(
+            "finish",
+            helpers::passthrough("finish", SimpleState::Success),
+        );
+        let flow = FlowSpec::from_json(json).unwrap().build(&registry).unwrap();
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert_eq!(result.final_state, SimpleState::Success);
+
+        // Nodes may only return states declared in the spec
+        let mut registry = NodeRegistry::new();
+        registry.register_node("finish", helpers::passthrough("finish", SimpleState::Error));
+        let flow = FlowSpec::from_json(json).unwrap().build(&registry).unwrap();
+        let result = flow.execute(Context::new()).await.unwrap();
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("undeclared state 'error'"));
+    }
+}
@@ -1,5 +1,11 @@
 //! State management for PocketFlow workflows.
 
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{FlowError, Result};
+
 /// Trait representing a state in the workflow.
 ///
 /// States define the current position in the workflow and control
@@ -10,6 +16,13 @@ pub trait FlowState:
     /// Returns true if this is a terminal state (workflow should stop).
     fn is_terminal(&self) -> bool;
 
+    /// Returns true if this is a terminal state that marks a failed run.
+    ///
+    /// Runs ending in such a state are reported as unsuccessful.
+    fn is_error(&self) -> bool {
+        false
+    }
+
     /// Returns true if this state can transition to the target state.
     /// Default implementation allows all transitions.
     fn can_transition_to(&self, _target: &Self) -> bool {
@@ -18,7 +31,7 @@ pub trait FlowState:
 }
 
 /// A simple enum-based state for basic workflows.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SimpleState {
     /// Initial state
     Start,
@@ -37,6 +50,10 @@ impl FlowState for SimpleState {
         matches!(self, SimpleState::Success | SimpleState::Error)
     }
 
+    fn is_error(&self) -> bool {
+        matches!(self, SimpleState::Error)
+    }
+
     fn can_transition_to(&self, target: &Self) -> bool {
         match (self, target) {
             // Can't transition from terminal states
@@ -52,6 +69,73 @@ impl FlowState for SimpleState {
     }
 }
 
+/// A string-keyed state for flows defined at runtime.
+///
+/// States are identified by name only; the terminal flag is carried along so
+/// a loaded flow knows where to stop. Used by flows built from a
+/// [`FlowSpec`](crate::spec::FlowSpec).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NamedState {
+    name: String,
+    #[serde(default)]
+    terminal: bool,
+}
+
+impl NamedState {
+    /// Create a non-terminal state.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            terminal: false,
+        }
+    }
+
+    /// Create a terminal state.
+    pub fn terminal(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            terminal: true,
+        }
+    }
+
+    /// Name of the state.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl PartialEq for NamedState {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for NamedState {}
+
+impl std::hash::Hash for NamedState {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+impl std::fmt::Debug for NamedState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl std::fmt::Display for NamedState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl FlowState for NamedState {
+    fn is_terminal(&self) -> bool {
+        self.terminal
+    }
+}
+
 /// State transition information.
 #[derive(Clone, Debug)]
 pub struct StateTransition<S: FlowState> {
@@ -88,6 +172,111 @@ impl<S: FlowState> StateTransition<S> {
     }
 }
 
+/// Flow-level table of allowed transitions.
+///
+/// When a table is attached to a flow it replaces
+/// [`FlowState::can_transition_to`]: a transition is legal only if it has been
+/// declared here.
+#[derive(Clone, Debug)]
+pub struct TransitionTable<S: FlowState> {
+    allowed: HashMap<S, HashSet<S>>,
+}
+
+impl<S: FlowState> TransitionTable<S> {
+    /// Create an empty table (no transitions allowed).
+    pub fn new() -> Self {
+        Self {
+            allowed: HashMap::new(),
+        }
+    }
+
+    /// Allow a transition from `from` to `to`.
+    pub fn allow(mut self, from: S, to: S) -> Self {
+        self.insert(from, to);
+        self
+    }
+
+    /// Allow transitions from `from` to each of `targets`.
+    pub fn allow_many(mut self, from: S, targets: impl IntoIterator<Item = S>) -> Self {
+        for to in targets {
+            self.insert(from.clone(), to);
+        }
+        self
+    }
+
+    /// Add an allowed transition in place.
+    pub fn insert(&mut self, from: S, to: S) {
+        self.allowed.entry(from).or_default().insert(to);
+    }
+
+    /// Check whether a transition has been declared.
+    pub fn allows(&self, from: &S, to: &S) -> bool {
+        self.allowed
+            .get(from)
+            .is_some_and(|targets| targets.contains(to))
+    }
+
+    /// Iterate over the declared transitions.
+    pub fn iter(&self) -> impl Iterator<Item = (&S, &S)> {
+        self.allowed
+            .iter()
+            .flat_map(|(from, targets)| targets.iter().map(move |to| (from, to)))
+    }
+}
+
+impl<S: FlowState> Default for TransitionTable<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Transition checking shared by the flow engines.
+#[derive(Clone, Debug)]
+pub(crate) struct TransitionRules<S: FlowState> {
+    pub(crate) strict: bool,
+    pub(crate) table: Option<TransitionTable<S>>,
+}
+
+impl<S: FlowState> TransitionRules<S> {
+    /// Strict checking against [`FlowState::can_transition_to`].
+    pub(crate) fn strict() -> Self {
+        Self {
+            strict: true,
+            table: None,
+        }
+    }
+
+    /// Add an allowed transition, creating the table if necessary.
+    pub(crate) fn allow(&mut self, from: S, to: S) {
+        self.table
+            .get_or_insert_with(TransitionTable::new)
+            .insert(from, to);
+    }
+
+    /// The declared table, if it is being enforced.
+    pub(crate) fn enforced_table(&self) -> Option<&TransitionTable<S>> {
+        self.table.as_ref().filter(|_| self.strict)
+    }
+
+    /// Check whether `node` may move the flow from `from` to `to`.
+    pub(crate) fn check(&self, node: &str, from: &S, to: &S) -> Result<()> {
+        if !self.strict {
+            return Ok(());
+        }
+
+        let allowed = match &self.table {
+            Some(table) => table.allows(from, to),
+            None => StateTransition::new(from.clone(), to.clone()).is_valid(),
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(FlowError::invalid_node_transition(node, from, to))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,6 +311,49 @@ mod tests {
         assert!(!invalid_transition.is_valid());
     }
 
+    #[test]
+    fn test_transition_table() {
+        let table = TransitionTable::new()
+            .allow(SimpleState::Start, SimpleState::Processing)
+            .allow_many(
+                SimpleState::Processing,
+                [SimpleState::Success, SimpleState::Error],
+            );
+
+        assert!(table.allows(&SimpleState::Start, &SimpleState::Processing));
+        assert!(table.allows(&SimpleState::Processing, &SimpleState::Error));
+        assert!(!table.allows(&SimpleState::Start, &SimpleState::Success));
+        assert_eq!(table.iter().count(), 3);
+    }
+
+    #[test]
+    fn test_transition_rules_name_the_node() {
+        let rules = TransitionRules::strict();
+        assert!(
+            rules
+                .check("n", &SimpleState::Start, &SimpleState::Processing)
+                .is_ok()
+        );
+
+        let err = rules
+            .check("finisher", &SimpleState::Success, &SimpleState::Processing)
+            .unwrap_err();
+        assert!(matches!(
+            &err,
+            FlowError::InvalidTransition { node, .. } if node == "finisher"
+        ));
+
+        let lenient = TransitionRules {
+            strict: false,
+            table: None,
+        };
+        assert!(
+            lenient
+                .check("n", &SimpleState::Success, &SimpleState::Processing)
+                .is_ok()
+        );
+    }
+
     #[test]
     fn test_terminal_states() {
         assert!(!SimpleState::Start.is_terminal());
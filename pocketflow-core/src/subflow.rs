@@ -0,0 +1,445 @@
+//! Nodes that run a whole flow as a single step of a parent flow.
+//!
+//! A [`SubFlowNode`] wraps a [`SimpleFlow`] or [`AdvancedFlow`] whose state
+//! type may differ from the parent's. Data moves between the two contexts
+//! through input/output key mappings, and the child's terminal state is
+//! mapped to the parent state to continue with.
+
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+
+use crate::{
+    context::Context,
+    error::{FlowError, Result},
+    flow_advanced::{AdvancedFlow, ChildTrace, NestedStep},
+    flow_simple::SimpleFlow,
+    merge::MergePolicy,
+    node::Node,
+    state::FlowState,
+};
+
+/// Outcome of a child run, already translated to the parent's state type.
+struct ChildOutcome<S> {
+    context: Context,
+    state: S,
+    trace: Vec<NestedStep>,
+}
+
+/// Type-erased child flow runner.
+type RunChild<S> =
+    Arc<dyn Fn(Context) -> BoxFuture<'static, Result<ChildOutcome<S>>> + Send + Sync>;
+
+/// Flow wrapped by a [`SubFlowNode`].
+enum ChildFlow<C: FlowState> {
+    Simple(SimpleFlow<C>),
+    Advanced(Box<AdvancedFlow<C>>),
+}
+
+/// Node that runs a nested flow.
+///
+/// Without input mappings the child starts from a copy of the parent context;
+/// otherwise it starts empty and receives only the mapped keys. Without output
+/// mappings all of the child's data is merged back into the parent (by
+/// default overwriting, see [`SubFlowNodeBuilder::merge_policy`]); otherwise
+/// only the mapped keys are copied (keys the child did not set are skipped).
+///
+/// For [`AdvancedFlow`] children the child trace is nested under the parent's
+/// step in [`ExecutionStep::children`](crate::flow_advanced::ExecutionStep::children).
+pub struct SubFlowNode<S: FlowState> {
+    name: String,
+    inputs: Vec<(String, String)>,
+    outputs: Vec<(String, String)>,
+    merge: MergePolicy,
+    emits: Vec<S>,
+    run: RunChild<S>,
+}
+
+impl<S: FlowState> std::fmt::Debug for SubFlowNode<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubFlowNode")
+            .field("name", &self.name)
+            .field("inputs", &self.inputs)
+            .field("outputs", &self.outputs)
+            .finish()
+    }
+}
+
+impl<S: FlowState> SubFlowNode<S> {
+    /// Start building a node around a [`SimpleFlow`].
+    pub fn simple<C: FlowState>(
+        name: impl Into<String>,
+        flow: SimpleFlow<C>,
+    ) -> SubFlowNodeBuilder<S, C> {
+        SubFlowNodeBuilder::new(name, ChildFlow::Simple(flow))
+    }
+
+    /// Start building a node around an [`AdvancedFlow`].
+    pub fn advanced<C: FlowState>(
+        name: impl Into<String>,
+        flow: AdvancedFlow<C>,
+    ) -> SubFlowNodeBuilder<S, C> {
+        SubFlowNodeBuilder::new(name, ChildFlow::Advanced(Box::new(flow)))
+    }
+
+    fn child_context(&self, parent: &Context) -> Result<Context> {
+        if self.inputs.is_empty() {
+            return Ok(parent.clone());
+        }
+
+        let mut child = Context::new();
+        for (parent_key, child_key) in &self.inputs {
+            let value = parent.get_raw(parent_key).ok_or_else(|| {
+                FlowError::context(format!(
+                    "Sub-flow '{}' input key '{parent_key}' not found",
+                    self.name
+                ))
+            })?;
+            child.set(child_key.clone(), value)?;
+        }
+        Ok(child)
+    }
+
+    fn copy_outputs(&self, child: &Context, parent: &mut Context) -> Result<()> {
+        if self.outputs.is_empty() {
+            parent.merge_with(child, &self.merge).map_err(|error| {
+                FlowError::context(format!(
+                    "Sub-flow '{}' output could not be merged: {error}",
+                    self.name
+                ))
+            })?;
+            return Ok(());
+        }
+
+        for (child_key, parent_key) in &self.outputs {
+            if let Some(value) = child.get_raw(child_key) {
+                parent.set(parent_key.clone(), value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S: FlowState> Node for SubFlowNode<S> {
+    type State = S;
+
+    async fn execute(&self, mut context: Context) -> Result<(Context, Self::State)> {
+        let child_context = self.child_context(&context)?;
+        let outcome = (self.run)(child_context).await?;
+
+        self.copy_outputs(&outcome.context, &mut context)?;
+        if !outcome.trace.is_empty() {
+            context.insert(ChildTrace(outcome.trace))?;
+        }
+        Ok((context, outcome.state))
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn emits(&self) -> Option<Vec<Self::State>> {
+        Some(self.emits.clone())
+    }
+}
+
+/// Builder for [`SubFlowNode`].
+pub struct SubFlowNodeBuilder<S: FlowState, C: FlowState> {
+    name: String,
+    flow: ChildFlow<C>,
+    inputs: Vec<(String, String)>,
+    outputs: Vec<(String, String)>,
+    merge: MergePolicy,
+    states: HashMap<C, S>,
+    on_failure: Option<S>,
+}
+
+impl<S: FlowState, C: FlowState> SubFlowNodeBuilder<S, C> {
+    fn new(name: impl Into<String>, flow: ChildFlow<C>) -> Self {
+        Self {
+            name: name.into(),
+            flow,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            merge: MergePolicy::default(),
+            states: HashMap::new(),
+            on_failure: None,
+        }
+    }
+
+    /// Copy `parent_key` from the parent context to `child_key` in the child.
+    pub fn input(mut self, parent_key: impl Into<String>, child_key: impl Into<String>) -> Self {
+        self.inputs.push((parent_key.into(), child_key.into()));
+        self
+    }
+
+    /// Copy `child_key` from the child context to `parent_key` in the parent.
+    pub fn output(mut self, child_key: impl Into<String>, parent_key: impl Into<String>) -> Self {
+        self.outputs.push((child_key.into(), parent_key.into()));
+        self
+    }
+
+    /// How the child's context is merged back without output mappings.
+    pub fn merge_policy(mut self, policy: MergePolicy) -> Self {
+        self.merge = policy;
+        self
+    }
+
+    /// Continue the parent in `parent_state` when the child ends in `child_state`.
+    pub fn map_state(mut self, child_state: C, parent_state: S) -> Self {
+        self.states.insert(child_state, parent_state);
+        self
+    }
+
+    /// Parent state to continue in when the child run fails.
+    ///
+    /// Without it, a failed child run is returned as an error.
+    pub fn on_failure(mut self, state: S) -> Self {
+        self.on_failure = Some(state);
+        self
+    }
+
+    /// Build the node.
+    pub fn build(self) -> Result<SubFlowNode<S>> {
+        if self.states.is_empty() {
+            return Err(FlowError::construction(format!(
+                "Sub-flow '{}' has no terminal state mappings",
+                self.name
+            )));
+        }
+
+        let mut emits: Vec<S> = self.states.values().cloned().collect();
+        emits.extend(self.on_failure.clone());
+        emits.sort_by_key(|state| format!("{state:?}"));
+        emits.dedup();
+
+        let name = self.name.clone();
+        let states = Arc::new(self.states);
+        let on_failure = self.on_failure;
+        let flow = Arc::new(self.flow);
+
+        let run: RunChild<S> = Arc::new(move |context| {
+            let (name, states, on_failure, flow) = (
+                name.clone(),
+                states.clone(),
+                on_failure.clone(),
+                flow.clone(),
+            );
+            Box::pin(async move {
+                let (context, final_state, success, error, trace) = match flow.as_ref() {
+                    ChildFlow::Simple(flow) => {
+                        let result = flow.execute(context).await?;
+                        (
+                            result.context,
+                            result.final_state,
+                            result.success,
+                            result.error,
+                            Vec::new(),
+                        )
+                    }
+                    ChildFlow::Advanced(flow) => {
+                        let result = flow.execute(context).await?;
+                        let trace = result.trace.iter().map(NestedStep::from).collect();
+                        (
+                            result.context,
+                            result.final_state,
+                            result.success,
+                            result.error,
+                            trace,
+                        )
+                    }
+                };
+
+                // A mapped terminal state wins even when the child reports it
+                // as a failure, like an error state
+                let mapped = states
+                    .get(&final_state)
+                    .filter(|_| success || final_state.is_terminal());
+                let state = if let Some(state) = mapped {
+                    state.clone()
+                } else if success {
+                    return Err(FlowError::context(format!(
+                        "Sub-flow '{name}' ended in unmapped state {final_state:?}"
+                    )));
+                } else {
+                    on_failure.ok_or_else(|| {
+                        FlowError::execution(format!(
+                            "Sub-flow '{name}' failed in state {final_state:?}: {}",
+                            error.unwrap_or_else(|| "unknown error".to_string())
+                        ))
+                    })?
+                };
+
+                Ok(ChildOutcome {
+                    context,
+                    state,
+                    trace,
+                })
+            })
+        });
+
+        Ok(SubFlowNode {
+            name: self.name,
+            inputs: self.inputs,
+            outputs: self.outputs,
+            merge: self.merge,
+            emits,
+            run,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{node::helpers, state::SimpleState};
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Review {
+        Pending,
+        Approved,
+        Rejected,
+    }
+
+    impl FlowState for Review {
+        fn is_terminal(&self) -> bool {
+            matches!(self, Review::Approved | Review::Rejected)
+        }
+    }
+
+    fn review_flow() -> AdvancedFlow<Review> {
+        let reviewer = helpers::fn_node("reviewer", |mut context: Context| async move {
+            let amount: i64 = context.get_json("amount")?.unwrap_or_default();
+            context.set("verdict", if amount < 100 { "ok" } else { "too large" })?;
+            let state = if amount < 100 {
+                Review::Approved
+            } else {
+                Review::Rejected
+            };
+            Ok((context, state))
+        });
+
+        AdvancedFlow::builder()
+            .name("review")
+            .initial_state(Review::Pending)
+            .on_state(Review::Pending, reviewer)
+            .build()
+            .unwrap()
+    }
+
+    fn parent_flow() -> AdvancedFlow<SimpleState> {
+        let review = SubFlowNode::advanced("review", review_flow())
+            .input("order_total", "amount")
+            .output("verdict", "review_verdict")
+            .map_state(Review::Approved, SimpleState::Success)
+            .map_state(Review::Rejected, SimpleState::Error)
+            .build()
+            .unwrap();
+
+        AdvancedFlow::builder()
+            .name("order")
+            .initial_state(SimpleState::Start)
+            .on_state(SimpleState::Start, review)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn maps_keys_and_terminal_states() {
+        let flow = parent_flow();
+
+        let mut context = Context::new();
+        context.set("order_total", 42).unwrap();
+        let result = flow.execute(context).await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.final_state, SimpleState::Success);
+        assert_eq!(
+            result.context.get_json::<String>("review_verdict").unwrap(),
+            Some("ok".to_string())
+        );
+        // Only mapped keys cross the boundary
+        assert!(!result.context.contains_json("amount"));
+
+        let mut context = Context::new();
+        context.set("order_total", 500).unwrap();
+        let result = flow.execute(context).await.unwrap();
+        assert_eq!(result.final_state, SimpleState::Error);
+    }
+
+    #[tokio::test]
+    async fn nests_child_trace_under_parent_step() {
+        let mut context = Context::new();
+        context.set("order_total", 1).unwrap();
+        let result = parent_flow().execute(context).await.unwrap();
+
+        assert_eq!(result.trace.len(), 1);
+        let step = &result.trace[0];
+        assert_eq!(step.node_name, "review");
+        assert_eq!(step.children.len(), 1);
+        assert_eq!(step.children[0].node_name, "reviewer");
+        assert_eq!(step.children[0].from_state, "Pending");
+        assert_eq!(step.children[0].to_state, "Approved");
+    }
+
+    #[tokio::test]
+    async fn failed_child_routes_to_failure_state() {
+        let child = SimpleFlow::builder()
+            .name("child")
+            .initial_state(SimpleState::Start)
+            .node(
+                SimpleState::Start,
+                helpers::fn_node("boom", |_context: Context| async move {
+                    Err::<(Context, SimpleState), _>(FlowError::context("boom"))
+                }),
+            )
+            .build()
+            .unwrap();
+
+        let node = SubFlowNode::simple("child", child)
+            .map_state(SimpleState::Success, SimpleState::Success)
+            .on_failure(SimpleState::Error)
+            .build()
+            .unwrap();
+        let (_, state) = node.execute(Context::new()).await.unwrap();
+        assert_eq!(state, SimpleState::Error);
+
+        let missing_input = SubFlowNode::<SimpleState>::advanced("review", review_flow())
+            .input("order_total", "amount")
+            .map_state(Review::Approved, SimpleState::Success)
+            .build()
+            .unwrap();
+        assert!(missing_input.execute(Context::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn mapped_error_states_are_not_failures() {
+        let reject = || helpers::passthrough("reject", SimpleState::Error);
+        let simple = SimpleFlow::builder()
+            .initial_state(SimpleState::Start)
+            .node(SimpleState::Start, reject())
+            .build()
+            .unwrap();
+        let advanced = AdvancedFlow::builder()
+            .initial_state(SimpleState::Start)
+            .on_state(SimpleState::Start, reject())
+            .build()
+            .unwrap();
+        let rejected = SimpleState::Custom("rejected".to_string());
+
+        let nodes = [
+            SubFlowNode::simple("simple", simple),
+            SubFlowNode::advanced("advanced", advanced),
+        ];
+        for node in nodes {
+            let node = node
+                .map_state(SimpleState::Success, SimpleState::Success)
+                .map_state(SimpleState::Error, rejected.clone())
+                .build()
+                .unwrap();
+            let (_, state) = node.execute(Context::new()).await.unwrap();
+            assert_eq!(state, rejected);
+        }
+    }
+}
@@ -0,0 +1,464 @@
+//! Mermaid and Graphviz DOT export for flow graphs and execution traces.
+//!
+//! States are drawn as boxes labelled with the node handling them, routers
+//! as diamonds and terminal states as rounded boxes. Router edges are
+//! labelled with their branch and fallback edges are dashed.
+//!
+//! Trace renderings overlay an [`ExecutionStep`] trace on the graph: visited
+//! states and taken edges are highlighted, each taken edge is labelled with
+//! its step numbers and durations, and states whose last step failed are
+//! marked as failed.
+
+use std::{collections::HashMap, fmt::Write, time::Duration};
+
+use crate::{
+    flow_advanced::{AdvancedFlow, ExecutionStep, StepKind},
+    flow_simple::SimpleFlow,
+    graph::{EdgeKind, FlowGraph},
+    state::FlowState,
+};
+
+const VISITED_COLOR: &str = "#34a853";
+const FAILED_COLOR: &str = "#ea4335";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    Node,
+    Router,
+    Terminal,
+}
+
+struct DiagramNode {
+    label: Vec<String>,
+    shape: Shape,
+    visited: bool,
+    failed: bool,
+}
+
+struct DiagramEdge {
+    from: usize,
+    to: usize,
+    label: String,
+    dashed: bool,
+    taken: Vec<String>,
+}
+
+impl DiagramEdge {
+    fn label(&self) -> String {
+        match (self.label.is_empty(), self.taken.is_empty()) {
+            (_, true) => self.label.clone(),
+            (true, false) => self.taken.join(", "),
+            (false, false) => format!("{}: {}", self.label, self.taken.join(", ")),
+        }
+    }
+}
+
+/// Renderer-neutral view of a graph, optionally overlaid with a trace.
+struct Diagram {
+    title: String,
+    nodes: Vec<DiagramNode>,
+    edges: Vec<DiagramEdge>,
+}
+
+impl Diagram {
+    fn from_graph<S: FlowState>(graph: &FlowGraph<S>) -> Self {
+        let nodes = graph
+            .states()
+            .iter()
+            .map(|state| {
+                let mut label = vec![format!("{state:?}")];
+                let shape = match graph.handler(state) {
+                    Some("conditional_router") => Shape::Router,
+                    Some(handler) => {
+                        label.push(handler.to_string());
+                        Shape::Node
+                    }
+                    None if state.is_terminal() => Shape::Terminal,
+                    None => Shape::Node,
+                };
+                DiagramNode {
+                    label,
+                    shape,
+                    visited: false,
+                    failed: false,
+                }
+            })
+            .collect();
+
+        let index = Self::index(graph);
+        let edges = graph
+            .edges()
+            .iter()
+            .filter_map(|edge| {
+                Some(DiagramEdge {
+                    from: *index.get(&edge.from)?,
+                    to: *index.get(&edge.to)?,
+                    label: match edge.kind {
+                        EdgeKind::Node => String::new(),
+                        EdgeKind::Router | EdgeKind::Fallback => edge.label.clone(),
+                    },
+                    dashed: edge.kind == EdgeKind::Fallback,
+                    taken: Vec::new(),
+                })
+            })
+            .collect();
+
+        Self {
+            title: graph.name().to_string(),
+            nodes,
+            edges,
+        }
+    }
+
+    fn index<S: FlowState>(graph: &FlowGraph<S>) -> HashMap<&S, usize> {
+        graph
+            .states()
+            .iter()
+            .enumerate()
+            .map(|(i, state)| (state, i))
+            .collect()
+    }
+
+    fn with_trace<S: FlowState>(graph: &FlowGraph<S>, trace: &[ExecutionStep<S>]) -> Self {
+        let mut diagram = Self::from_graph(graph);
+        let mut index: HashMap<S, usize> = Self::index(graph)
+            .into_iter()
+            .map(|(state, i)| (state.clone(), i))
+            .collect();
+        let mut node_for = |diagram: &mut Self, state: &S| {
+            *index.entry(state.clone()).or_insert_with(|| {
+                diagram.nodes.push(DiagramNode {
+                    label: vec![format!("{state:?}")],
+                    shape: if state.is_terminal() {
+                        Shape::Terminal
+                    } else {
+                        Shape::Node
+                    },
+                    visited: false,
+                    failed: false,
+                });
+                diagram.nodes.len() - 1
+            })
+        };
+
+        let mut last_failure: HashMap<usize, Option<&ExecutionStep<S>>> = HashMap::new();
+        for step in trace {
+            let from = node_for(&mut diagram, &step.from_state);
+            let to = node_for(&mut diagram, &step.to_state);
+            diagram.nodes[from].visited = true;
+
+            // A fallback step moves on despite the node's error
+            let taken = step.error.is_none()
+                || (step.kind == StepKind::Fallback && step.from_state != step.to_state);
+            if !taken {
+                last_failure.insert(from, Some(step));
+                continue;
+            }
+            last_failure.insert(from, None);
+            diagram.nodes[to].visited = true;
+
+            let taken = format!("#{} {}", step.step_number, format_duration(step.duration));
+            let existing = diagram.edges.iter().position(|edge| {
+                edge.from == from
+                    && edge.to == to
+                    && (step.kind != StepKind::Fallback || edge.dashed)
+            });
+            match existing {
+                Some(position) => diagram.edges[position].taken.push(taken),
+                None => diagram.edges.push(DiagramEdge {
+                    from,
+                    to,
+                    label: step.node_name.clone(),
+                    dashed: step.kind == StepKind::Fallback,
+                    taken: vec![taken],
+                }),
+            }
+        }
+
+        for (node, step) in last_failure {
+            if let Some(step) = step {
+                diagram.nodes[node].failed = true;
+                diagram.nodes[node].label.push(format!(
+                    "failed at #{} after {}",
+                    step.step_number,
+                    format_duration(step.duration)
+                ));
+            }
+        }
+
+        diagram
+    }
+
+    fn to_mermaid(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "---\ntitle: {}\n---", self.title);
+        out.push_str("flowchart TD\n");
+        out.push_str("    start((\" \"))\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = node
+                .label
+                .iter()
+                .map(|line| mermaid_escape(line))
+                .collect::<Vec<_>>()
+                .join("<br/>");
+            let _ = match node.shape {
+                Shape::Node => writeln!(out, "    s{i}[\"{label}\"]"),
+                Shape::Router => writeln!(out, "    s{i}{{\"{label}\"}}"),
+                Shape::Terminal => writeln!(out, "    s{i}([\"{label}\"])"),
+            };
+        }
+
+        out.push_str("    start --> s0\n");
+        let mut taken_links = Vec::new();
+        for (i, edge) in self.edges.iter().enumerate() {
+            let arrow = if edge.dashed { "-.->" } else { "-->" };
+            let label = edge.label();
+            let _ = if label.is_empty() {
+                writeln!(out, "    s{} {arrow} s{}", edge.from, edge.to)
+            } else {
+                let label = mermaid_escape(&label);
+                writeln!(out, "    s{} {arrow}|\"{label}\"| s{}", edge.from, edge.to)
+            };
+            if !edge.taken.is_empty() {
+                // Link 0 is the start marker
+                taken_links.push((i + 1).to_string());
+            }
+        }
+
+        let visited = self.class_members(|node| node.visited && !node.failed);
+        let failed = self.class_members(|node| node.failed);
+        if !visited.is_empty() || !failed.is_empty() {
+            let _ = writeln!(
+                out,
+                "    classDef visited fill:#e6f4ea,stroke:{VISITED_COLOR},stroke-width:2px"
+            );
+            let _ = writeln!(
+                out,
+                "    classDef failed fill:#fce8e6,stroke:{FAILED_COLOR},stroke-width:2px"
+            );
+        }
+        if !visited.is_empty() {
+            let _ = writeln!(out, "    class {} visited", visited.join(","));
+        }
+        if !failed.is_empty() {
+            let _ = writeln!(out, "    class {} failed", failed.join(","));
+        }
+        if !taken_links.is_empty() {
+            let _ = writeln!(
+                out,
+                "    linkStyle {} stroke:{VISITED_COLOR},stroke-width:3px",
+                taken_links.join(",")
+            );
+        }
+        out
+    }
+
+    fn class_members(&self, filter: impl Fn(&DiagramNode) -> bool) -> Vec<String> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| filter(node))
+            .map(|(i, _)| format!("s{i}"))
+            .collect()
+    }
+
+    fn to_dot(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "digraph \"{}\" {{", dot_escape(&self.title));
+        out.push_str("    rankdir=TB;\n");
+        out.push_str("    node [shape=box, style=rounded, fontname=\"Helvetica\"];\n");
+        out.push_str("    edge [fontname=\"Helvetica\", fontsize=10];\n");
+        out.push_str("    start [shape=point, width=0.2];\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = node
+                .label
+                .iter()
+                .map(|line| dot_escape(line))
+                .collect::<Vec<_>>()
+                .join("\\n");
+            let mut attributes = vec![format!("label=\"{label}\"")];
+            match node.shape {
+                Shape::Node => {}
+                Shape::Router => attributes.push("shape=diamond, style=\"\"".to_string()),
+                Shape::Terminal => attributes.push("peripheries=2".to_string()),
+            }
+            if node.failed {
+                attributes.push(format!("color=\"{FAILED_COLOR}\", penwidth=2"));
+            } else if node.visited {
+                attributes.push(format!("color=\"{VISITED_COLOR}\", penwidth=2"));
+            }
+            let _ = writeln!(out, "    s{i} [{}];", attributes.join(", "));
+        }
+
+        out.push_str("    start -> s0;\n");
+        for edge in &self.edges {
+            let mut attributes = Vec::new();
+            let label = edge.label();
+            if !label.is_empty() {
+                attributes.push(format!("label=\"{}\"", dot_escape(&label)));
+            }
+            if edge.dashed {
+                attributes.push("style=dashed".to_string());
+            }
+            if !edge.taken.is_empty() {
+                attributes.push(format!("color=\"{VISITED_COLOR}\", penwidth=2.5"));
+            }
+            if attributes.is_empty() {
+                let _ = writeln!(out, "    s{} -> s{};", edge.from, edge.to);
+            } else {
+                let _ = writeln!(
+                    out,
+                    "    s{} -> s{} [{}];",
+                    edge.from,
+                    edge.to,
+                    attributes.join(", ")
+                );
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_secs_f64() * 1000.0;
+    if millis >= 1000.0 {
+        format!("{:.2} s", millis / 1000.0)
+    } else {
+        format!("{millis:.1} ms")
+    }
+}
+
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl<S: FlowState> FlowGraph<S> {
+    /// Render the graph as a Mermaid flowchart.
+    pub fn to_mermaid(&self) -> String {
+        Diagram::from_graph(self).to_mermaid()
+    }
+
+    /// Render the graph in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        Diagram::from_graph(self).to_dot()
+    }
+
+    /// Render the graph as a Mermaid flowchart highlighting the path taken
+    /// by `trace`.
+    pub fn trace_to_mermaid(&self, trace: &[ExecutionStep<S>]) -> String {
+        Diagram::with_trace(self, trace).to_mermaid()
+    }
+
+    /// Render the graph in DOT format highlighting the path taken by `trace`.
+    pub fn trace_to_dot(&self, trace: &[ExecutionStep<S>]) -> String {
+        Diagram::with_trace(self, trace).to_dot()
+    }
+}
+
+impl<S: FlowState> AdvancedFlow<S> {
+    /// Render the flow as a Mermaid flowchart.
+    pub fn to_mermaid(&self) -> String {
+        self.graph().to_mermaid()
+    }
+
+    /// Render the flow in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        self.graph().to_dot()
+    }
+
+    /// Render the flow as a Mermaid flowchart highlighting the path taken by
+    /// a run, with per-step durations.
+    pub fn trace_to_mermaid(&self, trace: &[ExecutionStep<S>]) -> String {
+        self.graph().trace_to_mermaid(trace)
+    }
+
+    /// Render the flow in DOT format highlighting the path taken by a run,
+    /// with per-step durations.
+    pub fn trace_to_dot(&self, trace: &[ExecutionStep<S>]) -> String {
+        self.graph().trace_to_dot(trace)
+    }
+}
+
+impl<S: FlowState> SimpleFlow<S> {
+    /// Render the flow as a Mermaid flowchart.
+    pub fn to_mermaid(&self) -> String {
+        self.graph().to_mermaid()
+    }
+
+    /// Render the flow in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        self.graph().to_dot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::Context, node::helpers, policy::NodePolicy, state::SimpleState};
+
+    fn review_flow() -> AdvancedFlow<SimpleState> {
+        AdvancedFlow::builder()
+            .name("review")
+            .initial_state(SimpleState::Start)
+            .on_state_with_policy(
+                SimpleState::Start,
+                helpers::passthrough("fetch", SimpleState::Processing),
+                NodePolicy::new().fallback_state(SimpleState::Error),
+            )
+            .when_state(
+                SimpleState::Processing,
+                |context, _| context.get_json::<bool>("approved").ok().flatten() == Some(true),
+                SimpleState::Success,
+                SimpleState::Error,
+            )
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn renders_flow_structure() {
+        let flow = review_flow();
+
+        let mermaid = flow.to_mermaid();
+        assert!(mermaid.contains("title: review"));
+        assert!(mermaid.contains("s0[\"Start<br/>fetch\"]"));
+        assert!(mermaid.contains("s1{\"Processing\"}"));
+        assert!(mermaid.contains("s3([\"Success\"])"));
+        assert!(mermaid.contains("s0 --> s1"));
+        assert!(mermaid.contains("s0 -.->|\"fallback\"| s2"));
+        assert!(mermaid.contains("s1 -->|\"true\"| s3"));
+        assert!(mermaid.contains("s1 -->|\"false\"| s2"));
+        assert!(!mermaid.contains("linkStyle"));
+
+        let dot = flow.to_dot();
+        assert!(dot.starts_with("digraph \"review\" {"));
+        assert!(dot.contains("s1 [label=\"Processing\", shape=diamond, style=\"\"];"));
+        assert!(dot.contains("s0 -> s2 [label=\"fallback\", style=dashed];"));
+        assert!(dot.contains("s1 -> s3 [label=\"true\"];"));
+    }
+
+    #[tokio::test]
+    async fn trace_highlights_path_taken() {
+        let flow = review_flow();
+        let mut context = Context::new();
+        context.set("approved", true).unwrap();
+        let result = flow.execute(context).await.unwrap();
+
+        let mermaid = flow.trace_to_mermaid(&result.trace);
+        assert!(mermaid.contains("class s0,s1,s3 visited"));
+        // Links: start, s0->s1, s0->s2, s1->s3, s1->s2
+        assert!(mermaid.contains("linkStyle 1,3 stroke"));
+        assert!(mermaid.contains("s1 -->|\"true: #2 "));
+
+        let dot = flow.trace_to_dot(&result.trace);
+        assert!(dot.contains("s0 -> s1 [label=\"#1 "));
+        assert!(dot.contains("s2 [label=\"Error\", peripheries=2];"));
+    }
+}
@@ -34,6 +34,7 @@ impl FlowState for McpWorkflowState {
         match (self, target) {
             (McpWorkflowState::Start, McpWorkflowState::ReadingFile) => true,
             (McpWorkflowState::ReadingFile, McpWorkflowState::ProcessingContent) => true,
+            (McpWorkflowState::ReadingFile, McpWorkflowState::AnalyzingData) => true,
             (McpWorkflowState::ReadingFile, McpWorkflowState::Error) => true,
             (McpWorkflowState::ProcessingContent, McpWorkflowState::AnalyzingData) => true,
             (McpWorkflowState::ProcessingContent, McpWorkflowState::Error) => true,
@@ -7,12 +7,13 @@ use pocketflow_core::{
     context::Context, error::Result as FlowResult, node::Node, state::FlowState,
 };
 use serde_json::Value;
+use tracing::{Instrument, field};
 use ultrafast_mcp::{UltraFastClient, types::ResourceContent};
 
 use super::{
     ClientCapabilities, ClientInfo, ListResourcesRequest, ListToolsRequest, ReadResourceRequest,
     Resource, Result, ServerInfo, Tool, ToolCall, ToolContent,
-    error::McpError as PocketFlowMcpError,
+    error::McpError as PocketFlowMcpError, keys,
 };
 
 /// Configuration for MCP transport connections.
@@ -289,12 +290,26 @@ impl<S: FlowState> Node for McpClientNode<S> {
             tool_args.insert(self.context_arg_name.clone(), ctx_json);
         }
 
-        // Call the tool with retries
+        // Call the tool with retries, each attempt in its own span under the
+        // flow's node span
         let mut attempt = 0usize;
         let result = loop {
+            let span = tracing::info_span!(
+                "mcp.call_tool",
+                node = %self.name,
+                tool = %self.tool_name,
+                attempt = attempt + 1,
+                error = field::Empty,
+                otel.status_code = field::Empty,
+            );
             let call = client
                 .call_tool(&self.tool_name, Value::Object(tool_args.clone()))
+                .instrument(span.clone())
                 .await;
+            if let Err(error) = &call {
+                span.record("error", field::display(error));
+                span.record("otel.status_code", "ERROR");
+            }
             match call {
                 Ok(v) => break Ok(v),
                 Err(e) => {
@@ -314,8 +329,9 @@ impl<S: FlowState> Node for McpClientNode<S> {
         match result {
             Ok(tool_result) => {
                 // Store result in context
-                if let Some(output_key) = &self.output_key {
-                    context.set(output_key, &tool_result)?;
+                match &self.output_key {
+                    Some(output_key) => context.set(output_key, &tool_result)?,
+                    None => context.put(&keys::MCP_TOOL_RESULT, &tool_result)?,
                 }
 
                 // Transition to configured success state
@@ -329,7 +345,7 @@ impl<S: FlowState> Node for McpClientNode<S> {
             }
             Err(e) => {
                 // Store error in context
-                context.set("mcp_error", e.to_string())?;
+                context.put(&keys::MCP_ERROR, e.to_string())?;
 
                 // Transition to configured error state
                 let next_state = self.on_error.clone().ok_or_else(|| {
@@ -424,8 +440,8 @@ impl<S: FlowState> McpClientNodeBuilder<S> {
         self
     }
 
-    /// Store the MCP tool call result under this key in the workflow Context.
-    /// Set the context key to store the tool result.
+    /// Store the MCP tool call result under this key in the workflow Context
+    /// (defaults to [`keys::MCP_TOOL_RESULT`]).
     pub fn output_to(mut self, key: impl Into<String>) -> Self {
         self.output_key = Some(key.into());
         self
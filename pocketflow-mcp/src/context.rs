@@ -4,6 +4,7 @@ use std::{collections::HashMap, sync::Arc};
 
 use pocketflow_core::context::Context;
 use serde_json::Value;
+use tracing::{Instrument, field};
 
 use super::{Result, client::McpClient, error::McpError};
 
@@ -107,7 +108,22 @@ impl McpContextExt for Context {
                 client_name: client_name.to_string(),
             })?;
 
-        client.call_tool(tool_name, arguments).await
+        let span = tracing::info_span!(
+            "mcp.call_tool",
+            client = client_name,
+            tool = tool_name,
+            error = field::Empty,
+            otel.status_code = field::Empty,
+        );
+        let result = client
+            .call_tool(tool_name, arguments)
+            .instrument(span.clone())
+            .await;
+        if let Err(error) = &result {
+            span.record("error", field::display(error));
+            span.record("otel.status_code", "ERROR");
+        }
+        result
     }
 
     fn set_mcp_result(
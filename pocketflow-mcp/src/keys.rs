@@ -0,0 +1,11 @@
+//! Typed context keys written by [`McpClientNode`](crate::client::McpClientNode).
+
+use pocketflow_core::keys::ContextKey;
+use serde_json::Value;
+
+/// The tool result, unless the node was configured with
+/// [`output_to`](crate::client::McpClientNodeBuilder::output_to).
+pub const MCP_TOOL_RESULT: ContextKey<Value> = ContextKey::new("mcp_tool_result");
+
+/// The error message of a failed tool call.
+pub const MCP_ERROR: ContextKey<String> = ContextKey::new("mcp_error");
@@ -68,6 +68,7 @@
 pub mod client;
 pub mod context;
 pub mod error;
+pub mod keys;
 pub mod registry;
 pub mod server;
 
@@ -6,6 +6,7 @@ use pocketflow_core::{context::Context, node::Node};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::sync::RwLock;
+use tracing::{Instrument, field};
 
 use super::{Resource, Result, Tool, error::McpError};
 
@@ -153,12 +154,24 @@ impl McpToolNode {
                 message: format!("Failed to set input in context: {e}"),
             })?;
 
-        let (updated_context, _state) =
-            node.execute(context)
-                .await
-                .map_err(|e| McpError::ToolExecutionFailed {
+        let span = tracing::info_span!(
+            "mcp.tool",
+            tool = %self.name,
+            node = %node.name(),
+            error = field::Empty,
+            otel.status_code = field::Empty,
+        );
+        let (updated_context, _state) = node
+            .execute(context)
+            .instrument(span.clone())
+            .await
+            .map_err(|e| {
+                span.record("error", field::display(&e));
+                span.record("otel.status_code", "ERROR");
+                McpError::ToolExecutionFailed {
                     message: format!("Node execution failed: {e}"),
-                })?;
+                }
+            })?;
 
         // Try to extract result from context
         Ok(updated_context
@@ -2,6 +2,7 @@ use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use serde_json::{Value, json};
 use tokio::sync::RwLock;
+use tracing::{Instrument, field};
 
 use crate::{
     core::{Tool, ToolCapability, ToolCategory, ToolContext, ToolParameters, ToolResult},
@@ -82,11 +83,48 @@ impl ToolRegistry {
     }
 
     /// Execute a tool by name
+    ///
+    /// The execution runs in a `tool` span, nested under the caller's span
+    /// (for example the flow node calling the tool).
     pub async fn execute_tool(
         &self,
         tool_name: &str,
         parameters: &Value,
         context: &ToolContext,
+    ) -> Result<ToolResult> {
+        let span = tracing::info_span!(
+            "tool",
+            tool = tool_name,
+            cached = field::Empty,
+            error = field::Empty,
+            otel.status_code = field::Empty,
+        );
+        let result = self
+            .run_tool(tool_name, parameters, context)
+            .instrument(span.clone())
+            .await;
+
+        match &result {
+            Ok(result) => {
+                span.record("cached", result.cached);
+                if let Some(error) = &result.error {
+                    span.record("error", error.as_str());
+                    span.record("otel.status_code", "ERROR");
+                }
+            }
+            Err(error) => {
+                span.record("error", field::display(error));
+                span.record("otel.status_code", "ERROR");
+            }
+        }
+        result
+    }
+
+    async fn run_tool(
+        &self,
+        tool_name: &str,
+        parameters: &Value,
+        context: &ToolContext,
     ) -> Result<ToolResult> {
         // Get tool
         let tools = self.tools.read().await;
@@ -131,11 +169,14 @@ impl ToolRegistry {
             .into_iter()
             .map(|req| {
                 let registry = self.clone();
-                tokio::spawn(async move {
-                    registry
-                        .execute_tool(&req.tool_name, &req.parameters, &req.context)
-                        .await
-                })
+                tokio::spawn(
+                    async move {
+                        registry
+                            .execute_tool(&req.tool_name, &req.parameters, &req.context)
+                            .await
+                    }
+                    .in_current_span(),
+                )
             })
             .collect();
 